@@ -1,5 +1,6 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    /* the last 64K are reserved for ylab::ymem */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 64K
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
\ No newline at end of file
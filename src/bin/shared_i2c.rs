@@ -65,7 +65,7 @@ use ylab::ysns::yxz_lsm6;
 /// + YUI input (LED) and output (button)
 use ylab::yuio::led as yled;
 use ylab::yuio::disp as ydsp;
-use ylab::yuii::btn as ybtn;
+use ylab::yuii::keys as ykeys;
 /// + four built-in ADC sensors
 use ylab::ysns::adc as yadc;
 /// + four ADCs on a ADS1115;
@@ -213,7 +213,7 @@ fn init() -> ! {
             = i2c::I2c::new_async(i2c_contr, p.PIN_3, p.PIN_2, Irqs, Config::default());
         unwrap!(spawner.spawn(ydsp::task(i2c)));
         // task for listening to button presses.
-        unwrap!(spawner.spawn(ybtn::task(p.PIN_20.degrade())));
+        unwrap!(spawner.spawn(ykeys::task(Vec::from_iter([p.PIN_20.degrade()]), ykeys::Config::single())));
         // task listening for data packeges to send up the line (reverse USB ;)
         unwrap!(spawner.spawn(ybsu::task(p.USB)));
         // task to control sensors, storage and ui
@@ -258,15 +258,15 @@ async fn control_task() {
     
     yled::LED.signal(yled::State::Steady);
     loop {
-        let event = ybtn::BTN.wait().await;
+        let event = ykeys::EVENTS.receive().await.gesture;
         // Only when a new user event appears,
         // a state transition may occur.
         if let Some(next_state) = 
             match (state, event) {
-                (AppState::New,     ybtn::Event::Short) => Some(AppState::Ready),
-                (AppState::Ready,   ybtn::Event::Short) => Some(AppState::Record), 
-                (AppState::Record,  ybtn::Event::Short) => Some(AppState::Ready),
-                (_,                 ybtn::Event::Long)  => Some(AppState::New),
+                (AppState::New,     ykeys::Gesture::Click) => Some(AppState::Ready),
+                (AppState::Ready,   ykeys::Gesture::Click) => Some(AppState::Record), 
+                (AppState::Record,  ykeys::Gesture::Click) => Some(AppState::Ready),
+                (_,                 ykeys::Gesture::Long)  => Some(AppState::New),
                 (_, _) => None,}
                 {
                     // When a new event has been announced we do the transition.
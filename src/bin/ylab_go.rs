@@ -0,0 +1,18 @@
+#![no_std]
+#![no_main]
+
+//! YLab Go, the whole firmware as a device description
+//!
+//! + Moi on GP21/22, ADC on GP26-28 (core 0)
+//! + LSM6 motion sensor on Grove 1 (core 1)
+//! + LED, button, serial transport with host commands
+//!
+//! Rates are defaults, which can be changed by `cfg` commands
+//! and saved to flash.
+ylab::firmware! {
+    sink: serial,
+    ui: [led, button],
+    control: ylab::ydev::button_control(record),
+    core0: [moi() as 0, adc() at 53 Hz as 1],
+    core1: [lsm6(1) at 149 Hz as 2],
+}
@@ -0,0 +1,19 @@
+#![no_std]
+#![no_main]
+
+//! YLab Session, sensors and session set up on the device
+//!
+//! + ADC on GP26-28 (core 0)
+//! + LSM6 motion sensor on Grove 1 (core 1)
+//! + LED, three keys and the menu on a display on Grove 2
+//! + serial transport with host commands
+//!
+//! Sensors, rates, participant and condition are chosen in the
+//! menu (`yuii::menu`). Recording starts with the session.
+ylab::firmware! {
+    sink: serial,
+    ui: [led, keys, display(2), menu],
+    control: ylab::ydev::menu_control(record),
+    core0: [adc() at 100 Hz as 0],
+    core1: [lsm6(1) at 149 Hz as 1],
+}
@@ -0,0 +1,25 @@
+//! YLab Sim, a firmware on the host
+//!
+//! + sine (3 channels), noise (2) and steps (1) as sensories 0-2
+//! + values from a file as sensory 3, if a path is given
+//! + a recording played back with `--play <file>`, under its own
+//!   sensories and at its own pace, or faster with `--speed <x>`
+//! + buzzer, stimulus pins and response box as sensories 4-6
+//! + LED, display and button on the terminal (see `ysim::ui`)
+//! + samples on stdout, host commands on stdin (see `ysim::host`)
+//!
+//! ```console
+//! $ cargo run --bin ylab_sim --no-default-features --features sim \
+//!     --target x86_64-unknown-linux-gnu -- values.csv --play recording.csv
+//! ```
+//!
+//! Type `btn short` to pause and resume recording, `cfg show`
+//! for the configuration. Recordings can also be streamed in on
+//! stdin as `play ...` lines.
+ylab::firmware! {
+    sink: host,
+    ui: [led, display, button, buzzer(4), stim(5), rbx(6), play],
+    control: ylab::ydev::button_control(record),
+    core0: [sine() at 100 Hz as 0, noise() at 250 Hz as 1, step() at 10 Hz as 2, values() at 50 Hz as 3],
+    core1: [],
+}
@@ -0,0 +1,244 @@
+//! # Board support
+//!
+//! Which GPIO does what on the boards YLab runs on. A `Board` names
+//! its Grove ports with the functions their two pins can take, and the
+//! pins of the on-board LED, buttons, buzzer, RGB pixels and ADC.
+//!
+//! `Claims` keeps track of who uses which pin, so that a device
+//! description can be checked before anything is set up, e.g.
+//!
+//! ```ignore
+//! let mut claims = Claims::new(&MAKER_PI_PICO);
+//! claims.port("co2", 5, Function::I2c0)?;   // GP8/GP9
+//! claims.pins("moi", &[21, 22, 8, 9])?;     // Err(Taken { pin: 8, by: "co2" })
+//! ```
+//!
+//! The tables are plain data and `Claims` is const, so the check also
+//! runs at compile time (`ydev::check`) and on the host.
+
+/// What a port can be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    I2c0,
+    I2c1,
+    Uart0,
+    Uart1,
+    Adc,
+    Digital,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Port {
+    /// number printed on the board
+    pub number: u8,
+    /// SDA/TX/first ADC pin first
+    pub pins: [u8; 2],
+    pub functions: &'static [Function],
+}
+
+impl Port {
+    pub const fn supports(&self, function: Function) -> bool {
+        let mut i = 0;
+        while i < self.functions.len() {
+            if self.functions[i] as u8 == function as u8 {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Board {
+    pub name: &'static str,
+    pub ports: &'static [Port],
+    /// LED on a GPIO, the Pico W has its LED on the radio chip
+    pub led: Option<u8>,
+    pub buttons: &'static [u8],
+    pub buzzer: Option<u8>,
+    pub rgb: Option<u8>,
+    pub adc: &'static [u8],
+    /// pins taken by the board itself
+    pub reserved: &'static [u8],
+}
+
+impl Board {
+    pub const fn port(&self, number: u8) -> Option<&Port> {
+        let mut i = 0;
+        while i < self.ports.len() {
+            if self.ports[i].number == number {
+                return Some(&self.ports[i]);
+            }
+            i += 1;
+        }
+        None
+    }
+}
+
+use Function::*;
+
+/// Cytron Maker Pi Pico, six Grove ports
+pub const MAKER_PI_PICO: Board = Board {
+    name: "Maker Pi Pico",
+    ports: &[
+        Port { number: 1, pins: [0, 1], functions: &[I2c0, Uart0, Digital] },
+        Port { number: 2, pins: [2, 3], functions: &[I2c1, Digital] },
+        Port { number: 3, pins: [4, 5], functions: &[I2c0, Uart1, Digital] },
+        Port { number: 4, pins: [6, 7], functions: &[I2c1, Digital] },
+        Port { number: 5, pins: [8, 9], functions: &[I2c0, Uart1, Digital] },
+        Port { number: 6, pins: [26, 27], functions: &[I2c1, Adc, Digital] },
+    ],
+    led: Some(25),
+    buttons: &[20, 21, 22],
+    buzzer: Some(18),
+    rgb: Some(28),
+    adc: &[26, 27, 28],
+    reserved: &[23, 24, 29],
+};
+
+/// Raspberry Pi Pico, no Grove ports
+pub const PICO: Board = Board {
+    name: "Pico",
+    ports: &[],
+    led: Some(25),
+    buttons: &[],
+    buzzer: None,
+    rgb: None,
+    adc: &[26, 27, 28],
+    reserved: &[23, 24, 29],
+};
+
+/// Raspberry Pi Pico W, the radio takes GP23-25 and GP29
+pub const PICO_W: Board = Board {
+    name: "Pico W",
+    ports: &[],
+    led: None,
+    buttons: &[],
+    buzzer: None,
+    rgb: None,
+    adc: &[26, 27, 28],
+    reserved: &[23, 24, 25, 29],
+};
+
+pub const GPIOS: usize = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    NoPort(u8),
+    Unsupported(u8, Function),
+    NoPin(u8),
+    Taken { pin: u8, by: &'static str },
+}
+
+pub struct Claims<'b> {
+    board: &'b Board,
+    owners: [Option<&'static str>; GPIOS],
+}
+
+impl<'b> Claims<'b> {
+    pub const fn new(board: &'b Board) -> Self {
+        let mut owners = [None; GPIOS];
+        let mut i = 0;
+        while i < board.reserved.len() {
+            owners[board.reserved[i] as usize] = Some(board.name);
+            i += 1;
+        }
+        Claims { board, owners }
+    }
+
+    /// Claims all `pins` for `owner`, or none of them
+    pub const fn pins(&mut self, owner: &'static str, pins: &[u8]) -> Result<(), Conflict> {
+        let mut i = 0;
+        while i < pins.len() {
+            let pin = pins[i];
+            if pin as usize >= GPIOS {
+                return Err(Conflict::NoPin(pin));
+            }
+            if let Some(by) = self.owners[pin as usize] {
+                return Err(Conflict::Taken { pin, by });
+            }
+            i += 1;
+        }
+        let mut i = 0;
+        while i < pins.len() {
+            self.owners[pins[i] as usize] = Some(owner);
+            i += 1;
+        }
+        Ok(())
+    }
+
+    /// Claims both pins of a port used as `function`
+    pub const fn port(&mut self, owner: &'static str, number: u8, function: Function) -> Result<[u8; 2], Conflict> {
+        let Some(&port) = self.board.port(number) else {
+            return Err(Conflict::NoPort(number));
+        };
+        if !port.supports(function) {
+            return Err(Conflict::Unsupported(number, function));
+        }
+        match self.pins(owner, &port.pins) {
+            Ok(()) => Ok(port.pins),
+            Err(conflict) => Err(conflict),
+        }
+    }
+
+    pub const fn owner(&self, pin: u8) -> Option<&'static str> {
+        if (pin as usize) < GPIOS {
+            self.owners[pin as usize]
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_pins() {
+        let claims = Claims::new(&PICO_W);
+        assert_eq!(claims.owner(25), Some("Pico W"));
+        assert_eq!(claims.owner(0), None);
+        assert_eq!(claims.owner(GPIOS as u8), None);
+    }
+
+    #[test]
+    fn ports() {
+        let mut claims = Claims::new(&MAKER_PI_PICO);
+        assert_eq!(claims.port("co2", 5, I2c0), Ok([8, 9]));
+        assert_eq!(claims.owner(9), Some("co2"));
+        assert_eq!(claims.port("bmi160", 4, I2c0), Err(Conflict::Unsupported(4, I2c0)));
+        assert_eq!(claims.port("tlv", 7, I2c0), Err(Conflict::NoPort(7)));
+        assert_eq!(Claims::new(&PICO).port("tlv", 1, I2c0), Err(Conflict::NoPort(1)));
+    }
+
+    #[test]
+    fn taken() {
+        let mut claims = Claims::new(&MAKER_PI_PICO);
+        assert_eq!(claims.port("co2", 5, I2c0), Ok([8, 9]));
+        assert_eq!(claims.port("tlv", 5, I2c0), Err(Conflict::Taken { pin: 8, by: "co2" }));
+        assert_eq!(claims.pins("moi", &[21, 22, 8, 9]), Err(Conflict::Taken { pin: 8, by: "co2" }));
+        assert_eq!(claims.pins("rgb", &[28, 29]), Err(Conflict::Taken { pin: 29, by: "Maker Pi Pico" }));
+    }
+
+    #[test]
+    fn all_or_nothing() {
+        let mut claims = Claims::new(&MAKER_PI_PICO);
+        assert_eq!(claims.pins("moi", &[21, 22, 30]), Err(Conflict::NoPin(30)));
+        assert_eq!(claims.owner(21), None);
+        assert_eq!(claims.pins("adc", &[26, 27, 28]), Ok(()));
+        assert_eq!(claims.port("graph", 6, I2c1), Err(Conflict::Taken { pin: 26, by: "adc" }));
+        assert_eq!(claims.owner(26), Some("adc"));
+    }
+
+    #[test]
+    fn in_const() {
+        const OWNER: Option<&str> = {
+            let mut claims = Claims::new(&MAKER_PI_PICO);
+            assert!(claims.port("lsm6", 1, I2c0).is_ok());
+            claims.owner(0)
+        };
+        assert_eq!(OWNER, Some("lsm6"));
+    }
+}
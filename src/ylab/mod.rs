@@ -1,5 +1,11 @@
-#![no_std]
+#![cfg_attr(not(feature = "sim"), no_std)]
 
+#[cfg(all(feature = "rp", feature = "sim"))]
+compile_error!("`rp` and `sim` exclude each other, build `sim` with `--no-default-features`");
+#[cfg(not(any(feature = "rp", feature = "sim")))]
+compile_error!("either `rp` (the device) or `sim` (the host) is needed");
+
+#[cfg(feature = "rp")]
 pub use embassy_rp as hal;
 pub use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex as RawMutex;
 pub use embassy_sync::channel::Channel;
@@ -13,6 +19,14 @@ pub use core::sync::atomic::AtomicBool;
 pub use core::sync::atomic::Ordering;
 pub static ORD: Ordering = Ordering::SeqCst;
 
+pub mod board; // Board support (pins and Grove ports)
+pub mod ycfg; // YLab device configuration
+pub mod ydev; // YLab device descriptions (`firmware!`)
+pub mod ymem; // YLab persistent storage
+pub mod ypro; // YLab on-device processing
+#[cfg(feature = "sim")]
+pub mod ysim; // YLab host simulation
+#[cfg(any(feature = "rp", test))]
 pub mod ysns; // Ylab sensors
 pub mod ytfk;
 pub mod yuii; // YLab UI Input
@@ -32,6 +46,7 @@ pub const YTF_LEN: usize = 8;
 pub type YtfType = f64;
 pub type YtfRead = [Option<YtfType>; YTF_LEN];
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Ytf {
     pub sensory: u8,
     pub time: Instant,
@@ -68,3 +83,71 @@ impl core::fmt::Display for Ytf {
         write!(f, "")
     }
 }
+
+/// A line that is not a `Ytf` as written by `Display`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct YtfError;
+
+impl core::str::FromStr for Ytf {
+    type Err = YtfError;
+
+    /// Reads a line as written by `Display`, spaces around values
+    /// are skipped, missing values at the end are empty.
+    fn from_str(line: &str) -> Result<Ytf, YtfError> {
+        let mut fields = line.trim().split(',');
+        let mut next = || fields.next().map(str::trim).ok_or(YtfError);
+        let time = next()?.parse().map_err(|_| YtfError)?;
+        let sensory = next()?.parse().map_err(|_| YtfError)?;
+        let mut read: YtfRead = [None; YTF_LEN];
+        for value in read.iter_mut() {
+            *value = match next() {
+                Ok("") | Err(_) => None,
+                Ok(field) => Some(field.parse().map_err(|_| YtfError)?),
+            };
+        }
+        Ok(Ytf {
+            sensory,
+            time: Instant::from_micros(time),
+            read,
+        })
+    }
+}
+
+impl Ytf {
+    /// time in µs (u64), sensory, a bit per value present,
+    /// then the values as f32, all little endian
+    pub const BIN_LEN: usize = 8 + 1 + 1 + 4 * YTF_LEN;
+
+    pub fn to_bytes(&self) -> [u8; Ytf::BIN_LEN] {
+        let mut bytes = [0u8; Ytf::BIN_LEN];
+        bytes[..8].copy_from_slice(&self.time.as_micros().to_le_bytes());
+        bytes[8] = self.sensory;
+        for (i, value) in self.read.iter().enumerate() {
+            if let Some(v) = value {
+                bytes[9] |= 1 << i;
+                bytes[10 + 4 * i..14 + 4 * i].copy_from_slice(&(*v as f32).to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// The record at the start of `bytes`, `None` if they are too short
+    pub fn from_bytes(bytes: &[u8]) -> Option<Ytf> {
+        let bytes = bytes.get(..Ytf::BIN_LEN)?;
+        let mut time = [0u8; 8];
+        time.copy_from_slice(&bytes[..8]);
+        let mut read: YtfRead = [None; YTF_LEN];
+        for (i, value) in read.iter_mut().enumerate() {
+            if bytes[9] & (1 << i) != 0 {
+                let mut v = [0u8; 4];
+                v.copy_from_slice(&bytes[10 + 4 * i..14 + 4 * i]);
+                *value = Some(f32::from_le_bytes(v) as YtfType);
+            }
+        }
+        Some(Ytf {
+            sensory: bytes[8],
+            time: Instant::from_micros(u64::from_le_bytes(time)),
+            read,
+        })
+    }
+}
@@ -0,0 +1,408 @@
+pub use crate::*;
+
+/// # Device configuration
+///
+/// What used to be constants at the top of a binary (`DEV`, `HZ`,
+/// `SPEED`, `N_PROBES`, `LOG_LEVEL`) as one versioned record in
+/// `ymem::Slot::Config`, where `ymem` adds the CRC. A binary calls
+/// `boot` with its own defaults before starting the executors and
+/// spawns its tasks from what it gets back. An empty or damaged slot
+/// gives the defaults.
+///
+/// At run time, `CONFIG` can be changed from the host (`cfg ...`,
+/// see `Command`) or the menu. Changes are saved with `cfg save`
+/// and take effect at the next reset.
+///
+/// `Config::to_bytes` and `Config::from_bytes` are plain functions
+/// on byte slices, so the format and its migrations run on the host.
+///
+/// A configuration the tasks cannot start with (`Config::invalid`)
+/// is neither set nor read back, as the slot outlives a reflash
+/// and the board would not get past the spawns.
+use core::fmt::Write;
+use core::str::SplitWhitespace;
+use ymem::{Flash, Slot};
+
+pub const VERSION: u8 = 2;
+pub const MAX_DEVICES: usize = 8;
+/// fast mode plus, the most the RP2040 I2C controllers do
+pub const MAX_SPEED: u32 = 1_000_000;
+/// version, devices, rates, speed, log level, probes, participant, condition
+pub const LEN: usize = 1 + 1 + 2 * MAX_DEVICES + 4 + 1 + 1 + 2 + 1;
+
+/// Version 1 was the menu settings without a version byte:
+/// devices, rate indices, participant, condition. It is told by
+/// its length, which no versioned record has; every later version
+/// starts with its number.
+const V1_LEN: usize = 1 + MAX_DEVICES + 2 + 1;
+const V1_RATES: [u16; 7] = [10, 50, 100, 200, 500, 1000, 2000];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// bit mask of the devices to run, numbered by the binary
+    pub devices: u8,
+    /// sample rate per device in Hz
+    pub rates: [u16; MAX_DEVICES],
+    /// bit mask of the devices that take a rate of 0 as their own
+    /// (CO2 per measurement, moi per edge), set by the binary and
+    /// not stored
+    pub native: u8,
+    /// I2C bus speed in Hz
+    pub speed: u32,
+    /// 0 = off ... 5 = trace
+    pub log_level: u8,
+    /// sensors behind a hub
+    pub probes: u8,
+    pub participant: u16,
+    pub condition: u8,
+}
+
+impl Config {
+    pub const DEFAULT: Config = Config {
+        devices: 0xFF,
+        rates: [100; MAX_DEVICES],
+        native: 0,
+        speed: 100_000,
+        log_level: 3,
+        probes: 1,
+        participant: 0,
+        condition: 0,
+    };
+
+    pub fn enabled(&self, device: usize) -> bool {
+        device < MAX_DEVICES && self.devices & (1 << device) != 0
+    }
+
+    pub fn hz(&self, device: usize) -> u64 {
+        self.rates.get(device).map_or(0, |&hz| hz as u64)
+    }
+
+    /// Why the tasks cannot start with this configuration, if they
+    /// cannot: a rate of 0 for a device with a ticker, or an I2C
+    /// speed the controllers do not take
+    pub fn invalid(&self) -> Option<&'static str> {
+        if !(1..=MAX_SPEED).contains(&self.speed) {
+            return Some("speed is 1 to 1000000 Hz");
+        }
+        let ticked = (0..MAX_DEVICES).any(|d| self.rates[d] == 0 && self.native & (1 << d) == 0);
+        if ticked {
+            return Some("rate 0 is for co2 and moi only");
+        }
+        None
+    }
+
+    pub fn level(&self) -> log::LevelFilter {
+        match self.log_level {
+            0 => log::LevelFilter::Off,
+            1 => log::LevelFilter::Error,
+            2 => log::LevelFilter::Warn,
+            3 => log::LevelFilter::Info,
+            4 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; LEN] {
+        let mut bytes = [0u8; LEN];
+        bytes[0] = VERSION;
+        bytes[1] = self.devices;
+        for (d, hz) in self.rates.iter().enumerate() {
+            bytes[2 + 2 * d..4 + 2 * d].copy_from_slice(&hz.to_le_bytes());
+        }
+        let rest = 2 + 2 * MAX_DEVICES;
+        bytes[rest..rest + 4].copy_from_slice(&self.speed.to_le_bytes());
+        bytes[rest + 4] = self.log_level;
+        bytes[rest + 5] = self.probes;
+        bytes[rest + 6..rest + 8].copy_from_slice(&self.participant.to_le_bytes());
+        bytes[rest + 8] = self.condition;
+        bytes
+    }
+
+    /// The version of a record, 1 for the one without a version byte
+    pub fn version(bytes: &[u8]) -> Option<u8> {
+        match bytes.len() {
+            0 => None,
+            V1_LEN => Some(1),
+            _ => Some(bytes[0]),
+        }
+    }
+
+    /// Reads the current or an older version, filling
+    /// what older versions did not have from `default`.
+    /// Invalid records give `None`.
+    pub fn from_bytes(bytes: &[u8], default: &Config) -> Option<Config> {
+        let config = match Config::version(bytes)? {
+            VERSION if bytes.len() == LEN => {
+                let mut config = *default;
+                config.devices = bytes[1];
+                for (d, hz) in config.rates.iter_mut().enumerate() {
+                    *hz = u16::from_le_bytes([bytes[2 + 2 * d], bytes[3 + 2 * d]]);
+                }
+                let rest = 2 + 2 * MAX_DEVICES;
+                config.speed = u32::from_le_bytes([bytes[rest], bytes[rest + 1], bytes[rest + 2], bytes[rest + 3]]);
+                config.log_level = bytes[rest + 4];
+                config.probes = bytes[rest + 5];
+                config.participant = u16::from_le_bytes([bytes[rest + 6], bytes[rest + 7]]);
+                config.condition = bytes[rest + 8];
+                config
+            }
+            1 if bytes.len() == V1_LEN => {
+                let mut config = *default;
+                config.devices = bytes[0];
+                for (d, hz) in config.rates.iter_mut().enumerate() {
+                    *hz = V1_RATES[bytes[1 + d] as usize % V1_RATES.len()];
+                }
+                config.participant = u16::from_le_bytes([bytes[1 + MAX_DEVICES], bytes[2 + MAX_DEVICES]]);
+                config.condition = bytes[3 + MAX_DEVICES];
+                config
+            }
+            _ => return None,
+        };
+        match config.invalid() {
+            Some(_) => None,
+            None => Some(config),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::DEFAULT
+    }
+}
+
+pub static CONFIG: Mutex<RawMutex, Config> = Mutex::new(Config::DEFAULT);
+/// the defaults of the binary, for `cfg reset`
+static FALLBACK: Mutex<RawMutex, Config> = Mutex::new(Config::DEFAULT);
+
+/// Takes the flash for `ymem` and reads the configuration,
+/// or `default` if there is none. Call before the executors run.
+pub fn boot(flash: ymem::FlashPeripheral, default: Config) -> Config {
+    let mut flash = Flash::new_blocking(flash);
+    let mut buf = [0u8; LEN];
+    let config = ymem::read(&mut flash, Slot::Config, &mut buf)
+        .and_then(|len| Config::from_bytes(&buf[..len], &default))
+        .unwrap_or(default);
+    // nothing else runs yet, so the locks are free
+    if let Ok(mut slot) = ymem::FLASH.try_lock() {
+        slot.replace(flash);
+    }
+    if let Ok(mut current) = CONFIG.try_lock() {
+        *current = config;
+    }
+    if let Ok(mut fallback) = FALLBACK.try_lock() {
+        *fallback = default;
+    }
+    config
+}
+
+pub async fn get() -> Config {
+    *CONFIG.lock().await
+}
+
+pub async fn set(config: Config) {
+    *CONFIG.lock().await = config;
+}
+
+pub async fn save() -> Result<(), ymem::Error> {
+    let bytes = get().await.to_bytes();
+    ymem::store(Slot::Config, &bytes).await
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Devices(u8),
+    Rate(u8, u16),
+    Speed(u32),
+    LogLevel(u8),
+    Probes(u8),
+    Participant(u16),
+    Condition(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Show,
+    Set(Field),
+    Save,
+    /// back to the defaults of the binary
+    Reset,
+}
+
+impl Command {
+    /// Parses the words following `cfg` on a command line
+    pub fn parse(mut words: SplitWhitespace) -> Option<Command> {
+        let command = match words.next()? {
+            "show" => Command::Show,
+            "save" => Command::Save,
+            "reset" => Command::Reset,
+            "set" => {
+                let field = match words.next()? {
+                    "dev" => Field::Devices(words.next()?.parse().ok()?),
+                    "rate" => Field::Rate(words.next()?.parse().ok()?, words.next()?.parse().ok()?),
+                    "speed" => Field::Speed(words.next()?.parse().ok()?),
+                    "log" => Field::LogLevel(words.next()?.parse().ok()?),
+                    "probes" => Field::Probes(words.next()?.parse().ok()?),
+                    "participant" => Field::Participant(words.next()?.parse().ok()?),
+                    "condition" => Field::Condition(words.next()?.parse().ok()?),
+                    _ => return None,
+                };
+                Command::Set(field)
+            }
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+pub async fn apply(command: Command) {
+    use crate::ytfk::bsu::{Reply, REPLY};
+    let mut reply = Reply::new();
+    match command {
+        Command::Show => {
+            let c = get().await;
+            let _ = write!(
+                reply,
+                "cfg v{} dev {:#04x} rates {:?} speed {} log {} probes {} participant {} condition {}",
+                VERSION, c.devices, c.rates, c.speed, c.log_level, c.probes, c.participant, c.condition
+            );
+        }
+        Command::Set(field) => {
+            let mut config = CONFIG.lock().await;
+            let mut c = *config;
+            match field {
+                Field::Devices(mask) => c.devices = mask,
+                Field::Rate(d, hz) => {
+                    if let Some(rate) = c.rates.get_mut(d as usize) {
+                        *rate = hz;
+                    }
+                }
+                Field::Speed(hz) => c.speed = hz,
+                Field::LogLevel(level) => c.log_level = level,
+                Field::Probes(n) => c.probes = n,
+                Field::Participant(p) => c.participant = p,
+                Field::Condition(k) => c.condition = k,
+            }
+            match c.invalid() {
+                Some(why) => {
+                    let _ = write!(reply, "cfg not set: {}", why);
+                }
+                None => *config = c,
+            }
+        }
+        Command::Save => {
+            let _ = match save().await {
+                Ok(_) => write!(reply, "cfg saved"),
+                Err(e) => write!(reply, "cfg not saved: {:?}", e),
+            };
+        }
+        Command::Reset => {
+            let fallback = *FALLBACK.lock().await;
+            set(fallback).await;
+        }
+    }
+    if !reply.is_empty() {
+        REPLY.send(reply).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom() -> Config {
+        Config {
+            devices: 0b1010_0101,
+            rates: [10, 53, 149, 419, 1000, 2000, 0, 65535],
+            native: 0b0100_0000,
+            speed: 400_000,
+            log_level: 5,
+            probes: 6,
+            participant: 1234,
+            condition: 7,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let config = custom();
+        let bytes = config.to_bytes();
+        assert_eq!(bytes.len(), LEN);
+        assert_eq!(bytes[0], VERSION);
+        assert_eq!(Config::version(&bytes), Some(VERSION));
+        // the native devices come from the binary
+        let default = Config { native: config.native, ..Config::DEFAULT };
+        assert_eq!(Config::from_bytes(&bytes, &default), Some(config));
+    }
+
+    /// Rate 0 for a ticker or speed 0 would panic at every boot
+    #[test]
+    fn invalid_records_are_not_read() {
+        let config = custom();
+        assert_eq!(config.invalid(), None);
+        assert!(Config { native: 0, ..config }.invalid().is_some());
+        for speed in [0, MAX_SPEED + 1] {
+            let bad = Config { speed, ..config };
+            assert!(bad.invalid().is_some());
+            assert_eq!(Config::from_bytes(&bad.to_bytes(), &config), None);
+        }
+        let mut bad = config;
+        bad.rates[2] = 0;
+        assert_eq!(Config::from_bytes(&bad.to_bytes(), &config), None);
+        assert_eq!(Config::from_bytes(&config.to_bytes(), &Config::DEFAULT), None);
+        assert_eq!(Config { speed: MAX_SPEED, ..config }.invalid(), None);
+    }
+
+    #[test]
+    fn version_1_keeps_the_menu_settings() {
+        let mut bytes = [0u8; V1_LEN];
+        // devices 0b0010 would read as version 2 by its first byte
+        bytes[0] = 0b0010;
+        bytes[1..1 + MAX_DEVICES].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        bytes[1 + MAX_DEVICES..3 + MAX_DEVICES].copy_from_slice(&300u16.to_le_bytes());
+        bytes[3 + MAX_DEVICES] = 4;
+        assert_eq!(Config::version(&bytes), Some(1));
+        let default = custom();
+        let config = Config::from_bytes(&bytes, &default).unwrap();
+        assert_eq!(config.devices, 0b0010);
+        // index 7 wraps around
+        assert_eq!(config.rates, [10, 50, 100, 200, 500, 1000, 2000, 10]);
+        assert_eq!(config.participant, 300);
+        assert_eq!(config.condition, 4);
+        // what version 1 did not have
+        assert_eq!(config.speed, default.speed);
+        assert_eq!(config.log_level, default.log_level);
+        assert_eq!(config.probes, default.probes);
+    }
+
+    #[test]
+    fn unknown_records_give_none() {
+        let mut bytes = custom().to_bytes();
+        assert_eq!(Config::from_bytes(&[], &Config::DEFAULT), None);
+        assert_eq!(Config::from_bytes(&bytes[..LEN - 1], &Config::DEFAULT), None);
+        bytes[0] = VERSION + 1;
+        assert_eq!(Config::from_bytes(&bytes, &Config::DEFAULT), None);
+        bytes[0] = 1;
+        assert_eq!(Config::from_bytes(&bytes, &Config::DEFAULT), None);
+    }
+
+    #[test]
+    fn levels() {
+        let mut config = Config::DEFAULT;
+        assert_eq!(config.level(), log::LevelFilter::Info);
+        config.log_level = 0;
+        assert_eq!(config.level(), log::LevelFilter::Off);
+        config.log_level = 9;
+        assert_eq!(config.level(), log::LevelFilter::Trace);
+    }
+
+    #[test]
+    fn commands() {
+        let parse = |line: &str| Command::parse(line.split_whitespace());
+        assert_eq!(parse("show"), Some(Command::Show));
+        assert_eq!(parse("set rate 2 149"), Some(Command::Set(Field::Rate(2, 149))));
+        assert_eq!(parse("set probes 6"), Some(Command::Set(Field::Probes(6))));
+        assert_eq!(parse("set rate 2"), None);
+        assert_eq!(parse("set colour 2"), None);
+    }
+}
@@ -0,0 +1,698 @@
+pub use crate::*;
+
+/// # Device descriptions
+///
+/// `firmware!` turns a short description of a device into a whole
+/// binary: interrupt bindings, both executors, the stack of core 1,
+/// the configuration (`ycfg`) with the described rates as defaults,
+/// and the spawns. A sensor entry names the kind, its Grove port
+/// where it has one, an optional rate and its sensory, which is also
+/// its device number in the configuration:
+///
+/// ```ignore
+/// ylab::firmware! {
+///     sink: serial,
+///     ui: [led, button],
+///     control: ylab::ydev::button_control(record),
+///     core0: [moi() as 0, adc() at 53 Hz as 1],
+///     core1: [lsm6(1, quat) at 149 Hz as 2, bmi160(4) at 100 Hz as 3],
+/// }
+/// ```
+///
+/// Ports are the Grove ports of `board::MAKER_PI_PICO`. Sensors
+/// on I2C0 take the ports 1, 3 and 5, those on I2C1 (and the
+/// display) 2, 4 and 6. Any other port does not compile. The pins
+/// of every entry come from the board and are claimed at compile
+/// time (`check`), so an entry on the pins or the I2C controller of
+/// another one does not compile either. The macro also defines
+/// `fn record(on: bool)`, which switches recording of all described
+/// sensors, e.g. for `button_control`.
+///
+/// | kind | port | task |
+/// |---|---|---|
+/// | `moi()` | GP21/22 | `ysns::moi::task_2` |
+/// | `moi(4)` | GP21/22, GP8/9 | `ysns::moi::task` |
+/// | `adc()` | GP26-28 | `ysns::adc::task` |
+/// | `lsm6(p, out, n)` | I2C0 | `ysns::yxz_lsm6::task`, `multi_task` for n probes |
+/// | `bmi160(p, out)` | I2C1 | `ysns::yxz_bmi160::task` |
+/// | `irt_max(p)` | I2C0 | `ysns::yirt_max::task` |
+/// | `tlv(p)` | I2C0 | `ysns::yxz_tlv::task` |
+/// | `co2(p)` | I2C0 | `ysns::yco2::task`, events on sensory + 1 |
+///
+/// UI elements are `led`, `button` (`yuii::keys` on GP20 alone),
+/// `keys` (GP20-22, instead of `button`), `buzzer(sensory)`, `display(p)`, `graph(p)` and
+/// `play` (`ytfk::replay`, recordings streamed in by the host).
+/// `menu` runs `yuii::menu` on `keys` and the display for the
+/// described sensors; with `control: ylab::ydev::menu_control(record)`
+/// recording starts with the session.
+/// The output `out` of the motion sensors is `raw` (default), `quat`
+/// or `euler` (`ypro::fusion::Output`). `n` is the default of the
+/// configured number of LSM6 probes (1, up to 8 on a multiplexer).
+/// `graph` plots channel 0 of the first enabled sensors.
+/// The sink is `serial` (`bsu::serial_task`) or `logger`.
+///
+/// Every kind, `display`, `graph` and the sink need their cargo
+/// feature, which the binary's `required-features` should list.
+///
+/// With `sim`, the same description runs on the host, on one
+/// executor for both cores (`ysim`). The sensors are `sine()`,
+/// `noise()`, `step()` and `values()`, the file named on the
+/// command line (`ysim::Args`). The sink is `host`, and the UI
+/// elements `led`, `display`, `button`, `keys` and `play` are on
+/// the terminal, as is `menu`, next to `buzzer(sensory)`, `stim(sensory)`
+/// (`yuio::stim`) and `rbx(sensory)` (`yuii::rbx`).
+use board::{Board, Claims, Conflict, Function};
+use yuii::keys::{Gesture, EVENTS};
+use yuii::menu::SESSION;
+use yuio::led::{State, LED};
+
+/// What an entry of `firmware!` takes from the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Use {
+    /// a Grove port with its I2C controller
+    I2c(Function, u8),
+    /// GPIOs outside the board tables
+    Pins(&'static [u8]),
+    Led,
+    /// the first n buttons
+    Buttons(usize),
+    Buzzer,
+    Adc,
+    Nothing,
+}
+
+/// An entry of `firmware!`, `what` names it in errors
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub what: &'static str,
+    pub uses: Use,
+}
+
+/// Claims what the entries use on `board`, in order, and panics
+/// on the first conflict. `firmware!` calls it in a const, so the
+/// panic is a compile error, e.g. "firmware!: `moi() as 0` wants
+/// GP21, which `keys` has".
+pub const fn check(board: &Board, entries: &[Entry]) {
+    let mut claims = Claims::new(board);
+    let mut i = 0;
+    while i < entries.len() {
+        let Entry { what, uses } = entries[i];
+        let claimed = match uses {
+            Use::I2c(controller, port) => {
+                let mut j = 0;
+                while j < i {
+                    if let Use::I2c(other, _) = entries[j].uses {
+                        if controller as u8 == other as u8 {
+                            Message::new(what)
+                                .push(" takes ")
+                                .push(name(controller))
+                                .push(", which `")
+                                .push(entries[j].what)
+                                .push("` has")
+                                .fail();
+                        }
+                    }
+                    j += 1;
+                }
+                match claims.port(what, port, controller) {
+                    Ok(_) => Ok(()),
+                    Err(conflict) => Err(conflict),
+                }
+            }
+            Use::Pins(pins) => claims.pins(what, pins),
+            Use::Led => match board.led {
+                Some(pin) => claims.pins(what, &[pin]),
+                None => Message::new(what).push(" wants the LED, which ").push(board.name).push(" has not").fail(),
+            },
+            Use::Buttons(n) if n <= board.buttons.len() => claims.pins(what, board.buttons.split_at(n).0),
+            Use::Buttons(_) => {
+                Message::new(what).push(" wants more buttons than ").push(board.name).push(" has").fail()
+            }
+            Use::Buzzer => match board.buzzer {
+                Some(pin) => claims.pins(what, &[pin]),
+                None => Message::new(what).push(" wants the buzzer, which ").push(board.name).push(" has not").fail(),
+            },
+            Use::Adc => claims.pins(what, board.adc),
+            Use::Nothing => Ok(()),
+        };
+        match claimed {
+            Ok(()) => {}
+            Err(Conflict::NoPort(port)) => Message::new(what)
+                .push(" wants port ")
+                .num(port)
+                .push(", which ")
+                .push(board.name)
+                .push(" has not")
+                .fail(),
+            Err(Conflict::Unsupported(port, function)) => Message::new(what)
+                .push(" wants port ")
+                .num(port)
+                .push(" as ")
+                .push(name(function))
+                .push(", which it is not")
+                .fail(),
+            Err(Conflict::NoPin(pin)) => {
+                Message::new(what).push(" wants GP").num(pin).push(", which does not exist").fail()
+            }
+            Err(Conflict::Taken { pin, by }) => {
+                Message::new(what).push(" wants GP").num(pin).push(", which `").push(by).push("` has").fail()
+            }
+        }
+        i += 1;
+    }
+}
+
+const fn name(function: Function) -> &'static str {
+    match function {
+        Function::I2c0 => "I2C0",
+        Function::I2c1 => "I2C1",
+        Function::Uart0 => "UART0",
+        Function::Uart1 => "UART1",
+        Function::Adc => "ADC",
+        Function::Digital => "digital",
+    }
+}
+
+/// A panic message built in const, where `panic!` takes one `&str`
+struct Message {
+    bytes: [u8; 160],
+    len: usize,
+}
+
+impl Message {
+    const fn new(what: &str) -> Self {
+        Message { bytes: [0; 160], len: 0 }.push("firmware!: `").push(what).push("`")
+    }
+
+    const fn push(mut self, text: &str) -> Self {
+        let text = text.as_bytes();
+        let mut i = 0;
+        while i < text.len() && self.len < self.bytes.len() {
+            self.bytes[self.len] = text[i];
+            self.len += 1;
+            i += 1;
+        }
+        self
+    }
+
+    const fn num(self, n: u8) -> Self {
+        let digits = [b'0' + n / 100, b'0' + n / 10 % 10, b'0' + n % 10];
+        let skip = (n < 100) as usize + (n < 10) as usize;
+        match core::str::from_utf8(digits.split_at(skip).1) {
+            Ok(text) => self.push(text),
+            Err(_) => self,
+        }
+    }
+
+    const fn fail(self) -> ! {
+        // truncation may split a character, then the start has to do
+        match core::str::from_utf8(self.bytes.split_at(self.len).0) {
+            Ok(text) => panic!("{}", text),
+            Err(error) => match core::str::from_utf8(self.bytes.split_at(error.valid_up_to()).0) {
+                Ok(text) => panic!("{}", text),
+                Err(_) => panic!("firmware!: conflict"),
+            },
+        }
+    }
+}
+
+/// Click toggles recording, long press stops it
+#[embassy_executor::task]
+pub async fn button_control(record: fn(bool)) {
+    let mut recording = true;
+    record(recording);
+    LED.signal(State::Steady);
+    loop {
+        match EVENTS.receive().await.gesture {
+            Gesture::Click => {
+                recording = !recording;
+                record(recording);
+                LED.signal(if recording { State::Steady } else { State::Blink });
+            }
+            Gesture::Long => {
+                recording = false;
+                record(recording);
+                LED.signal(State::Vibrate);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Records from the start of a session in the `menu` on,
+/// sessions started later go on recording
+#[embassy_executor::task]
+pub async fn menu_control(record: fn(bool)) {
+    record(false);
+    LED.signal(State::Off);
+    SESSION.wait().await;
+    record(true);
+    LED.signal(State::Steady);
+}
+
+#[cfg(feature = "rp")]
+#[macro_export]
+macro_rules! firmware {
+    (
+        sink: $sink:ident,
+        ui: [$($ui:ident $(($($uarg:tt)*))?),* $(,)?],
+        control: $control:expr,
+        core0: [$($k0:ident ($($a0:tt)*) $(at $hz0:literal Hz)? as $s0:literal),* $(,)?],
+        core1: [$($k1:ident ($($a1:tt)*) $(at $hz1:literal Hz)? as $s1:literal),* $(,)?] $(,)?
+    ) => {
+        use {defmt_rtt as _, panic_probe as _};
+
+        static mut CORE1_STACK: $crate::hal::multicore::Stack<4096> = $crate::hal::multicore::Stack::new();
+        static EXECUTOR0: ::static_cell::StaticCell<::embassy_executor::Executor> = ::static_cell::StaticCell::new();
+        static EXECUTOR1: ::static_cell::StaticCell<::embassy_executor::Executor> = ::static_cell::StaticCell::new();
+
+        /// the described sensors by device number, for the `menu`
+        #[allow(dead_code)]
+        const DEVICES: &[$crate::yuii::menu::Device<'static>] =
+            &[$(($s0, stringify!($k0)),)* $(($s1, stringify!($k1)),)*];
+
+        /// the described sensories, in order
+        #[allow(dead_code)]
+        const SENSORIES: &[u8] = &[$($s0,)* $($s1,)*];
+
+        const _: () = $crate::ydev::check(&$crate::board::MAKER_PI_PICO, &[
+            $( $crate::firmware!(@entry $ui $(($($uarg)*))?), )*
+            $( $crate::firmware!(@entry $k0 ($($a0)*) as $s0), )*
+            $( $crate::firmware!(@entry $k1 ($($a1)*) as $s1), )*
+        ]);
+
+        $crate::hal::bind_interrupts!(struct Irqs {
+            I2C0_IRQ => $crate::hal::i2c::InterruptHandler<$crate::hal::peripherals::I2C0>;
+            I2C1_IRQ => $crate::hal::i2c::InterruptHandler<$crate::hal::peripherals::I2C1>;
+            ADC_IRQ_FIFO => $crate::hal::adc::InterruptHandler;
+        });
+
+        /// the described devices, until a configuration is saved
+        fn defaults() -> $crate::ycfg::Config {
+            let mut config = $crate::ycfg::Config::default();
+            config.devices = 0;
+            $( config.devices |= 1 << $s0; $( config.rates[$s0] = $hz0; )? )*
+            $( config.devices |= 1 << $s1; $( config.rates[$s1] = $hz1; )? )*
+            $( config.native |= ($crate::firmware!(@native $k0) as u8) << $s0; )*
+            $( config.native |= ($crate::firmware!(@native $k1) as u8) << $s1; )*
+            $( $crate::firmware!(@config config $k0 ($($a0)*)); )*
+            $( $crate::firmware!(@config config $k1 ($($a1)*)); )*
+            config
+        }
+
+        /// switches recording of all described sensors
+        #[allow(dead_code)]
+        fn record(on: bool) {
+            $( $crate::firmware!(@record $k0 on); )*
+            $( $crate::firmware!(@record $k1 on); )*
+            $crate::ytfk::replay::RECORD.store(on, $crate::ORD);
+            #[cfg(feature = "display")]
+            $crate::yuio::graph::RECORDING.store(on, $crate::ORD);
+        }
+
+        #[::cortex_m_rt::entry]
+        fn init() -> ! {
+            let p = $crate::hal::init(Default::default());
+            let cfg = $crate::ycfg::boot(p.FLASH, defaults());
+            $crate::hal::multicore::spawn_core1(p.CORE1, unsafe { &mut CORE1_STACK }, move || {
+                let executor1 = EXECUTOR1.init(::embassy_executor::Executor::new());
+                executor1.run(|spawner| {
+                    $( $crate::firmware!(@sensor spawner p cfg $s1 $k1 ($($a1)*)); )*
+                })
+            });
+            let executor0 = EXECUTOR0.init(::embassy_executor::Executor::new());
+            executor0.run(|spawner| {
+                $( $crate::firmware!(@ui spawner p cfg $ui $(($($uarg)*))?); )*
+                $crate::firmware!(@sink spawner p cfg $sink);
+                $( $crate::firmware!(@sensor spawner p cfg $s0 $k0 ($($a0)*)); )*
+                spawner.spawn($control).unwrap();
+            })
+        }
+    };
+
+    // Entries for `check`
+    (@entry $k:ident $(($($a:tt)*))? $(as $s:literal)?) => {
+        $crate::ydev::Entry {
+            what: concat!(stringify!($k), $("(", stringify!($($a)*), ")",)? $(" as ", $s,)?),
+            uses: $crate::firmware!(@use $k $(($($a)*))?),
+        }
+    };
+    (@use moi ()) => { $crate::ydev::Use::Pins(&[21, 22]) };
+    (@use moi (4)) => { $crate::ydev::Use::Pins(&[21, 22, 8, 9]) };
+    (@use adc ()) => { $crate::ydev::Use::Adc };
+    (@use led) => { $crate::ydev::Use::Led };
+    (@use button) => { $crate::ydev::Use::Buttons(1) };
+    (@use keys) => { $crate::ydev::Use::Buttons(3) };
+    (@use buzzer ($s:literal)) => { $crate::ydev::Use::Buzzer };
+    (@use lsm6 ($port:tt $(, $out:ident $(, $n:literal)?)?)) => { $crate::ydev::Use::I2c($crate::board::Function::I2c0, $port) };
+    (@use irt_max ($port:tt)) => { $crate::ydev::Use::I2c($crate::board::Function::I2c0, $port) };
+    (@use tlv ($port:tt)) => { $crate::ydev::Use::I2c($crate::board::Function::I2c0, $port) };
+    (@use co2 ($port:tt)) => { $crate::ydev::Use::I2c($crate::board::Function::I2c0, $port) };
+    (@use bmi160 ($port:tt $(, $out:ident)?)) => { $crate::ydev::Use::I2c($crate::board::Function::I2c1, $port) };
+    (@use display ($port:tt)) => { $crate::ydev::Use::I2c($crate::board::Function::I2c1, $port) };
+    (@use graph ($port:tt)) => { $crate::ydev::Use::I2c($crate::board::Function::I2c1, $port) };
+    (@use $k:ident $($rest:tt)*) => { $crate::ydev::Use::Nothing };
+
+    // Grove ports
+    (@i2c0 $p:ident $cfg:ident 1) => { $crate::firmware!(@bus $p $cfg I2C0 PIN_1 PIN_0) };
+    (@i2c0 $p:ident $cfg:ident 3) => { $crate::firmware!(@bus $p $cfg I2C0 PIN_5 PIN_4) };
+    (@i2c0 $p:ident $cfg:ident 5) => { $crate::firmware!(@bus $p $cfg I2C0 PIN_9 PIN_8) };
+    (@i2c0 $p:ident $cfg:ident $port:tt) => {
+        compile_error!(concat!("firmware!: port ", stringify!($port), " is not on I2C0, which has the Grove ports 1, 3 and 5"))
+    };
+    (@i2c1 $p:ident $cfg:ident 2) => { $crate::firmware!(@bus $p $cfg I2C1 PIN_3 PIN_2) };
+    (@i2c1 $p:ident $cfg:ident 4) => { $crate::firmware!(@bus $p $cfg I2C1 PIN_7 PIN_6) };
+    (@i2c1 $p:ident $cfg:ident 6) => { $crate::firmware!(@bus $p $cfg I2C1 PIN_27 PIN_26) };
+    (@i2c1 $p:ident $cfg:ident $port:tt) => {
+        compile_error!(concat!("firmware!: port ", stringify!($port), " is not on I2C1, which has the Grove ports 2, 4 and 6"))
+    };
+    (@bus $p:ident $cfg:ident $ctrl:ident $scl:ident $sda:ident) => {{
+        let mut config = $crate::hal::i2c::Config::default();
+        config.frequency = $cfg.speed.into();
+        $crate::hal::i2c::I2c::new_async($p.$ctrl, $p.$scl, $p.$sda, Irqs, config)
+    }};
+
+    // Sensors
+    (@sensor $sp:ident $p:ident $cfg:ident $s:literal moi ()) => {
+        if $cfg.enabled($s) {
+            $sp.spawn($crate::ysns::moi::task_2($p.PIN_21, $p.PIN_22, $s)).unwrap();
+        }
+    };
+    (@sensor $sp:ident $p:ident $cfg:ident $s:literal moi (4)) => {
+        if $cfg.enabled($s) {
+            $sp.spawn($crate::ysns::moi::task($p.PIN_21, $p.PIN_22, $p.PIN_8, $p.PIN_9, $s)).unwrap();
+        }
+    };
+    (@sensor $sp:ident $p:ident $cfg:ident $s:literal adc ()) => {
+        if $cfg.enabled($s) {
+            let adc = $crate::hal::adc::Adc::new($p.ADC, Irqs, $crate::hal::adc::Config::default());
+            $sp.spawn($crate::ysns::adc::task(adc, $p.PIN_26, $p.PIN_27, $p.PIN_28, $cfg.hz($s), $s)).unwrap();
+        }
+    };
+    (@sensor $sp:ident $p:ident $cfg:ident $s:literal lsm6 ($port:tt $(, $out:ident $(, $n:literal)?)?)) => {
+        if $cfg.enabled($s) {
+            let i2c = $crate::firmware!(@i2c0 $p $cfg $port);
+            let output = $crate::firmware!(@output $($out)?);
+            match $cfg.probes {
+                1 => $sp.spawn($crate::ysns::yxz_lsm6::task(i2c, $cfg.hz($s), output, $s)).unwrap(),
+                // probes behind a multiplexer share the rate
+                n @ 2..=8 => {
+                    let hz = $cfg.hz($s) / n as u64;
+                    $sp.spawn($crate::ysns::yxz_lsm6::multi_task(i2c, n, hz, false, output, $s)).unwrap()
+                }
+                _ => {}
+            }
+        }
+    };
+    (@sensor $sp:ident $p:ident $cfg:ident $s:literal bmi160 ($port:tt $(, $out:ident)?)) => {
+        if $cfg.enabled($s) {
+            let i2c = $crate::firmware!(@i2c1 $p $cfg $port);
+            let output = $crate::firmware!(@output $($out)?);
+            $sp.spawn($crate::ysns::yxz_bmi160::task(i2c, $cfg.hz($s), output, $s)).unwrap();
+        }
+    };
+    (@sensor $sp:ident $p:ident $cfg:ident $s:literal irt_max ($port:tt)) => {
+        if $cfg.enabled($s) {
+            let i2c = $crate::firmware!(@i2c0 $p $cfg $port);
+            $sp.spawn($crate::ysns::yirt_max::task(i2c, Default::default(), $cfg.hz($s), $s)).unwrap();
+        }
+    };
+    (@sensor $sp:ident $p:ident $cfg:ident $s:literal tlv ($port:tt)) => {
+        if $cfg.enabled($s) {
+            let i2c = $crate::firmware!(@i2c0 $p $cfg $port);
+            $sp.spawn($crate::ysns::yxz_tlv::task(i2c, Default::default(), $cfg.hz($s), $s)).unwrap();
+        }
+    };
+    (@sensor $sp:ident $p:ident $cfg:ident $s:literal co2 ($port:tt)) => {
+        if $cfg.enabled($s) {
+            let i2c = $crate::firmware!(@i2c0 $p $cfg $port);
+            $sp.spawn($crate::ysns::yco2::task(i2c, Default::default(), $cfg.hz($s), $s)).unwrap();
+        }
+    };
+
+    (@output) => { $crate::ypro::fusion::Output::Raw };
+    (@output raw) => { $crate::ypro::fusion::Output::Raw };
+    (@output quat) => { $crate::ypro::fusion::Output::Quat };
+    (@output euler) => { $crate::ypro::fusion::Output::Euler };
+    (@output $out:ident) => {
+        compile_error!(concat!("firmware!: output `", stringify!($out), "` is none of raw, quat and euler"))
+    };
+
+    // Sensors taking a rate of 0 as their own
+    (@native moi) => { true };
+    (@native co2) => { true };
+    (@native $k:ident) => { false };
+
+    // Defaults from the arguments
+    (@config $c:ident lsm6 ($port:tt, $out:ident, $n:literal)) => { $c.probes = $n; };
+    (@config $c:ident $k:ident ($($a:tt)*)) => {};
+
+    (@record moi $on:ident) => { $crate::ysns::moi::RECORD.store($on, $crate::ORD) };
+    (@record adc $on:ident) => { $crate::ysns::adc::RECORD.store($on, $crate::ORD) };
+    (@record lsm6 $on:ident) => { $crate::ysns::yxz_lsm6::RECORD.store($on, $crate::ORD) };
+    (@record bmi160 $on:ident) => { $crate::ysns::yxz_bmi160::RECORD.store($on, $crate::ORD) };
+    (@record irt_max $on:ident) => { $crate::ysns::yirt_max::RECORD.store($on, $crate::ORD) };
+    (@record tlv $on:ident) => { $crate::ysns::yxz_tlv::RECORD.store($on, $crate::ORD) };
+    (@record co2 $on:ident) => { $crate::ysns::yco2::RECORD.store($on, $crate::ORD) };
+
+    // UI
+    (@ui $sp:ident $p:ident $cfg:ident led) => {
+        $sp.spawn($crate::yuio::led::task($crate::hal::gpio::Pin::degrade($p.PIN_25))).unwrap();
+    };
+    (@ui $sp:ident $p:ident $cfg:ident button) => {{
+        let pins = $crate::Vec::from_iter([$crate::hal::gpio::Pin::degrade($p.PIN_20)]);
+        $sp.spawn($crate::yuii::keys::task(pins, $crate::yuii::keys::Config::single())).unwrap();
+    }};
+    (@ui $sp:ident $p:ident $cfg:ident keys) => {{
+        let mut pins = $crate::Vec::new();
+        for pin in [
+            $crate::hal::gpio::Pin::degrade($p.PIN_20),
+            $crate::hal::gpio::Pin::degrade($p.PIN_21),
+            $crate::hal::gpio::Pin::degrade($p.PIN_22),
+        ] {
+            let _ = pins.push(pin);
+        }
+        $sp.spawn($crate::yuii::keys::task(pins, Default::default())).unwrap();
+    }};
+    (@ui $sp:ident $p:ident $cfg:ident buzzer ($s:literal)) => {
+        $sp.spawn($crate::yuio::buzzer::task($p.PWM_CH1, $p.PIN_18, $s)).unwrap();
+    };
+    (@ui $sp:ident $p:ident $cfg:ident display ($port:tt)) => {
+        $sp.spawn($crate::yuio::disp::task($crate::firmware!(@i2c1 $p $cfg $port))).unwrap();
+    };
+    (@ui $sp:ident $p:ident $cfg:ident graph ($port:tt)) => {{
+        let traces = SENSORIES
+            .iter()
+            .filter(|&&s| $cfg.enabled(s as usize))
+            .map(|&sensory| $crate::yuio::graph::Trace { sensory, channel: 0 })
+            .take($crate::yuio::graph::MAX_TRACES)
+            .collect();
+        $sp.spawn($crate::yuio::graph::task($crate::firmware!(@i2c1 $p $cfg $port), traces)).unwrap();
+    }};
+    (@ui $sp:ident $p:ident $cfg:ident play) => {
+        $sp.spawn($crate::ytfk::replay::task(1.0)).unwrap();
+    };
+    (@ui $sp:ident $p:ident $cfg:ident menu) => {
+        $sp.spawn($crate::yuii::menu::task(DEVICES)).unwrap();
+    };
+
+    // Sinks
+    (@sink $sp:ident $p:ident $cfg:ident serial) => {
+        $crate::ytfk::bsu::log_to_reply($cfg.level());
+        $sp.spawn($crate::ytfk::bsu::serial_task($p.USB, None)).unwrap();
+    };
+    (@sink $sp:ident $p:ident $cfg:ident logger) => {
+        $sp.spawn($crate::ytfk::bsu::logger_task($p.USB, $cfg.level())).unwrap();
+        $sp.spawn($crate::ytfk::bsu::task()).unwrap();
+    };
+}
+
+#[cfg(feature = "sim")]
+#[macro_export]
+macro_rules! firmware {
+    (
+        sink: $sink:ident,
+        ui: [$($ui:ident $(($($uarg:tt)*))?),* $(,)?],
+        control: $control:expr,
+        core0: [$($k0:ident ($($a0:tt)*) $(at $hz0:literal Hz)? as $s0:literal),* $(,)?],
+        core1: [$($k1:ident ($($a1:tt)*) $(at $hz1:literal Hz)? as $s1:literal),* $(,)?] $(,)?
+    ) => {
+        /// the described sensors by device number, for the `menu`
+        #[allow(dead_code)]
+        const DEVICES: &[$crate::yuii::menu::Device<'static>] =
+            &[$(($s0, stringify!($k0)),)* $(($s1, stringify!($k1)),)*];
+
+        /// the described devices, until a configuration is saved
+        fn defaults() -> $crate::ycfg::Config {
+            let mut config = $crate::ycfg::Config::default();
+            config.devices = 0;
+            $( config.devices |= 1 << $s0; $( config.rates[$s0] = $hz0; )? )*
+            $( config.devices |= 1 << $s1; $( config.rates[$s1] = $hz1; )? )*
+            $( config.native |= ($crate::firmware!(@native $k0) as u8) << $s0; )*
+            $( config.native |= ($crate::firmware!(@native $k1) as u8) << $s1; )*
+            $( $crate::firmware!(@config config $k0 ($($a0)*)); )*
+            $( $crate::firmware!(@config config $k1 ($($a1)*)); )*
+            config
+        }
+
+        /// switches recording of all described sensors
+        #[allow(dead_code)]
+        fn record(on: bool) {
+            $( $crate::firmware!(@record $k0 on); )*
+            $( $crate::firmware!(@record $k1 on); )*
+            $crate::ytfk::replay::RECORD.store(on, $crate::ORD);
+        }
+
+        #[::embassy_executor::main]
+        async fn main(spawner: ::embassy_executor::Spawner) {
+            let args = $crate::ysim::Args::parse(::std::env::args().skip(1));
+            let cfg = $crate::ycfg::boot($crate::ysim::flash::FlashPeripheral, defaults());
+            $( $crate::firmware!(@ui spawner args cfg $ui $(($($uarg)*))?); )*
+            $crate::firmware!(@sink spawner $sink);
+            $( $crate::firmware!(@sensor spawner args cfg $s0 $k0 ($($a0)*)); )*
+            $( $crate::firmware!(@sensor spawner args cfg $s1 $k1 ($($a1)*)); )*
+            spawner.spawn($control).unwrap();
+        }
+    };
+
+    // Virtual sensors
+    (@sensor $sp:ident $args:ident $cfg:ident $s:literal sine ()) => {
+        if $cfg.enabled($s) {
+            $sp.spawn($crate::ysim::sine::task(Default::default(), $cfg.hz($s), $s)).unwrap();
+        }
+    };
+    (@sensor $sp:ident $args:ident $cfg:ident $s:literal noise ()) => {
+        if $cfg.enabled($s) {
+            $sp.spawn($crate::ysim::noise::task(Default::default(), $cfg.hz($s), $s)).unwrap();
+        }
+    };
+    (@sensor $sp:ident $args:ident $cfg:ident $s:literal step ()) => {
+        if $cfg.enabled($s) {
+            $sp.spawn($crate::ysim::step::task(Default::default(), $cfg.hz($s), $s)).unwrap();
+        }
+    };
+    (@sensor $sp:ident $args:ident $cfg:ident $s:literal values ()) => {
+        if let (true, Some(path)) = ($cfg.enabled($s), $args.values) {
+            $sp.spawn($crate::ysim::replay::task(path, $cfg.hz($s), $s)).unwrap();
+        }
+    };
+
+    (@native $k:ident) => { false };
+    (@config $c:ident $k:ident ($($a:tt)*)) => {};
+
+    (@record sine $on:ident) => { $crate::ysim::sine::RECORD.store($on, $crate::ORD) };
+    (@record noise $on:ident) => { $crate::ysim::noise::RECORD.store($on, $crate::ORD) };
+    (@record step $on:ident) => { $crate::ysim::step::RECORD.store($on, $crate::ORD) };
+    (@record values $on:ident) => { $crate::ysim::replay::RECORD.store($on, $crate::ORD) };
+
+    // UI, buttons come in on stdin
+    (@ui $sp:ident $args:ident $cfg:ident led) => {
+        $sp.spawn($crate::ysim::ui::led()).unwrap();
+    };
+    (@ui $sp:ident $args:ident $cfg:ident display $(($port:tt))?) => {
+        $sp.spawn($crate::ysim::ui::display()).unwrap();
+    };
+    (@ui $sp:ident $args:ident $cfg:ident button) => {};
+    (@ui $sp:ident $args:ident $cfg:ident keys) => {};
+    (@ui $sp:ident $args:ident $cfg:ident menu) => {
+        $sp.spawn($crate::yuii::menu::task(DEVICES)).unwrap();
+    };
+    (@ui $sp:ident $args:ident $cfg:ident buzzer ($s:literal)) => {
+        $sp.spawn($crate::ysim::ui::buzzer($s)).unwrap();
+    };
+    (@ui $sp:ident $args:ident $cfg:ident stim ($s:literal)) => {
+        $sp.spawn($crate::ysim::ui::stim($s)).unwrap();
+    };
+    (@ui $sp:ident $args:ident $cfg:ident rbx ($s:literal)) => {
+        $crate::yuii::rbx::RECORD.store(true, $crate::ORD);
+        $sp.spawn($crate::ysim::ui::responses(Default::default(), $s)).unwrap();
+    };
+    (@ui $sp:ident $args:ident $cfg:ident play) => {
+        $sp.spawn($crate::ytfk::replay::task($args.speed.unwrap_or(1.0))).unwrap();
+        // a recording named on the command line is meant to be heard
+        if let Some(path) = $args.play {
+            $crate::ytfk::replay::RECORD.store(true, $crate::ORD);
+            $sp.spawn($crate::ysim::replay::recording(path)).unwrap();
+        }
+    };
+
+    // Sinks
+    (@sink $sp:ident host) => {
+        $sp.spawn($crate::ysim::host::task()).unwrap();
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use board::MAKER_PI_PICO;
+
+    fn entry(what: &'static str, uses: Use) -> Entry {
+        Entry { what, uses }
+    }
+
+    #[test]
+    fn whole_device() {
+        check(
+            &MAKER_PI_PICO,
+            &[
+                entry("led", Use::Led),
+                entry("button", Use::Buttons(1)),
+                entry("buzzer(4)", Use::Buzzer),
+                entry("graph(2)", Use::I2c(Function::I2c1, 2)),
+                entry("moi() as 0", Use::Pins(&[21, 22])),
+                entry("adc() as 1", Use::Adc),
+                entry("lsm6(1) as 2", Use::I2c(Function::I2c0, 1)),
+            ],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "firmware!: `co2(5) as 3` takes I2C0, which `lsm6(1) as 2` has")]
+    fn shared_controller() {
+        check(
+            &MAKER_PI_PICO,
+            &[entry("lsm6(1) as 2", Use::I2c(Function::I2c0, 1)), entry("co2(5) as 3", Use::I2c(Function::I2c0, 5))],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "firmware!: `moi() as 0` wants GP21, which `keys` has")]
+    fn shared_pin() {
+        check(&MAKER_PI_PICO, &[entry("keys", Use::Buttons(3)), entry("moi() as 0", Use::Pins(&[21, 22]))]);
+    }
+
+    /// The four-channel moi takes Grove 5 as plain pins
+    #[test]
+    #[should_panic(expected = "firmware!: `co2(5) as 2` wants GP8, which `moi(4) as 0` has")]
+    fn four_moi_on_port_5() {
+        check(
+            &MAKER_PI_PICO,
+            &[entry("moi(4) as 0", Use::Pins(&[21, 22, 8, 9])), entry("co2(5) as 2", Use::I2c(Function::I2c0, 5))],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "firmware!: `graph(6)` wants GP26, which `adc() as 1` has")]
+    fn port_on_adc() {
+        check(&MAKER_PI_PICO, &[entry("adc() as 1", Use::Adc), entry("graph(6)", Use::I2c(Function::I2c1, 6))]);
+    }
+
+    #[test]
+    #[should_panic(expected = "firmware!: `lsm6(1) as 2` wants port 1, which Pico has not")]
+    fn no_port() {
+        check(&board::PICO, &[entry("lsm6(1) as 2", Use::I2c(Function::I2c0, 1))]);
+    }
+
+    #[test]
+    #[should_panic(expected = "firmware!: `led` wants the LED, which Pico W has not")]
+    fn no_led() {
+        check(&board::PICO_W, &[entry("led", Use::Led)]);
+    }
+
+    #[test]
+    fn message() {
+        let message = Message::new("x").push(" wants GP").num(7).push(", ").num(29).push(", ").num(130);
+        assert_eq!(&message.bytes[..message.len], b"firmware!: `x` wants GP7, 29, 130");
+    }
+}
@@ -0,0 +1,169 @@
+pub use crate::*;
+
+/// # Persistent storage
+///
+/// The last 64 KiB of flash are kept free of firmware (see `memory.x`)
+/// and split into 4 KiB slots, one erase sector each. A slot holds a
+/// single record, framed by a magic word, its length and a CRC,
+/// so that an erased or half-written slot reads as empty.
+///
+/// The flash is owned by `FLASH` and borrowed by whichever task
+/// loads or stores a record. Call `init` once at boot.
+/// In the host simulation, the flash is `ysim::flash`, kept in memory.
+#[cfg(feature = "rp")]
+use hal::flash::{Blocking, Flash as HalFlash, ERASE_SIZE};
+#[cfg(feature = "rp")]
+pub use hal::peripherals::FLASH as FlashPeripheral;
+#[cfg(feature = "sim")]
+pub use crate::ysim::flash::{Flash, FlashPeripheral, ERASE_SIZE};
+
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+/// Start of the reserved area, relative to the flash base
+pub const STORAGE: u32 = (FLASH_SIZE - 64 * 1024) as u32;
+pub const SLOT_SIZE: usize = ERASE_SIZE;
+
+#[cfg(feature = "rp")]
+pub type Flash = HalFlash<'static, FlashPeripheral, Blocking, FLASH_SIZE>;
+pub static FLASH: Mutex<RawMutex, Option<Flash>> = Mutex::new(None);
+
+const MAGIC: u32 = 0x594C_4142; // "YLAB"
+/// magic, length, CRC
+const HEADER: usize = 4 + 2 + 4;
+pub const MAX_RECORD: usize = 1024;
+/// flash is written in whole pages
+const PAGE: usize = 256;
+
+/// One slot per kind of record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    Config = 0,
+    LoadCell = 1,
+    Protocol = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// `init` has not been called
+    NoFlash,
+    Flash,
+    TooLong,
+}
+
+pub async fn init(flash: FlashPeripheral) {
+    FLASH.lock().await.replace(Flash::new_blocking(flash));
+}
+
+/// CRC-32 (IEEE), bitwise to keep the table out of flash
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn offset(slot: Slot) -> u32 {
+    STORAGE + slot as u32 * SLOT_SIZE as u32
+}
+
+/// Bytes of the reserved area in slots without a record
+pub async fn free() -> Option<usize> {
+    let mut flash = FLASH.lock().await;
+    let flash = flash.as_mut()?;
+    let mut free = 64 * 1024;
+    for slot in 0..(64 * 1024 / SLOT_SIZE) as u32 {
+        let mut header = [0u8; HEADER];
+        flash.blocking_read(STORAGE + slot * SLOT_SIZE as u32, &mut header).ok()?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        if magic == MAGIC {
+            free -= SLOT_SIZE;
+        }
+    }
+    Some(free)
+}
+
+/// Reads the record in `slot` into `buf` and returns its length,
+/// or `None` if the slot is empty or damaged.
+pub async fn load(slot: Slot, buf: &mut [u8]) -> Option<usize> {
+    let mut flash = FLASH.lock().await;
+    read(flash.as_mut()?, slot, buf)
+}
+
+/// `load` on a flash at hand, for use before the executors run
+pub fn read(flash: &mut Flash, slot: Slot, buf: &mut [u8]) -> Option<usize> {
+    let mut header = [0u8; HEADER];
+    flash.blocking_read(offset(slot), &mut header).ok()?;
+    let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let len = u16::from_le_bytes([header[4], header[5]]) as usize;
+    let crc = u32::from_le_bytes([header[6], header[7], header[8], header[9]]);
+    if magic != MAGIC || len > buf.len() || len > MAX_RECORD {
+        return None;
+    }
+    flash
+        .blocking_read(offset(slot) + HEADER as u32, &mut buf[..len])
+        .ok()?;
+    if crc32(&buf[..len]) != crc {
+        return None;
+    }
+    Some(len)
+}
+
+/// Replaces the record in `slot`
+pub async fn store(slot: Slot, data: &[u8]) -> Result<(), Error> {
+    if data.len() > MAX_RECORD {
+        return Err(Error::TooLong);
+    }
+    let mut record: Vec<u8, { (HEADER + MAX_RECORD).next_multiple_of(PAGE) }> = Vec::new();
+    let _ = record.extend_from_slice(&MAGIC.to_le_bytes());
+    let _ = record.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    let _ = record.extend_from_slice(&crc32(data).to_le_bytes());
+    let _ = record.extend_from_slice(data);
+    let _ = record.resize(record.len().next_multiple_of(PAGE), 0xFF);
+    let mut flash = FLASH.lock().await;
+    let flash = flash.as_mut().ok_or(Error::NoFlash)?;
+    let start = offset(slot);
+    flash
+        .blocking_erase(start, start + SLOT_SIZE as u32)
+        .map_err(|_| Error::Flash)?;
+    flash
+        .blocking_write(start, &record)
+        .map_err(|_| Error::Flash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+
+    /// One test, as `FLASH` is shared
+    #[test]
+    fn records_round_trip_and_take_whole_slots() {
+        block_on(async {
+            assert_eq!(store(Slot::Config, &[1]).await, Err(Error::NoFlash));
+            init(FlashPeripheral).await;
+            assert_eq!(free().await, Some(64 * 1024));
+            let mut buf = [0u8; MAX_RECORD];
+            assert_eq!(load(Slot::Protocol, &mut buf).await, None);
+
+            // lengths around the page ends, up to the longest record
+            for len in [0, 1, 245, 246, 247, 1013, 1014, 1015, 1020, MAX_RECORD] {
+                let data: std::vec::Vec<u8> = (0..len).map(|i| (i * 7 + len) as u8).collect();
+                assert_eq!(store(Slot::Protocol, &data).await, Ok(()));
+                assert_eq!(load(Slot::Protocol, &mut buf).await, Some(len));
+                assert_eq!(buf[..len], data[..]);
+            }
+            assert_eq!(store(Slot::Protocol, &[0; MAX_RECORD + 1]).await, Err(Error::TooLong));
+            assert_eq!(free().await, Some(64 * 1024 - SLOT_SIZE));
+            assert_eq!(store(Slot::Config, &[1, 2, 3]).await, Ok(()));
+            assert_eq!(free().await, Some(64 * 1024 - 2 * SLOT_SIZE));
+
+            // a damaged record reads as empty
+            let mut flash = FLASH.lock().await;
+            flash.as_mut().unwrap().blocking_write(offset(Slot::Config) + HEADER as u32, &[0]).unwrap();
+            assert_eq!(read(flash.as_mut().unwrap(), Slot::Config, &mut buf), None);
+        });
+    }
+}
@@ -0,0 +1,1038 @@
+pub use crate::*;
+
+/// Readings of a fixture written in the logger's CSV format,
+/// lines starting with `#` describe the fixture.
+#[cfg(test)]
+fn fixture(csv: &str) -> impl Iterator<Item = Ytf> + '_ {
+    csv.lines().filter(|line| !line.starts_with('#')).map(|line| line.parse().expect(line))
+}
+
+/// # On-device processing
+///
+/// Signal processing stages that sensor tasks can put between
+/// the raw reading and `SINK`. Everything in here is plain
+/// arithmetic on readings and does not touch peripherals.
+
+pub mod fusion {
+    //! ## Orientation fusion
+    //!
+    //! A Mahony complementary filter turning six-axis readings
+    //! (accelerometer and gyroscope) into an orientation quaternion.
+    //! The M0+ has no FPU, so the filter runs in fixed point
+    //! (`I8F24`): the update only uses multiplications, additions
+    //! and two Newton-iterated inverse square roots per step.
+    //! Readings are converted once on the way in and the quaternion
+    //! once on the way out. Trigonometry is only needed for Euler output.
+    //!
+    //! Before the filter starts, the gyro bias is estimated
+    //! from a still period. As long as the sensor is moved,
+    //! the estimation window starts over.
+    use super::*;
+    use fixed::types::I8F24;
+    use libm::{asinf, atan2f};
+
+    /// What a motion task sends to `SINK`
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Output {
+        /// Raw accel and gyro (six channels)
+        Raw,
+        /// Orientation quaternion w, x, y, z (four channels)
+        Quat,
+        /// Roll, pitch, yaw in degrees (three channels)
+        Euler,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Quat {
+        pub w: f32,
+        pub x: f32,
+        pub y: f32,
+        pub z: f32,
+    }
+
+    impl Quat {
+        pub const IDENTITY: Quat = Quat {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+
+        /// Roll, pitch and yaw in degrees
+        pub fn euler(&self) -> [f32; 3] {
+            let Quat { w, x, y, z } = *self;
+            let roll = atan2f(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y));
+            let sinp = (2.0 * (w * y - z * x)).clamp(-1.0, 1.0);
+            let pitch = asinf(sinp);
+            let yaw = atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+            [roll.to_degrees(), pitch.to_degrees(), yaw.to_degrees()]
+        }
+    }
+
+    impl Into<[f32; 4]> for Quat {
+        fn into(self) -> [f32; 4] {
+            [self.w, self.x, self.y, self.z]
+        }
+    }
+
+    /// Filter arithmetic, ±128 at 6e-8 resolution. Enough for
+    /// unit quaternions, rates up to 128 rad/s and the per-step
+    /// increments of kHz sample rates.
+    type Fx = I8F24;
+
+    const ONE: Fx = Fx::ONE;
+    const TWO: Fx = Fx::lit("2");
+    const FOUR: Fx = Fx::lit("4");
+
+    /// Inverse square root: `x` is brought into [1, 4) by powers
+    /// of four, then three Newton steps from a linear first guess
+    /// get to about 1e-6. Callers keep `x` between 0.25 and 16.
+    fn inv_sqrt(x: Fx) -> Fx {
+        if x <= Fx::ZERO {
+            return Fx::ZERO;
+        }
+        let (mut x, mut up, mut down) = (x, 0, 0);
+        while x >= FOUR {
+            x >>= 2;
+            down += 1;
+        }
+        while x < ONE {
+            x <<= 2;
+            up += 1;
+        }
+        let mut y = Fx::lit("1.1") - Fx::lit("0.15") * x;
+        for _ in 0..3 {
+            y *= Fx::lit("1.5") - ((x * y * y) >> 1);
+        }
+        (y << up) >> down
+    }
+
+    /// `v` scaled to unit length, `None` for the zero vector.
+    /// Shifting by powers of two first keeps the squares in range
+    /// for any accelerometer unit.
+    fn unit(mut v: [Fx; 3]) -> Option<[Fx; 3]> {
+        if v.iter().all(|c| *c == Fx::ZERO) {
+            return None;
+        }
+        while v.iter().any(|c| c.saturating_abs() >= TWO) {
+            v = v.map(|c| c >> 1);
+        }
+        while v.iter().all(|c| c.abs() < ONE) {
+            v = v.map(|c| c << 1);
+        }
+        let n = inv_sqrt(v[0] * v[0] + v[1] * v[1] + v[2] * v[2]);
+        Some(v.map(|c| c * n))
+    }
+
+    fn fx(v: f32) -> Fx {
+        Fx::saturating_from_num(v)
+    }
+
+    /// ### Gyro bias from a still period
+    ///
+    /// Collects `n` samples and accepts their mean gyro
+    /// as bias, if no axis spread more than `max_spread` (rad/s).
+    pub struct Still {
+        n: u16,
+        max_spread: f32,
+        count: u16,
+        sum: [f32; 3],
+        min: [f32; 3],
+        max: [f32; 3],
+    }
+
+    impl Still {
+        pub fn new(n: u16, max_spread: f32) -> Self {
+            Still {
+                n: n.max(1),
+                max_spread,
+                count: 0,
+                sum: [0.0; 3],
+                min: [f32::MAX; 3],
+                max: [f32::MIN; 3],
+            }
+        }
+
+        fn restart(&mut self) {
+            *self = Still::new(self.n, self.max_spread);
+        }
+
+        /// Feeds one gyro reading (rad/s). Returns the bias once
+        /// a full window was still, otherwise `None`.
+        pub fn update(&mut self, gyr: [f32; 3]) -> Option<[f32; 3]> {
+            for i in 0..3 {
+                self.sum[i] += gyr[i];
+                self.min[i] = self.min[i].min(gyr[i]);
+                self.max[i] = self.max[i].max(gyr[i]);
+                if self.max[i] - self.min[i] > self.max_spread {
+                    self.restart();
+                    return None;
+                }
+            }
+            self.count += 1;
+            if self.count < self.n {
+                return None;
+            }
+            let n = self.count as f32;
+            Some([self.sum[0] / n, self.sum[1] / n, self.sum[2] / n])
+        }
+    }
+
+    /// ### Mahony filter
+    ///
+    /// `kp` pulls the estimate towards gravity, `ki` slowly
+    /// integrates residual gyro drift.
+    pub struct Mahony {
+        pub kp: f32,
+        pub ki: f32,
+        q: [Fx; 4],
+        integral: [Fx; 3],
+    }
+
+    impl Mahony {
+        pub fn new(kp: f32, ki: f32) -> Self {
+            Mahony {
+                kp,
+                ki,
+                q: [ONE, Fx::ZERO, Fx::ZERO, Fx::ZERO],
+                integral: [Fx::ZERO; 3],
+            }
+        }
+
+        /// Current estimate
+        pub fn q(&self) -> Quat {
+            let [w, x, y, z] = self.q.map(|c| c.to_num::<f32>());
+            Quat { w, x, y, z }
+        }
+
+        /// One filter step with accel in any unit,
+        /// gyro in rad/s and `dt` in seconds.
+        pub fn update(&mut self, acc: [f32; 3], gyr: [f32; 3], dt: f32) -> Quat {
+            let [w, x, y, z] = self.q;
+            let [mut gx, mut gy, mut gz] = gyr.map(fx);
+            let dt = fx(dt);
+            if let Some([ax, ay, az]) = unit(acc.map(fx)) {
+                // gravity as seen from the current estimate
+                let vx = (x * z - w * y) << 1;
+                let vy = (w * x + y * z) << 1;
+                let vz = w * w - x * x - y * y + z * z;
+                // error is the cross product of measured and estimated gravity
+                let ex = ay * vz - az * vy;
+                let ey = az * vx - ax * vz;
+                let ez = ax * vy - ay * vx;
+                if self.ki > 0.0 {
+                    let ki = fx(self.ki) * dt;
+                    self.integral[0] += ki * ex;
+                    self.integral[1] += ki * ey;
+                    self.integral[2] += ki * ez;
+                    gx += self.integral[0];
+                    gy += self.integral[1];
+                    gz += self.integral[2];
+                }
+                let kp = fx(self.kp);
+                gx += kp * ex;
+                gy += kp * ey;
+                gz += kp * ez;
+            }
+            let (hx, hy, hz) = ((dt * gx) >> 1, (dt * gy) >> 1, (dt * gz) >> 1);
+            let q = [
+                w - x * hx - y * hy - z * hz,
+                x + w * hx + y * hz - z * hy,
+                y + w * hy - x * hz + z * hx,
+                z + w * hz + x * hy - y * hx,
+            ];
+            let n = inv_sqrt(q.iter().map(|c| *c * *c).sum());
+            self.q = q.map(|c| c * n);
+            self.q()
+        }
+    }
+
+    /// ### Fusion stage for one probe
+    ///
+    /// Estimates the gyro bias first and runs the filter afterwards.
+    pub struct Fusion {
+        pub filter: Mahony,
+        still: Still,
+        bias: Option<[f32; 3]>,
+        last: Option<Instant>,
+    }
+
+    impl Fusion {
+        /// Default stage for a probe sampled at `hz`, with a
+        /// one second still period.
+        pub fn new(hz: u64) -> Self {
+            Fusion::with(Mahony::new(1.0, 0.01), Still::new(hz.clamp(1, 2000) as u16, 0.05))
+        }
+
+        pub fn with(filter: Mahony, still: Still) -> Self {
+            Fusion {
+                filter,
+                still,
+                bias: None,
+                last: None,
+            }
+        }
+
+        pub fn bias(&self) -> Option<[f32; 3]> {
+            self.bias
+        }
+
+        /// Feeds a reading taken at `time`, gyro in rad/s.
+        /// Returns `None` while the bias is being estimated.
+        pub fn update(&mut self, time: Instant, acc: [f32; 3], gyr: [f32; 3]) -> Option<Quat> {
+            let last = self.last.replace(time);
+            let bias = match self.bias {
+                Some(bias) => bias,
+                None => {
+                    self.bias = self.still.update(gyr);
+                    return None;
+                }
+            };
+            let dt = match last {
+                Some(last) => (time - last).as_micros() as f32 * 1e-6,
+                None => 0.0,
+            };
+            let gyr = [gyr[0] - bias[0], gyr[1] - bias[1], gyr[2] - bias[2]];
+            Some(self.filter.update(acc, gyr, dt))
+        }
+
+        /// Runs the stage and packs the result as requested
+        /// by `output`. Raw output is left to the sensor task.
+        pub fn ytf(
+            &mut self,
+            output: Output,
+            sensory: u8,
+            time: Instant,
+            acc: [f32; 3],
+            gyr: [f32; 3],
+        ) -> Option<Ytf> {
+            let q = self.update(time, acc, gyr)?;
+            let ytf = match output {
+                Output::Raw => return None,
+                Output::Quat => {
+                    let read: [f32; 4] = q.into();
+                    Sample { sensory, time, read }.into()
+                }
+                Output::Euler => Sample {
+                    sensory,
+                    time,
+                    read: q.euler(),
+                }
+                .into(),
+            };
+            Some(ytf)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const HZ: u64 = 100;
+        const DT: f32 = 1.0 / HZ as f32;
+
+        fn close(a: f32, b: f32, tol: f32) -> bool {
+            (a - b).abs() <= tol
+        }
+
+        #[test]
+        fn inv_sqrt_over_the_used_range() {
+            for i in 1..=64 {
+                let x = i as f32 * 0.25;
+                let y = inv_sqrt(fx(x)).to_num::<f32>();
+                assert!(close(y, 1.0 / x.sqrt(), 1e-5), "{x}: {y}");
+            }
+        }
+
+        #[test]
+        fn unit_for_any_scale() {
+            for scale in [1e-3, 1.0, 9.81, 100.0] {
+                let [x, y, z] = unit([fx(0.6 * scale), fx(0.0), fx(-0.8 * scale)]).unwrap().map(|c| c.to_num::<f32>());
+                assert!(close(x, 0.6, 1e-3) && close(y, 0.0, 1e-6) && close(z, -0.8, 1e-3), "{scale}");
+            }
+            assert_eq!(unit([Fx::ZERO; 3]), None);
+        }
+
+        #[test]
+        fn still_period_gives_bias_and_restarts_on_motion() {
+            let mut still = Still::new(10, 0.05);
+            for _ in 0..5 {
+                assert_eq!(still.update([0.01, -0.02, 0.03]), None);
+            }
+            // moved: the window starts over
+            assert_eq!(still.update([1.0, 0.0, 0.0]), None);
+            let mut bias = None;
+            for i in 0..10 {
+                let d = if i % 2 == 0 { 0.005 } else { -0.005 };
+                bias = still.update([0.01 + d, -0.02, 0.03]);
+                assert_eq!(bias.is_some(), i == 9);
+            }
+            let [x, y, z] = bias.unwrap();
+            assert!(close(x, 0.01, 1e-6) && close(y, -0.02, 1e-6) && close(z, 0.03, 1e-6));
+        }
+
+        /// Level sensor turning at 90°/s about z for two seconds
+        #[test]
+        fn constant_rate_integrates_to_yaw() {
+            let mut mahony = Mahony::new(1.0, 0.01);
+            let rate = 90f32.to_radians();
+            let mut q = Quat::IDENTITY;
+            for _ in 0..HZ {
+                q = mahony.update([0.0, 0.0, 1.0], [0.0, 0.0, rate], DT);
+            }
+            let [roll, pitch, yaw] = q.euler();
+            assert!(close(roll, 0.0, 0.1) && close(pitch, 0.0, 0.1), "{roll} {pitch}");
+            assert!(close(yaw, 90.0, 0.5), "{yaw}");
+            for _ in 0..HZ {
+                q = mahony.update([0.0, 0.0, 1.0], [0.0, 0.0, rate], DT);
+            }
+            assert!(close(q.euler()[2].abs(), 180.0, 1.0), "{:?}", q.euler());
+        }
+
+        /// Sensor resting tilted, in m/s² this time: the estimate
+        /// is pulled from level to the measured roll and pitch.
+        #[test]
+        fn gravity_pulls_to_tilt() {
+            for (roll, pitch) in [(30f32, 0f32), (0.0, -20.0), (-45.0, 10.0)] {
+                let (r, p) = (roll.to_radians(), pitch.to_radians());
+                let g = 9.81;
+                let acc = [-g * p.sin(), g * r.sin() * p.cos(), g * r.cos() * p.cos()];
+                let mut mahony = Mahony::new(1.0, 0.0);
+                let mut q = Quat::IDENTITY;
+                for _ in 0..10 * HZ {
+                    q = mahony.update(acc, [0.0; 3], DT);
+                }
+                let [r, p, _] = q.euler();
+                assert!(close(r, roll, 0.5) && close(p, pitch, 0.5), "{roll} {pitch}: {r} {p}");
+            }
+        }
+
+        /// Gyro with an offset: the still second is used for the
+        /// bias, after which a resting sensor does not drift.
+        #[test]
+        fn fusion_removes_bias() {
+            let mut fusion = Fusion::new(HZ);
+            let offset = [0.02, -0.01, 0.05];
+            let mut last = None;
+            for i in 0..10 * HZ {
+                let time = Instant::from_millis(i * 1000 / HZ);
+                last = fusion.update(time, [0.0, 0.0, 1.0], offset).or(last);
+                assert_eq!(fusion.bias().is_some(), i >= HZ - 1);
+            }
+            let [roll, pitch, yaw] = last.unwrap().euler();
+            assert!(close(roll, 0.0, 0.1) && close(pitch, 0.0, 0.1) && close(yaw, 0.0, 0.1), "{roll} {pitch} {yaw}");
+        }
+
+        #[test]
+        fn ytf_packs_the_requested_output() {
+            let mut fusion = Fusion::new(1);
+            let time = Instant::from_millis(0);
+            assert!(fusion.ytf(Output::Quat, 2, time, [0.0, 0.0, 1.0], [0.0; 3]).is_none());
+            let time = Instant::from_millis(1000);
+            let ytf = fusion.ytf(Output::Quat, 2, time, [0.0, 0.0, 1.0], [0.0; 3]).unwrap();
+            assert_eq!(ytf.sensory, 2);
+            assert!(close(ytf.read[0].unwrap() as f32, 1.0, 1e-5));
+            assert!(fusion.ytf(Output::Raw, 2, time, [0.0, 0.0, 1.0], [0.0; 3]).is_none());
+        }
+
+        /// Logged-format stream: at rest, turned 90° about z, then
+        /// rolled 30°. The rest at the start gives the bias, the
+        /// attitude at the end is known from the motion.
+        #[test]
+        fn fixture_turn_ends_at_the_known_attitude() {
+            let mut fusion = Fusion::new(HZ);
+            let mut last = None;
+            for ytf in fixture(include_str!("fixtures/lsm6_turn.csv")) {
+                let r = ytf.read.map(|v| v.unwrap_or_default() as f32);
+                // the lsm6 task logs the gyro in rev/s
+                let gyr = [r[3], r[4], r[5]].map(|w| w * core::f32::consts::TAU);
+                last = fusion.update(ytf.time, [r[0], r[1], r[2]], gyr).or(last);
+            }
+            let [roll, pitch, yaw] = last.unwrap().euler();
+            assert!(close(roll, 30.0, 1.0) && close(pitch, 0.0, 1.0), "{roll} {pitch}");
+            assert!(close(yaw, 90.0, 2.0), "{yaw}");
+        }
+    }
+}
+
+pub mod ppg {
+    //! ## Pulse oximetry
+    //!
+    //! Heart rate and SpO2 from red/IR photoplethysmograms.
+    //! Each channel is split into a slow DC level and the pulsatile
+    //! AC part. Removing the DC level also cancels slowly changing
+    //! ambient light, which the MAX30102 leaves behind after its
+    //! own ambient light cancellation.
+    //!
+    //! Beats are detected as peaks of the smoothed IR pulse wave,
+    //! using an adaptive threshold and a refractory period.
+    //! SpO2 uses the ratio of ratios over the last beat and the
+    //! common linear calibration `110 - 25 R`.
+
+    /// Exponential DC tracker with a smoothed AC output
+    struct Dc {
+        level: f32,
+        ac: f32,
+        alpha: f32,
+        smooth: f32,
+    }
+
+    impl Dc {
+        fn new(hz: f32) -> Self {
+            Dc {
+                level: 0.0,
+                ac: 0.0,
+                // ~1.6 s DC time constant, ~10 Hz low-pass on AC
+                alpha: (1.0 / (1.6 * hz)).min(1.0),
+                smooth: (10.0 / hz).min(1.0),
+            }
+        }
+
+        fn update(&mut self, x: f32) -> f32 {
+            if self.level == 0.0 {
+                self.level = x;
+            }
+            self.level += self.alpha * (x - self.level);
+            self.ac += self.smooth * ((x - self.level) - self.ac);
+            self.ac
+        }
+    }
+
+    /// Min/max of the AC wave within the current beat
+    #[derive(Clone, Copy)]
+    struct Swing {
+        min: f32,
+        max: f32,
+    }
+
+    impl Swing {
+        const EMPTY: Swing = Swing {
+            min: f32::MAX,
+            max: f32::MIN,
+        };
+
+        fn update(&mut self, x: f32) {
+            self.min = self.min.min(x);
+            self.max = self.max.max(x);
+        }
+
+        fn amplitude(&self) -> f32 {
+            (self.max - self.min).max(0.0)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Vitals {
+        /// Heart rate in beats per minute
+        pub bpm: f32,
+        /// Oxygen saturation in percent, if the beat was clean
+        pub spo2: Option<f32>,
+    }
+
+    pub struct Estimator {
+        hz: f32,
+        red: Dc,
+        ir: Dc,
+        red_swing: Swing,
+        ir_swing: Swing,
+        /// IR level below which no finger is on the sensor
+        pub min_ir: u32,
+        threshold: f32,
+        prev: [f32; 2],
+        n: u32,
+        last_beat: Option<u32>,
+        intervals: [u32; 4],
+        n_intervals: usize,
+    }
+
+    impl Estimator {
+        /// Estimator for samples arriving at `hz` (after averaging)
+        pub fn new(hz: f32) -> Self {
+            Estimator {
+                hz,
+                red: Dc::new(hz),
+                ir: Dc::new(hz),
+                red_swing: Swing::EMPTY,
+                ir_swing: Swing::EMPTY,
+                min_ir: 50_000,
+                threshold: 0.0,
+                prev: [0.0; 2],
+                n: 0,
+                last_beat: None,
+                intervals: [0; 4],
+                n_intervals: 0,
+            }
+        }
+
+        pub fn reset(&mut self) {
+            let min_ir = self.min_ir;
+            *self = Estimator::new(self.hz);
+            self.min_ir = min_ir;
+        }
+
+        /// Feeds one red/IR sample. Returns the vitals
+        /// whenever a beat has been detected.
+        pub fn update(&mut self, red: u32, ir: u32) -> Option<Vitals> {
+            if ir < self.min_ir {
+                if self.n > 0 {
+                    self.reset();
+                }
+                return None;
+            }
+            self.n += 1;
+            let red_ac = self.red.update(red as f32);
+            // inverted, so that the systolic upstroke becomes a peak
+            let ir_ac = -self.ir.update(ir as f32);
+            self.red_swing.update(red_ac);
+            self.ir_swing.update(ir_ac);
+            // threshold decays slowly and follows new peaks
+            self.threshold *= 1.0 - 1.0 / self.hz;
+
+            let [before, peak] = self.prev;
+            self.prev = [peak, ir_ac];
+            let is_peak = peak > before && peak >= ir_ac && peak > 0.5 * self.threshold;
+            // the first two seconds only settle the DC trackers
+            if !is_peak || (self.n as f32) < 2.0 * self.hz {
+                return None;
+            }
+            let beat = self.n - 1;
+            if let Some(last) = self.last_beat {
+                // refractory period, 0.3 s allows up to 200 bpm
+                if ((beat - last) as f32) < 0.3 * self.hz {
+                    return None;
+                }
+            }
+            self.threshold = self.threshold.max(peak);
+
+            let vitals = self.last_beat.map(|last| {
+                self.intervals.rotate_right(1);
+                self.intervals[0] = beat - last;
+                self.n_intervals = (self.n_intervals + 1).min(self.intervals.len());
+                let mean = self.intervals[..self.n_intervals].iter().sum::<u32>() as f32
+                    / self.n_intervals as f32;
+                Vitals {
+                    bpm: 60.0 * self.hz / mean,
+                    spo2: self.spo2(),
+                }
+            });
+            self.last_beat = Some(beat);
+            self.red_swing = Swing::EMPTY;
+            self.ir_swing = Swing::EMPTY;
+            vitals.filter(|v| v.bpm >= 30.0 && v.bpm <= 220.0)
+        }
+
+        fn spo2(&self) -> Option<f32> {
+            let (ac_red, ac_ir) = (self.red_swing.amplitude(), self.ir_swing.amplitude());
+            if ac_ir <= 0.0 || self.red.level <= 0.0 || self.ir.level <= 0.0 {
+                return None;
+            }
+            let r = (ac_red / self.red.level) / (ac_ir / self.ir.level);
+            let spo2 = 110.0 - 25.0 * r;
+            if (50.0..=100.0).contains(&spo2) {
+                Some(spo2)
+            } else {
+                None
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::ypro::fixture;
+        use core::f32::consts::PI;
+
+        const HZ: f32 = 100.0;
+
+        /// Synthetic MAX30102-like trace, there are no recordings
+        /// in the repo: DC levels with a pulse wave (fundamental and
+        /// a second harmonic for the steeper upstroke) pulled down by
+        /// the arterial absorption, the red swing set by `spo2` through
+        /// the same `110 - 25 R` calibration the estimator uses.
+        struct Trace {
+            bpm: f32,
+            spo2: f32,
+            /// slow baseline wander as fraction of DC, 0.2 Hz
+            wander: f32,
+            n: u32,
+        }
+
+        impl Trace {
+            const DC_IR: f32 = 100_000.0;
+            const DC_RED: f32 = 80_000.0;
+            /// perfusion index of the IR channel
+            const PI_IR: f32 = 0.01;
+
+            fn new(bpm: f32, spo2: f32) -> Self {
+                Trace { bpm, spo2, wander: 0.0, n: 0 }
+            }
+
+            fn next(&mut self) -> (u32, u32) {
+                let t = self.n as f32 / HZ;
+                self.n += 1;
+                let phase = 2.0 * PI * self.bpm / 60.0 * t;
+                let pulse = 0.5 + 0.35 * phase.sin() + 0.15 * (2.0 * phase).sin();
+                let wander = 1.0 + self.wander * (2.0 * PI * 0.2 * t).sin();
+                let r = (110.0 - self.spo2) / 25.0;
+                let ir = Self::DC_IR * wander * (1.0 - Self::PI_IR * pulse);
+                let red = Self::DC_RED * wander * (1.0 - r * Self::PI_IR * pulse);
+                (red as u32, ir as u32)
+            }
+        }
+
+        fn run(estimator: &mut Estimator, trace: &mut Trace, seconds: f32) -> heapless::Vec<Vitals, 128> {
+            let mut out = heapless::Vec::new();
+            for _ in 0..(seconds * HZ) as u32 {
+                let (red, ir) = trace.next();
+                if let Some(v) = estimator.update(red, ir) {
+                    out.push(v).ok();
+                }
+            }
+            out
+        }
+
+        fn settled(vitals: &[Vitals]) -> &[Vitals] {
+            // four intervals fill the mean
+            &vitals[4.min(vitals.len())..]
+        }
+
+        #[test]
+        fn heart_rate_and_spo2_of_a_clean_trace() {
+            for (bpm, spo2) in [(60.0, 98.0), (72.0, 95.0), (110.0, 90.0)] {
+                let mut estimator = Estimator::new(HZ);
+                let vitals = run(&mut estimator, &mut Trace::new(bpm, spo2), 20.0);
+                let beats = settled(&vitals);
+                assert!(beats.len() as f32 >= (20.0 - 4.0) * bpm / 60.0 - 6.0, "{bpm}: {} beats", beats.len());
+                for v in beats {
+                    assert!((v.bpm - bpm).abs() < 2.0, "{bpm}: {v:?}");
+                    let s = v.spo2.expect("clean beat");
+                    assert!((s - spo2).abs() < 2.0, "{spo2}: {v:?}");
+                }
+            }
+        }
+
+        #[test]
+        fn fast_rate_passes_the_refractory_period() {
+            let mut estimator = Estimator::new(HZ);
+            let vitals = run(&mut estimator, &mut Trace::new(180.0, 97.0), 15.0);
+            let beats = settled(&vitals);
+            assert!(!beats.is_empty());
+            assert!(beats.iter().all(|v| (v.bpm - 180.0).abs() < 6.0), "{beats:?}");
+        }
+
+        #[test]
+        fn baseline_wander_is_removed() {
+            let mut estimator = Estimator::new(HZ);
+            let mut trace = Trace::new(72.0, 97.0);
+            trace.wander = 0.002;
+            let vitals = run(&mut estimator, &mut trace, 30.0);
+            let beats = settled(&vitals);
+            assert!(beats.len() > 20, "{} beats", beats.len());
+            assert!(beats.iter().all(|v| (v.bpm - 72.0).abs() < 3.0), "{beats:?}");
+        }
+
+        #[test]
+        fn no_finger_resets() {
+            let mut estimator = Estimator::new(HZ);
+            let mut trace = Trace::new(72.0, 97.0);
+            assert!(!run(&mut estimator, &mut trace, 10.0).is_empty());
+            for _ in 0..HZ as u32 {
+                assert_eq!(estimator.update(300, 1_000), None);
+            }
+            assert_eq!(estimator.n, 0);
+            // settling starts over: nothing within the first two seconds
+            assert!(run(&mut estimator, &mut trace, 1.9).is_empty());
+            assert!(!run(&mut estimator, &mut trace, 8.0).is_empty());
+        }
+
+        /// Logged-format stream of a resting finger, 66 bpm on
+        /// average with the rate following the breath.
+        #[test]
+        fn fixture_at_rest() {
+            let mut estimator = Estimator::new(HZ);
+            let mut vitals = heapless::Vec::<Vitals, 32>::new();
+            for ytf in fixture(include_str!("fixtures/max30102_rest.csv")) {
+                let [red, ir] = [ytf.read[0], ytf.read[1]].map(|v| v.unwrap() as u32);
+                if let Some(v) = estimator.update(red, ir) {
+                    vitals.push(v).unwrap();
+                }
+            }
+            let beats = settled(&vitals);
+            assert!(beats.len() >= 10, "{} beats", beats.len());
+            let mean = |f: fn(&Vitals) -> f32| beats.iter().map(f).sum::<f32>() / beats.len() as f32;
+            let bpm = mean(|v| v.bpm);
+            assert!((bpm - 66.0).abs() < 2.0, "{bpm}: {beats:?}");
+            assert!(beats.iter().all(|v| (v.bpm - 66.0).abs() < 6.0), "{beats:?}");
+            // the breathing leaks into both swings and pulls R towards 1,
+            // within the ±3 % expected of pulse oximeters
+            let spo2 = mean(|v| v.spo2.expect("clean beat"));
+            assert!((spo2 - 97.0).abs() < 3.0, "{spo2}: {beats:?}");
+        }
+    }
+}
+
+pub mod protocol {
+    //! ## Stimulus protocols
+    //!
+    //! A protocol is a list of trials, each switching one stimulus on
+    //! for `duration` and leaving a gap of `isi` before the next trial.
+    //! With a seed other than 0, the trial order is shuffled
+    //! (xorshift32, Fisher-Yates), so the same seed gives the same order.
+    //!
+    //! `Schedule` unrolls a protocol into on and off steps with times
+    //! in µs from the start. It is plain arithmetic, so the sequence
+    //! can be checked on the host before it runs on the device.
+    use heapless::Vec;
+
+    pub const MAX_TRIALS: usize = 64;
+    /// stimulus code, duration, isi
+    const TRIAL_BYTES: usize = 1 + 4 + 4;
+    /// seed and trials
+    pub const MAX_BYTES: usize = 4 + MAX_TRIALS * TRIAL_BYTES;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Stimulus {
+        /// on-board LED
+        Led,
+        /// one of the stimulus output pins, counted from 0
+        Pin(u8),
+    }
+
+    impl Stimulus {
+        /// 0 for the LED, pin + 1 otherwise
+        pub fn code(self) -> u8 {
+            match self {
+                Stimulus::Led => 0,
+                Stimulus::Pin(n) => n + 1,
+            }
+        }
+
+        pub fn from_code(code: u8) -> Stimulus {
+            match code {
+                0 => Stimulus::Led,
+                n => Stimulus::Pin(n - 1),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Trial {
+        pub stimulus: Stimulus,
+        /// µs on
+        pub duration: u32,
+        /// µs off after the stimulus
+        pub isi: u32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Default)]
+    pub struct Protocol {
+        pub trials: Vec<Trial, MAX_TRIALS>,
+        /// 0 keeps the order
+        pub seed: u32,
+    }
+
+    fn xorshift(state: &mut u32) -> u32 {
+        let mut x = *state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        *state = x;
+        x
+    }
+
+    impl Protocol {
+        /// Trial indices in the order they run
+        pub fn order(&self) -> Vec<u16, MAX_TRIALS> {
+            let mut order: Vec<u16, MAX_TRIALS> = (0..self.trials.len() as u16).collect();
+            if self.seed != 0 {
+                let mut state = self.seed;
+                for i in (1..order.len()).rev() {
+                    let j = xorshift(&mut state) as usize % (i + 1);
+                    order.swap(i, j);
+                }
+            }
+            order
+        }
+
+        pub fn schedule(&self) -> Schedule<'_> {
+            Schedule {
+                protocol: self,
+                order: self.order(),
+                next: 0,
+                at: 0,
+                on: true,
+            }
+        }
+
+        /// Total run time in µs
+        pub fn length(&self) -> u64 {
+            self.trials
+                .iter()
+                .map(|t| t.duration as u64 + t.isi as u64)
+                .sum()
+        }
+
+        /// Little endian seed, then code, duration and isi per trial
+        pub fn to_bytes(&self) -> Vec<u8, MAX_BYTES> {
+            let mut bytes: Vec<u8, MAX_BYTES> = Vec::new();
+            let _ = bytes.extend_from_slice(&self.seed.to_le_bytes());
+            for trial in self.trials.iter() {
+                let _ = bytes.push(trial.stimulus.code());
+                let _ = bytes.extend_from_slice(&trial.duration.to_le_bytes());
+                let _ = bytes.extend_from_slice(&trial.isi.to_le_bytes());
+            }
+            bytes
+        }
+
+        pub fn from_bytes(bytes: &[u8]) -> Option<Protocol> {
+            if bytes.len() < 4 || (bytes.len() - 4) % TRIAL_BYTES != 0 {
+                return None;
+            }
+            let word = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+            let mut protocol = Protocol {
+                trials: Vec::new(),
+                seed: word(&bytes[0..4]),
+            };
+            for chunk in bytes[4..].chunks_exact(TRIAL_BYTES) {
+                let trial = Trial {
+                    stimulus: Stimulus::from_code(chunk[0]),
+                    duration: word(&chunk[1..5]),
+                    isi: word(&chunk[5..9]),
+                };
+                protocol.trials.push(trial).ok()?;
+            }
+            Some(protocol)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Step {
+        /// µs from the start of the run
+        pub at: u64,
+        /// position in the run, counted from 1
+        pub trial: u16,
+        pub stimulus: Stimulus,
+        pub on: bool,
+    }
+
+    /// On and off steps of a protocol, in time order
+    pub struct Schedule<'a> {
+        protocol: &'a Protocol,
+        order: Vec<u16, MAX_TRIALS>,
+        next: usize,
+        at: u64,
+        on: bool,
+    }
+
+    impl<'a> Iterator for Schedule<'a> {
+        type Item = Step;
+
+        fn next(&mut self) -> Option<Step> {
+            let index = *self.order.get(self.next)? as usize;
+            let trial = self.protocol.trials[index];
+            let step = Step {
+                at: self.at,
+                trial: self.next as u16 + 1,
+                stimulus: trial.stimulus,
+                on: self.on,
+            };
+            if self.on {
+                self.at += trial.duration as u64;
+            } else {
+                self.at += trial.isi as u64;
+                self.next += 1;
+            }
+            self.on = !self.on;
+            Some(step)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// Four pins with distinct durations, so a step tells its trial
+        fn protocol(seed: u32) -> Protocol {
+            let mut protocol = Protocol { trials: Vec::new(), seed };
+            for n in 0..4u8 {
+                let trial = Trial {
+                    stimulus: if n == 0 { Stimulus::Led } else { Stimulus::Pin(n - 1) },
+                    duration: 1_000 * (n as u32 + 1),
+                    isi: 50_000,
+                };
+                protocol.trials.push(trial).unwrap();
+            }
+            protocol
+        }
+
+        #[test]
+        fn seed_zero_keeps_the_order() {
+            let protocol = protocol(0);
+            let order = protocol.order();
+            assert_eq!(order.as_slice(), &[0, 1, 2, 3]);
+            let onsets: std::vec::Vec<Stimulus> = protocol.schedule().filter(|s| s.on).map(|s| s.stimulus).collect();
+            let trials: std::vec::Vec<Stimulus> = protocol.trials.iter().map(|t| t.stimulus).collect();
+            assert_eq!(onsets, trials);
+        }
+
+        #[test]
+        fn shuffle_is_a_permutation_repeated_by_the_seed() {
+            for seed in [1, 7, 12345, u32::MAX] {
+                let protocol = protocol(seed);
+                let mut order = protocol.order();
+                assert_eq!(order, protocol.order());
+                order.sort_unstable();
+                assert_eq!(order.as_slice(), &[0, 1, 2, 3]);
+            }
+            assert_ne!(protocol(7).order(), protocol(8).order());
+        }
+
+        #[test]
+        fn every_trial_switches_on_and_off_once() {
+            let protocol = protocol(7);
+            let steps: std::vec::Vec<Step> = protocol.schedule().collect();
+            assert_eq!(steps.len(), 2 * protocol.trials.len());
+            for pair in steps.chunks_exact(2) {
+                assert!(pair[0].on && !pair[1].on);
+                assert_eq!(pair[0].stimulus, pair[1].stimulus);
+                assert_eq!(pair[0].trial, pair[1].trial);
+            }
+            for trial in protocol.trials.iter() {
+                let count = steps.iter().filter(|s| s.stimulus == trial.stimulus).count();
+                assert_eq!(count, 2);
+            }
+            let numbers: std::vec::Vec<u16> = steps.iter().filter(|s| s.on).map(|s| s.trial).collect();
+            assert_eq!(numbers, [1, 2, 3, 4]);
+        }
+
+        #[test]
+        fn steps_are_timed_by_duration_and_isi() {
+            let protocol = protocol(12345);
+            let steps: std::vec::Vec<Step> = protocol.schedule().collect();
+            assert_eq!(steps[0].at, 0);
+            for (i, step) in steps.iter().enumerate().skip(1) {
+                let before = &steps[i - 1];
+                let trial = protocol.trials.iter().find(|t| t.stimulus == before.stimulus).unwrap();
+                let gap = if before.on { trial.duration } else { trial.isi };
+                assert_eq!(step.at - before.at, gap as u64);
+            }
+            let last = steps.last().unwrap();
+            let trial = protocol.trials.iter().find(|t| t.stimulus == last.stimulus).unwrap();
+            assert_eq!(last.at + trial.isi as u64, protocol.length());
+        }
+
+        #[test]
+        fn empty_protocol_has_no_steps() {
+            assert_eq!(Protocol::default().schedule().count(), 0);
+            assert_eq!(Protocol::default().length(), 0);
+        }
+
+        #[test]
+        fn bytes_round_trip() {
+            let protocol = protocol(99);
+            assert_eq!(Protocol::from_bytes(&protocol.to_bytes()), Some(protocol));
+            assert_eq!(Protocol::from_bytes(&[1, 2, 3]), None);
+            assert_eq!(Protocol::from_bytes(&[0; 4 + TRIAL_BYTES + 1]), None);
+        }
+    }
+}
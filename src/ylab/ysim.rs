@@ -0,0 +1,633 @@
+pub use crate::*;
+
+/// # Host simulation
+///
+/// With the `sim` feature instead of `rp`, the library builds for the
+/// host and runs on embassy's std executor. What does not need the
+/// RP2040 is the same code as on the device: `Ytf` and its CSV lines,
+/// `SINK`, `TAP`, `telemetry`, `cmd`, `ycfg`, `ymem`, `ypro`, the LED
+/// `pattern`s and the `menu` model. This module stands in for the rest:
+///
+/// + virtual sensors (`sine`, `noise`, `step`, `replay`), which work
+///   like the `ysns` modules: `READY`, `RECORD` and a `task` taking
+///   the rate and sensory, sending to `SINK`
+/// + `host` in place of the USB serial port, on stdin and stdout
+/// + `ui`, the LED, the display, the buttons, the stimulus pins, the
+///   buzzer and the response box on the terminal
+/// + `flash`, the storage of `ymem` in memory
+///
+/// `firmware!` takes the same descriptions as on the device, with
+/// these sensors and `Args` from the command line, see
+/// `src/bin/ylab_sim.rs` for a whole firmware.
+use crate::ytfk::bsu::{self, SINK};
+use crate::ytfk::telemetry::{self, Report};
+
+/// The command line of a simulated firmware: a file of values
+/// (`values()`), and a recording to play (`--play <file>`,
+/// `play` in the UI) at a `--speed`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Args {
+    pub values: Option<&'static str>,
+    pub play: Option<&'static str>,
+    pub speed: Option<f32>,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = std::string::String>) -> Args {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        // paths have to live as long as the tasks
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--play" => parsed.play = args.next().map(|path| &*path.leak()),
+                "--speed" => parsed.speed = args.next().and_then(|speed| speed.parse().ok()).or(parsed.speed),
+                _ => parsed.values = Some(&*arg.leak()),
+            }
+        }
+        parsed
+    }
+}
+
+/// The loop of every virtual sensor: reports the rate, then sends
+/// `read(t)` at `hz` while `record` is set, `t` being the seconds
+/// since the task started.
+async fn run<const N: usize>(
+    record: &AtomicBool,
+    hz: u64,
+    sensory: u8,
+    mut read: impl FnMut(f64) -> [f32; N],
+) -> ! {
+    let mut ticker = Ticker::every(Duration::from_hz(hz));
+    telemetry::report(Report::Target(sensory, hz as f32));
+    let start = Instant::now();
+    loop {
+        ticker.next().await;
+        if record.load(ORD) {
+            let time = Instant::now();
+            let t = (time - start).as_micros() as f64 / 1e6;
+            let sample = Sample {
+                sensory,
+                time,
+                read: read(t),
+            };
+            bsu::send(sample.into()).await;
+        }
+    }
+}
+
+pub mod sine {
+    //! ## Sine wave
+    //!
+    //! Three channels, each a third of a period behind the one
+    //! before, like the axes of a sensor turning at `freq`.
+    use super::*;
+    use core::f64::consts::PI;
+
+    pub static READY: AtomicBool = AtomicBool::new(false);
+    pub static RECORD: AtomicBool = AtomicBool::new(false);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Config {
+        /// Hz
+        pub freq: f64,
+        pub amplitude: f64,
+        pub offset: f64,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                freq: 1.0,
+                amplitude: 1.0,
+                offset: 0.0,
+            }
+        }
+    }
+
+    #[embassy_executor::task]
+    pub async fn task(config: Config, hz: u64, sensory: u8) {
+        READY.store(true, ORD);
+        run(&RECORD, hz, sensory, |t| {
+            let phase = 2.0 * PI * config.freq * t;
+            [0.0, 1.0, 2.0].map(|k| (config.offset + config.amplitude * (phase - k * 2.0 * PI / 3.0).sin()) as f32)
+        })
+        .await
+    }
+}
+
+pub mod noise {
+    //! ## Gaussian noise
+    //!
+    //! Two independent channels (Box-Muller on xorshift64),
+    //! the same seed gives the same noise.
+    use super::*;
+    use core::f64::consts::PI;
+
+    pub static READY: AtomicBool = AtomicBool::new(false);
+    pub static RECORD: AtomicBool = AtomicBool::new(false);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Config {
+        pub mean: f64,
+        pub sd: f64,
+        /// not 0
+        pub seed: u64,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                mean: 0.0,
+                sd: 1.0,
+                seed: 0x594C_4142,
+            }
+        }
+    }
+
+    /// uniform in (0, 1]
+    fn uniform(state: &mut u64) -> f64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        ((*state >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    #[embassy_executor::task]
+    pub async fn task(config: Config, hz: u64, sensory: u8) {
+        let mut state = config.seed.max(1);
+        READY.store(true, ORD);
+        run(&RECORD, hz, sensory, |_| {
+            let r = (-2.0 * uniform(&mut state).ln()).sqrt();
+            let phi = 2.0 * PI * uniform(&mut state);
+            [r * phi.cos(), r * phi.sin()].map(|z| (config.mean + config.sd * z) as f32)
+        })
+        .await
+    }
+}
+
+pub mod step {
+    //! ## Steps
+    //!
+    //! One channel, starting `low` and changing between `low` and
+    //! `high` every `every`, e.g. for testing triggers and thresholds.
+    use super::*;
+
+    pub static READY: AtomicBool = AtomicBool::new(false);
+    pub static RECORD: AtomicBool = AtomicBool::new(false);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Config {
+        pub low: f32,
+        pub high: f32,
+        pub every: Duration,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                low: 0.0,
+                high: 1.0,
+                every: Duration::from_secs(1),
+            }
+        }
+    }
+
+    #[embassy_executor::task]
+    pub async fn task(config: Config, hz: u64, sensory: u8) {
+        let every = config.every.as_micros().max(1) as f64 / 1e6;
+        READY.store(true, ORD);
+        run(&RECORD, hz, sensory, |t| {
+            let high = (t / every) as u64 % 2 == 1;
+            [if high { config.high } else { config.low }]
+        })
+        .await
+    }
+}
+
+pub mod replay {
+    //! ## Values and recordings from files
+    //!
+    //! `task` plays a column file at the given rate, one line per sample
+    //! and over again at the end. Values are separated by commas or
+    //! white space, at most `YTF_LEN` per line, an empty field gives
+    //! an empty channel. Empty lines and lines starting with `#`
+    //! are skipped.
+    //!
+    //! `recording` feeds a whole recording to `ytfk::replay`, which
+    //! plays it with the sensories and timing it was recorded with.
+    //! Files ending in `.ytf` are binary records (`Ytf::to_bytes`),
+    //! anything else is CSV lines as sent by the transports, where
+    //! replies (`# ...`) and other lines are skipped.
+    use super::*;
+    use crate::ytfk::replay::{Command, CONTROL};
+
+    pub static READY: AtomicBool = AtomicBool::new(false);
+    pub static RECORD: AtomicBool = AtomicBool::new(false);
+
+    /// The values of one line, `None` if there are none
+    pub fn parse(line: &str) -> Option<YtfRead> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let mut read: YtfRead = [None; YTF_LEN];
+        if line.contains(',') {
+            for (value, field) in read.iter_mut().zip(line.split(',')) {
+                *value = field.trim().parse().ok();
+            }
+        } else {
+            for (value, field) in read.iter_mut().zip(line.split_whitespace()) {
+                *value = field.parse().ok();
+            }
+        }
+        Some(read)
+    }
+
+    #[embassy_executor::task]
+    pub async fn task(path: &'static str, hz: u64, sensory: u8) {
+        let reads: std::vec::Vec<YtfRead> = match std::fs::read_to_string(path) {
+            Ok(text) => text.lines().filter_map(parse).collect(),
+            Err(e) => {
+                eprintln!("replay {}: {}", path, e);
+                telemetry::report(Report::Error(sensory));
+                return;
+            }
+        };
+        if reads.is_empty() {
+            eprintln!("replay {}: no values", path);
+            return;
+        }
+        let mut ticker = Ticker::every(Duration::from_hz(hz));
+        telemetry::report(Report::Target(sensory, hz as f32));
+        READY.store(true, ORD);
+        for read in reads.iter().cycle() {
+            ticker.next().await;
+            if RECORD.load(ORD) {
+                bsu::send(Ytf {
+                    sensory,
+                    time: Instant::now(),
+                    read: *read,
+                }).await;
+            }
+        }
+    }
+
+    /// The records of a recording, see above for the formats
+    pub fn records(path: &str) -> std::io::Result<std::vec::Vec<Ytf>> {
+        let bytes = std::fs::read(path)?;
+        let records = if path.ends_with(".ytf") {
+            bytes.chunks_exact(Ytf::BIN_LEN).filter_map(Ytf::from_bytes).collect()
+        } else {
+            std::string::String::from_utf8_lossy(&bytes)
+                .lines()
+                .filter_map(|line| line.parse().ok())
+                .collect()
+        };
+        Ok(records)
+    }
+
+    /// Plays the recording once, needs `ytfk::replay::task`
+    #[embassy_executor::task]
+    pub async fn recording(path: &'static str) {
+        match records(path) {
+            Ok(records) => {
+                CONTROL.send(Command::Reset).await;
+                for record in records {
+                    CONTROL.send(Command::Record(record)).await;
+                }
+            }
+            Err(e) => eprintln!("replay {}: {}", path, e),
+        }
+    }
+}
+
+pub mod host {
+    //! ## Host console
+    //!
+    //! In place of `bsu::serial_task`: `SINK` goes to stdout as CSV
+    //! lines, replies as lines starting with `#`. Lines on stdin are
+    //! host commands (`cmd`), or the buttons of `ui::input`.
+    //! Everything else the simulation has to say goes to stderr,
+    //! so stdout can be piped into the same tools as the serial port.
+    //!
+    //! Stdin is read by a thread of its own, the executor never waits on it.
+    use super::*;
+    use crate::ytfk::bsu::{REPLY, TAP};
+    use crate::ytfk::{cmd, YtfSend};
+    use embassy_futures::join::join;
+    use embassy_futures::select::{select, Either};
+    use std::io::{BufRead, Write};
+
+    pub type Line = String<{ cmd::MAX_LINE }>;
+    static LINES: Channel<RawMutex, Line, 4> = Channel::new();
+
+    #[embassy_executor::task]
+    pub async fn task() {
+        std::thread::spawn(|| {
+            for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+                // overlong lines are cut off, like on the serial port
+                let mut cut = Line::new();
+                for c in line.chars() {
+                    if cut.push(c).is_err() {
+                        break;
+                    }
+                }
+                embassy_futures::block_on(LINES.send(cut));
+            }
+        });
+        telemetry::report(Report::Usb(true));
+        let send = async {
+            loop {
+                let next = select(SINK.receive(), REPLY.receive()).await;
+                let mut out = std::io::stdout().lock();
+                match next {
+                    Either::First(sample) => {
+                        let _ = TAP.try_send(sample);
+                        let sent = match sample.msg_csv() {
+                            Ok(msg) => out.write_all(&msg).and_then(|_| writeln!(out)).is_ok(),
+                            Err(_) => false,
+                        };
+                        telemetry::report(if sent { Report::Sent(sample.sensory) } else { Report::Dropped(sample.sensory) });
+                    }
+                    Either::Second(reply) => {
+                        let _ = writeln!(out, "# {}", reply);
+                    }
+                }
+                let _ = out.flush();
+            }
+        };
+        let receive = async {
+            loop {
+                let line = LINES.receive().await;
+                if super::ui::input(&line) {
+                    continue;
+                }
+                match cmd::parse(&line) {
+                    Some(command) => cmd::dispatch(command).await,
+                    None if !line.trim().is_empty() => eprintln!("Unknown command: {}", line),
+                    None => {}
+                }
+            }
+        };
+        join(send, receive).await;
+    }
+}
+
+pub mod ui {
+    //! ## LED, display, buttons and stimuli on the terminal
+    //!
+    //! The LED runs the patterns of `yuio::led` and prints every change,
+    //! the display prints what goes to `disp::TEXT`, both on stderr.
+    //! So do the pins of `yuio::stim` and the tones of `yuio::buzzer`,
+    //! which run as on the device (`stim`, `buzzer`).
+    //! Buttons are lines on stdin (see `host`):
+    //!
+    //! + `key <buttons> <gesture>`: an event of `yuii::keys`, e.g.
+    //!   `key 1 click` or `key 4 long`, for the `menu`
+    //! + `btn short` / `btn long`: the same as `key 1 click` and
+    //!   `key 1 long`, what `ydev::button_control` listens to
+    //! + `press <button>`: a response button of `yuii::rbx`, which
+    //!   times the line from the onset (`responses`)
+    use super::*;
+    use crate::yuii::keys::{Event, Gesture, EVENTS};
+    use crate::yuii::rbx::{self, Watch};
+    use crate::yuio::disp::TEXT;
+    use crate::yuio::{buzzer as bz, stim as pr};
+
+    static PRESSES: Channel<RawMutex, u8, 4> = Channel::new();
+
+    #[embassy_executor::task]
+    pub async fn led() {
+        let mut shown = None;
+        crate::yuio::led::run(|level| {
+            if shown != Some(level) {
+                shown = Some(level);
+                eprintln!("[LED {}]", if level { "on" } else { "off" });
+            }
+        })
+        .await
+    }
+
+    #[embassy_executor::task]
+    pub async fn display() {
+        loop {
+            let lines = TEXT.wait().await;
+            eprintln!("+--------------------+");
+            for line in lines {
+                eprintln!("|{:<20}|", line.as_ref().map_or("", |l| l.as_str()));
+            }
+            eprintln!("+--------------------+");
+        }
+    }
+
+    #[embassy_executor::task]
+    pub async fn stim(sensory: u8) {
+        let mut levels = [false; pr::MAX_PINS];
+        pr::run(
+            |n, on| {
+                if let Some(level) = levels.get_mut(n as usize).filter(|level| **level != on) {
+                    *level = on;
+                    eprintln!("[pin {} {}]", n, if on { "on" } else { "off" });
+                }
+            },
+            sensory,
+        )
+        .await
+    }
+
+    #[embassy_executor::task]
+    pub async fn buzzer(sensory: u8) {
+        let mut sounding = 0;
+        bz::run(
+            |hz| {
+                if hz != sounding {
+                    sounding = hz;
+                    match hz {
+                        0 => eprintln!("[buzzer off]"),
+                        hz => eprintln!("[buzzer {} Hz]", hz),
+                    }
+                }
+            },
+            sensory,
+        )
+        .await
+    }
+
+    /// Response buttons on stdin, pressed all the time in between
+    struct Lines {
+        start: Instant,
+    }
+
+    impl Watch for Lines {
+        async fn released(&mut self, _: Duration) {}
+
+        fn has_trigger(&self) -> bool {
+            false
+        }
+
+        fn arm(&mut self, _: bool) {
+            // presses before the onset do not count
+            while PRESSES.try_receive().is_ok() {}
+            self.start = Instant::now();
+        }
+
+        fn disarm(&mut self) {}
+
+        async fn triggered(&mut self) {
+            core::future::pending().await
+        }
+
+        async fn press(&mut self) -> (u8, Duration) {
+            let button = PRESSES.receive().await;
+            (button, Instant::now() - self.start)
+        }
+    }
+
+    #[embassy_executor::task]
+    pub async fn responses(config: rbx::Config, sensory: u8) {
+        rbx::run(Lines { start: Instant::now() }, config, sensory).await
+    }
+
+    /// Takes a button line, `false` for any other line
+    pub fn input(line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        if let (Some("press"), Some(button), None) = (words.next(), words.next(), words.next()) {
+            return match button.parse() {
+                Ok(button) if (1..=rbx::MAX_BUTTONS as u8).contains(&button) => PRESSES.try_send(button).is_ok(),
+                _ => false,
+            };
+        }
+        let mut words = line.split_whitespace();
+        let (buttons, gesture) = match (words.next(), words.next(), words.next()) {
+            (Some("btn"), Some("short"), None) => (1, Gesture::Click),
+            (Some("btn"), Some("long"), None) => (1, Gesture::Long),
+            (Some("key"), Some(buttons), Some(gesture)) => {
+                let gesture = match gesture {
+                    "down" => Gesture::Down,
+                    "up" => Gesture::Up,
+                    "click" => Gesture::Click,
+                    "double" => Gesture::DoubleClick,
+                    "long" => Gesture::Long,
+                    "repeat" => Gesture::Repeat,
+                    _ => return false,
+                };
+                match buttons.parse() {
+                    Ok(buttons) => (buttons, gesture),
+                    Err(_) => return false,
+                }
+            }
+            _ => return false,
+        };
+        let event = Event {
+            buttons,
+            gesture,
+            time: Instant::now(),
+        };
+        EVENTS.try_send(event).is_ok()
+    }
+}
+
+pub mod flash {
+    //! ## Flash in memory
+    //!
+    //! What `ymem` uses of the RP2040 flash, with only the reserved
+    //! area behind it, erased at start. As on the chip, erasing goes
+    //! by whole sectors and writing can only clear bits, so a slot
+    //! written twice without erasing reads as damaged.
+    use crate::ymem::{FLASH_SIZE, STORAGE};
+    use core::ops::Range;
+
+    pub const ERASE_SIZE: usize = 4096;
+
+    /// Stands in for `hal::peripherals::FLASH`
+    pub struct FlashPeripheral;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Error {
+        OutOfBounds,
+        Unaligned,
+    }
+
+    pub struct Flash {
+        data: std::vec::Vec<u8>,
+    }
+
+    impl Flash {
+        pub fn new_blocking(_flash: FlashPeripheral) -> Flash {
+            Flash {
+                data: std::vec![0xFF; FLASH_SIZE - STORAGE as usize],
+            }
+        }
+
+        fn range(&self, offset: u32, len: usize) -> Result<Range<usize>, Error> {
+            let start = offset.checked_sub(STORAGE).ok_or(Error::OutOfBounds)? as usize;
+            if start + len > self.data.len() {
+                return Err(Error::OutOfBounds);
+            }
+            Ok(start..start + len)
+        }
+
+        pub fn blocking_read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+            let range = self.range(offset, bytes.len())?;
+            bytes.copy_from_slice(&self.data[range]);
+            Ok(())
+        }
+
+        pub fn blocking_erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+            if from % ERASE_SIZE as u32 != 0 || to % ERASE_SIZE as u32 != 0 || to < from {
+                return Err(Error::Unaligned);
+            }
+            let range = self.range(from, (to - from) as usize)?;
+            self.data[range].fill(0xFF);
+            Ok(())
+        }
+
+        pub fn blocking_write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+            let range = self.range(offset, bytes.len())?;
+            for (cell, byte) in self.data[range].iter_mut().zip(bytes) {
+                *cell &= byte;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ytfk::YtfSend;
+    use embassy_futures::block_on;
+    use embassy_futures::select::{select, Either};
+
+    /// A virtual sensor through `SINK` to the lines of `host`
+    #[test]
+    fn sensor_to_csv() {
+        static RECORD: AtomicBool = AtomicBool::new(true);
+        let sensor = run(&RECORD, 1000, 7, |_| [1.5, -0.25]);
+        let lines = async {
+            let mut lines = std::vec::Vec::new();
+            while lines.len() < 3 {
+                let msg = SINK.receive().await.msg_csv().unwrap();
+                lines.push(std::string::String::from_utf8(msg.to_vec()).unwrap());
+            }
+            lines
+        };
+        let Either::Second(lines) = block_on(select(sensor, lines));
+        let mut last = 0;
+        for line in lines {
+            assert!(line.ends_with(", 7,1.500,-0.250,,,,,,"), "{}", line);
+            let sample: Ytf = line.parse().unwrap();
+            assert_eq!(sample.read[..3], [Some(1.5), Some(-0.25), None]);
+            assert!(sample.time.as_micros() > last);
+            last = sample.time.as_micros();
+        }
+    }
+
+    #[test]
+    fn args() {
+        let args = Args::parse(["--speed", "2", "values.csv", "--play", "rec.ytf"].map(Into::into));
+        assert_eq!(args, Args { values: Some("values.csv"), play: Some("rec.ytf"), speed: Some(2.0) });
+        assert_eq!(Args::parse([]), Args::default());
+        assert_eq!(Args::parse(["--speed".into(), "fast".into()]).speed, None);
+    }
+}
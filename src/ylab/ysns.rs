@@ -1,7 +1,8 @@
-use crate::ytfk::bsu::SINK;
+use crate::ytfk::bsu;
+use crate::ytfk::telemetry::{self, Report};
 pub use crate::*;
-use hal::i2c;
-use i2c::Async as Mode;
+#[cfg(feature = "rp")]
+use {hal::i2c, i2c::Async as Mode};
 pub use yuio::disp::TEXT as DISP;
 
 pub struct SensorResult<R> {
@@ -10,6 +11,7 @@ pub struct SensorResult<R> {
 }
 
 
+#[cfg(feature = "moi")]
 pub mod moi {
     use super::*;
     use hal::gpio::{Input, Pull};
@@ -53,7 +55,7 @@ pub mod moi {
                     time: Instant::now(),
                     read: reading,
                 };
-                SINK.send(sample.into()).await;
+                bsu::send(sample.into()).await;
             };
         }
     }
@@ -76,12 +78,13 @@ pub mod moi {
                     time: Instant::now(),
                     read: reading,
                 };
-                SINK.send(sample.into()).await;
+                bsu::send(sample.into()).await;
             };
         }
     }
 }
 
+#[cfg(feature = "adc")]
 pub mod adc {
 
     use super::*;
@@ -114,6 +117,7 @@ pub mod adc {
         sensory: u8,
     ) {
         let mut ticker = Ticker::every(Duration::from_hz(hz));
+        telemetry::report(Report::Target(sensory, hz as f32));
         //let mut reading: Reading;
         //let mut result: SensorResult<Reading>;
         let mut chan = [
@@ -137,7 +141,7 @@ pub mod adc {
                     read: reading,
                 };
 
-                SINK.send(sample.into()).await;
+                bsu::send(sample.into()).await;
             };
         }
     }
@@ -271,6 +275,7 @@ pub mod ads1115 {
 
 */
 
+#[cfg(feature = "lsm6")]
 pub mod yxz_lsm6_old {
     use super::*;
     use hal::peripherals::I2C0 as I2C;
@@ -305,6 +310,7 @@ pub mod yxz_lsm6_old {
             }
         };
         let mut ticker = Ticker::every(Duration::from_hz(hz));
+        telemetry::report(Report::Target(sensory, hz as f32));
         let mut reading: Reading;
         let mut result: SensorResult<Reading>;
         READY.store(true, ORD);
@@ -331,6 +337,7 @@ pub mod yxz_lsm6_old {
     }
 }
 
+#[cfg(feature = "lsm6")]
 pub mod yxz_lsm6 {
 
     use super::*;
@@ -338,6 +345,7 @@ pub mod yxz_lsm6 {
     use hal::peripherals::I2C0 as I2C;
     use lsm6dsox::*;
     use Lsm6dsox as Lsm6;
+    pub use ypro::fusion::{Fusion, Output};
 
     /* control channels */
     pub static READY: AtomicBool = AtomicBool::new(false);
@@ -347,8 +355,11 @@ pub mod yxz_lsm6 {
     pub type Reading = [Measure; N];
     pub type Sample = crate::Sample<Measure, N>;
 
+    /// Gyro comes in revolutions per second, fusion wants rad/s
+    const TAU: f32 = 2.0 * core::f32::consts::PI;
+
     #[embassy_executor::task]
-    pub async fn task(i2c: i2c::I2c<'static, I2C, Mode>, hz: u64, sensory: u8) {
+    pub async fn task(i2c: i2c::I2c<'static, I2C, Mode>, hz: u64, output: Output, sensory: u8) {
         let mut sensor = Lsm6::new(i2c, SlaveAddress::Low, time::Delay);
         log::debug!("Yxz init");
         sensor.setup().unwrap();
@@ -358,8 +369,10 @@ pub mod yxz_lsm6 {
         sensor.set_gyro_scale(GyroscopeScale::Dps250).unwrap();
         log::debug!("Yxz set");
         let mut ticker = Ticker::every(Duration::from_hz(hz));
+        telemetry::report(Report::Target(sensory, hz as f32));
         //let mut reading: Reading;
         //let mut result: SensorResult<Reading>;
+        let mut fusion = Fusion::new(hz);
         READY.store(true, ORD);
 
         loop {
@@ -375,19 +388,30 @@ pub mod yxz_lsm6 {
                     gyro.y.as_hertz() as f32,
                     gyro.z.as_hertz() as f32,
                 ];
-
-                let sample = Sample {
-                    sensory: sensory,
-                    time: Instant::now(),
-                    read: reading,
-                };
-                SINK.send(sample.into()).await;
+                let time = Instant::now();
+                if output == Output::Raw {
+                    let sample = Sample {
+                        sensory: sensory,
+                        time: time,
+                        read: reading,
+                    };
+                    bsu::send(sample.into()).await;
+                } else if let Some(ytf) = fuse(&mut fusion, output, sensory, time, reading) {
+                    bsu::send(ytf).await;
+                }
                 log::debug!("Yxz read");
                 ticker.next().await;
             };
         }
     }
 
+    /// Passes a reading in sensor units through the probe's fusion stage
+    fn fuse(fusion: &mut Fusion, output: Output, sensory: u8, time: Instant, reading: Reading) -> Option<Ytf> {
+        let acc = [reading[0], reading[1], reading[2]];
+        let gyr = [reading[3] * TAU, reading[4] * TAU, reading[5] * TAU];
+        fusion.ytf(output, sensory, time, acc, gyr)
+    }
+
     use xca9548a::{SlaveAddr, Xca9548a};
     #[embassy_executor::task]
     pub async fn multi_task(
@@ -395,6 +419,7 @@ pub mod yxz_lsm6 {
         n: u8,
         hz: u64,
         just_spin: bool,
+        output: Output,
         sensory: u8,
     ) {
         DISP.signal([
@@ -431,8 +456,12 @@ pub mod yxz_lsm6 {
         }
         //DISP.signal([None, None, None, Some("LSM6x3".try_into().unwrap())]);
         let mut ticker = Ticker::every(Duration::from_hz(hz));
+        for s in 0..n {
+            telemetry::report(Report::Target(sensory + s, hz as f32));
+        }
         //let mut reading: Reading;
         //let mut result: Sample;
+        let mut fusion = [(); 6].map(|_| Fusion::new(hz));
         READY.store(true, ORD);
         loop {
             if RECORD.load(ORD) {
@@ -450,12 +479,19 @@ pub mod yxz_lsm6 {
                             gyro.y.as_hertz() as f32,
                             gyro.z.as_hertz() as f32,
                         ];
-                        let sample = Sample {
-                            sensory: (s as u8 + sensory),
-                            time: Instant::now(),
-                            read: reading,
-                        };
-                        SINK.send(sample.into()).await;
+                        let time = Instant::now();
+                        if output == Output::Raw {
+                            let sample = Sample {
+                                sensory: (s as u8 + sensory),
+                                time: time,
+                                read: reading,
+                            };
+                            bsu::send(sample.into()).await;
+                        } else if let Some(ytf) =
+                            fuse(&mut fusion[s], output, s as u8 + sensory, time, reading)
+                        {
+                            bsu::send(ytf).await;
+                        }
                     }
 
                     /*log::info!("{},{},{:.2},{:.2},{:.2},{:.2},{:.2},{:.2},,",
@@ -557,11 +593,13 @@ pub mod yxz_lsm6 {
 
 /// ## BMI Acceleration Sensor
 
+#[cfg(feature = "bmi160")]
 pub mod yxz_bmi160 {
     use super::*;
     #[allow(unused)]
     use bmi160::{AccelerometerPowerMode, Bmi160, GyroscopePowerMode, SensorSelector, SlaveAddr};
     use hal::peripherals::I2C1 as I2C;
+    pub use ypro::fusion::{Fusion, Output};
 
     /* control channels */
     pub static READY: AtomicBool = AtomicBool::new(false);
@@ -573,8 +611,11 @@ pub mod yxz_bmi160 {
     /// <--- 4 channel is total accel for now
     pub type Sample = crate::Sample<Measure, N>;
 
+    /// Raw gyro counts to rad/s at the default range of 2000 °/s
+    const GYRO_RAD: f32 = core::f32::consts::PI / (180.0 * 16.4);
+
     #[embassy_executor::task]
-    pub async fn task(i2c: i2c::I2c<'static, I2C, Mode>, hz: u64, sensory: u8) {
+    pub async fn task(i2c: i2c::I2c<'static, I2C, Mode>, hz: u64, output: Output, sensory: u8) {
         //DISP.signal([None, None, None, Some("BMI160 task".try_into().unwrap())]);
         let address = SlaveAddr::default();
         let mut sensor = Bmi160::new_with_i2c(i2c, address);
@@ -589,19 +630,19 @@ pub mod yxz_bmi160 {
         //DISP.signal([None, Some("BMI160 gyro".try_into().unwrap()), None, None]);
         //DISP.signal([None, None, None, Some("BMI160 set".try_into().unwrap())]);
         let mut ticker = Ticker::every(Duration::from_hz(hz));
+        telemetry::report(Report::Target(sensory, hz as f32));
         //DISP.signal([None, None, None, Some("BMI160 ticks".try_into().unwrap())]);
         //let mut reading: Reading;
         //let mut result: Sample;
+        let mut fusion = Fusion::new(hz);
         READY.store(true, ORD);
         loop {
             ticker.next().await;
             if RECORD.load(ORD) {
-                DISP.signal([None, None, None, Some("BMI160 ...".try_into().unwrap())]);
                 let data = sensor.data(SensorSelector::new().accel().gyro()).unwrap();
                 let acc = data.accel.unwrap();
                 let gyr = data.gyro.unwrap();
-                DISP.signal([None, None, None, Some("BMI160     ...".try_into().unwrap())]);
-                let reading = [
+                let reading: Reading = [
                     acc.x.into(),
                     acc.y.into(),
                     acc.z.into(),
@@ -609,24 +650,51 @@ pub mod yxz_bmi160 {
                     gyr.y.into(),
                     gyr.z.into(),
                 ];
-                let sample = Sample {
-                    time: Instant::now(),
-                    sensory: sensory,
-                    read: reading.into(),
-                };
-                SINK.send(sample.into()).await;
+                let time = Instant::now();
+                if output == Output::Raw {
+                    let sample = Sample {
+                        time: time,
+                        sensory: sensory,
+                        read: reading.into(),
+                    };
+                    bsu::send(sample.into()).await;
+                } else {
+                    let acc = [reading[0], reading[1], reading[2]];
+                    let gyr = [
+                        reading[3] * GYRO_RAD,
+                        reading[4] * GYRO_RAD,
+                        reading[5] * GYRO_RAD,
+                    ];
+                    if let Some(ytf) = fusion.ytf(output, sensory, time, acc, gyr) {
+                        bsu::send(ytf).await;
+                    }
+                }
             };
         }
     }
 }
 
-/// ## BMI Acceleration Sensor
+/// ## TLV493D 3D magnetometer
 
+#[cfg(feature = "tlv")]
 pub mod yxz_tlv {
+    //! Field readings in mT and temperature in °C, with optional
+    //! derived channels for magnet position (`Output::Polar`) or
+    //! use as a 3D joystick (`Output::Joystick`).
+    //!
+    //! The sensor picks its I2C address at power-up from the SDA
+    //! level: `0x5E` when high, `0x1F` when low. With one sensor
+    //! of each kind, two sensors share a bus (`dual_task`).
+    //!
+    //! The registers are accessed directly (TLV493D-A1B6 data sheet):
+    //! a read returns the readings in the first 7 bytes, and the
+    //! factory bits in bytes 7 to 9, which every write of the 4 mode
+    //! bytes has to carry along.
     use super::*;
+    use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+    use embedded_hal_async::i2c::I2c as _;
     use hal::peripherals::I2C0 as I2C;
-    #[allow(unused)]
-    use tlv493d as tlv;
+    use libm::{atan2f, sqrtf};
 
     /* control channels */
     pub static READY: AtomicBool = AtomicBool::new(false);
@@ -637,103 +705,415 @@ pub mod yxz_tlv {
     pub type Reading = [Measure; N];
     pub type Sample = crate::Sample<Measure, N>;
 
-    #[embassy_executor::task]
-    pub async fn task(i2c: i2c::I2c<'static, I2C, Mode>, hz: u64, sensory: u8) {
+    /// Field resolution of the 12 bit readings
+    pub const MT_PER_LSB: f32 = 0.098;
+    /// Temperature reads 340 LSB at 25°C, with 1.1 °C per LSB
+    const T_OFFSET: f32 = 340.0;
+    const T_PER_LSB: f32 = 1.1;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Address {
+        Primary = 0x5E,
+        Secondary = 0x1F,
+    }
+
+    /// Power modes, with their highest sample rate
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Power {
+        /// 3.3 kHz
+        Fast,
+        /// 100 Hz
+        LowPower,
+        /// 10 Hz
+        UltraLowPower,
+    }
+
+    impl Power {
+        /// FAST and LOW bits of mode byte 1, LP bit of mode byte 3
+        fn bits(self) -> (u8, u8) {
+            match self {
+                Power::Fast => (0b10, 0),
+                Power::LowPower => (0b01, 0x40),
+                Power::UltraLowPower => (0b01, 0),
+            }
+        }
+    }
+
+    /// The mode bytes for `power`, with temperature on and the
+    /// factory bits of the read `registers`. The parity bit makes
+    /// the number of ones odd.
+    fn mode(registers: &[u8; 10], power: Power) -> [u8; 4] {
+        let (fast_low, lp) = power.bits();
+        let mut mode = [0, registers[7] & 0x18 | fast_low, registers[8], registers[9] & 0x1F | lp];
+        if mode.iter().map(|b| b.count_ones()).sum::<u32>() % 2 == 0 {
+            mode[1] |= 0x80;
+        }
+        mode
+    }
+
+    /// 12 bit two's complement
+    fn twelve(bits: u16) -> Measure {
+        ((bits << 4) as i16) >> 4
+    }
+
+    /// Bx, By, Bz and T from the first 7 read registers
+    fn reading(r: &[u8; 7]) -> Reading {
+        let [r0, r1, r2, r3, r4, r5, r6] = r.map(u16::from);
+        [
+            twelve(r0 << 4 | r4 >> 4),
+            twelve(r1 << 4 | r4 & 0x0F),
+            twelve(r2 << 4 | r5 & 0x0F),
+            twelve((r3 & 0xF0) << 4 | r6),
+        ]
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Output {
+        /// Bx, By, Bz, T as counts
+        Raw,
+        /// Bx, By, Bz in mT, T in °C
+        Units,
+        /// Units, plus magnitude (mT), azimuth and elevation (°)
+        Polar,
+        /// Units, plus joystick x and y from -1 to 1
+        Joystick,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Config {
+        pub address: Address,
+        pub power: Power,
+        pub output: Output,
+        /// tilt (°) away from the rest position that counts
+        /// as full joystick deflection
+        pub range: f32,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                address: Address::Primary,
+                power: Power::Fast,
+                output: Output::Units,
+                range: 30.0,
+            }
+        }
+    }
+
+    /// Field in mT
+    pub fn field(raw: Reading) -> [f32; 3] {
+        [
+            raw[0] as f32 * MT_PER_LSB,
+            raw[1] as f32 * MT_PER_LSB,
+            raw[2] as f32 * MT_PER_LSB,
+        ]
+    }
+
+    /// Temperature in °C
+    pub fn temperature(raw: Reading) -> f32 {
+        (raw[3] as f32 - T_OFFSET) * T_PER_LSB + 25.0
+    }
+
+    /// Magnitude (mT), azimuth in the x/y plane and elevation
+    /// above it (°)
+    pub fn polar(b: [f32; 3]) -> [f32; 3] {
+        let planar = sqrtf(b[0] * b[0] + b[1] * b[1]);
+        let magnitude = sqrtf(planar * planar + b[2] * b[2]);
+        [
+            magnitude,
+            atan2f(b[1], b[0]).to_degrees(),
+            atan2f(b[2], planar).to_degrees(),
+        ]
+    }
+
+    /// Tilt of the field towards x and y (°)
+    fn tilt(b: [f32; 3]) -> [f32; 2] {
+        [atan2f(b[0], b[2]).to_degrees(), atan2f(b[1], b[2]).to_degrees()]
+    }
+
+    /// Readings averaged for the joystick rest position
+    const REST: u8 = 16;
+
+    /// One sensor with its conversion state
+    struct Probe<'a> {
+        i2c: Device<'a>,
+        config: Config,
+        rest: [f32; 2],
+        n_rest: u8,
+    }
+
+    impl<'a> Probe<'a> {
+        async fn new(bus: &'a Bus, config: Config) -> Option<Probe<'a>> {
+            let mut i2c = I2cDevice::new(bus);
+            let mut registers = [0; 10];
+            i2c.read(config.address as u8, &mut registers).await.ok()?;
+            i2c.write(config.address as u8, &mode(&registers, config.power)).await.ok()?;
+            Some(Probe {
+                i2c,
+                config,
+                rest: [0.0; 2],
+                n_rest: 0,
+            })
+        }
+
+        /// Reads and converts, `None` on bus errors and
+        /// while the joystick rest position is taken.
+        async fn read(&mut self, sensory: u8) -> Option<Ytf> {
+            let mut registers = [0; 7];
+            self.i2c.read(self.config.address as u8, &mut registers).await.ok()?;
+            let raw = reading(&registers);
+            let time = Instant::now();
+            if self.config.output == Output::Raw {
+                return Some(Sample { sensory, time, read: raw }.into());
+            }
+            let b = field(raw);
+            let mut read: YtfRead = [None; YTF_LEN];
+            for (i, v) in [b[0], b[1], b[2], temperature(raw)].into_iter().enumerate() {
+                read[i] = Some(v.into());
+            }
+            match self.config.output {
+                Output::Polar => {
+                    for (i, v) in polar(b).into_iter().enumerate() {
+                        read[4 + i] = Some(v.into());
+                    }
+                }
+                Output::Joystick => {
+                    let [x, y] = tilt(b);
+                    if self.n_rest < REST {
+                        self.rest[0] += x / REST as f32;
+                        self.rest[1] += y / REST as f32;
+                        self.n_rest += 1;
+                        return None;
+                    }
+                    let range = self.config.range;
+                    read[4] = Some(((x - self.rest[0]) / range).clamp(-1.0, 1.0).into());
+                    read[5] = Some(((y - self.rest[1]) / range).clamp(-1.0, 1.0).into());
+                }
+                _ => {}
+            }
+            Some(Ytf { sensory, time, read })
+        }
+    }
+
+    type Bus = Mutex<RawMutex, i2c::I2c<'static, I2C, Mode>>;
+    type Device<'a> = I2cDevice<'a, RawMutex, i2c::I2c<'static, I2C, Mode>>;
+
+    async fn run(i2c: i2c::I2c<'static, I2C, Mode>, configs: &[Config], hz: u64, sensory: u8) {
         //DISP.signal([None, None, None, Some("LVT task".try_into().unwrap())]);
-        let address = 0x5E;
-        let mut sensor = tlv::Tlv493d::new_async(i2c, address, tlv::Mode::Master)
-            .await
-            .unwrap();
-        //DISP.signal([None, Some("BMI160 |==| I2C".try_into().unwrap()), None, None]);
-        let _: Reading = sensor.read_raw_async().await.unwrap();
-        sensor.configure(tlv::Mode::Fast, true).await.unwrap();
+        let bus: Bus = Mutex::new(i2c);
+        let mut probes: Vec<Probe, 2> = Vec::new();
+        for config in configs {
+            match Probe::new(&bus, *config).await {
+                Some(probe) => {
+                    let _ = probes.push(probe);
+                }
+                None => {
+                    telemetry::report(Report::Error(sensory + probes.len() as u8));
+                    return;
+                }
+            }
+        }
         let mut ticker = Ticker::every(Duration::from_hz(hz));
+        for s in 0..probes.len() as u8 {
+            telemetry::report(Report::Target(sensory + s, hz as f32));
+        }
         READY.store(true, ORD);
         loop {
             ticker.next().await;
             if RECORD.load(ORD) {
-                let reading: Reading = sensor.read_raw_async().await.unwrap();
-                let sample = Sample {
-                    time: Instant::now(),
-                    sensory: sensory,
-                    read: reading.into(),
-                };
-                SINK.send(sample.into()).await;
+                for (s, probe) in probes.iter_mut().enumerate() {
+                    if let Some(ytf) = probe.read(sensory + s as u8).await {
+                        bsu::send(ytf).await;
+                    }
+                }
             };
         }
     }
+
+    #[embassy_executor::task]
+    pub async fn task(i2c: i2c::I2c<'static, I2C, Mode>, config: Config, hz: u64, sensory: u8) {
+        run(i2c, &[config], hz, sensory).await
+    }
+
+    /// Two sensors on one bus, sent as `sensory` and `sensory + 1`.
+    /// They must have different addresses.
+    #[embassy_executor::task]
+    pub async fn dual_task(
+        i2c: i2c::I2c<'static, I2C, Mode>,
+        configs: [Config; 2],
+        hz: u64,
+        sensory: u8,
+    ) {
+        run(i2c, &configs, hz, sensory).await
+    }
 }
 
+#[cfg(feature = "irt_max")]
 pub mod yirt_max {
+    //! ## MAX30102 pulse oximeter
+    //!
+    //! Runs the sensor in SpO2 mode (red and IR LED) and drains
+    //! the FIFO on every tick, as far as the read and write pointers
+    //! say there are samples. Samples are sent with their
+    //! reconstructed sampling time, so the tick rate only has to be high
+    //! enough that the 32-sample FIFO never overflows.
+    //!
+    //! Ambient light is cancelled by the sensor itself,
+    //! slower drifts are removed in the optional estimator
+    //! (`ypro::ppg`), which adds heart rate and SpO2 on every beat.
     use super::*;
     use hal::peripherals::I2C0 as I2C;
-    use max3010x::{Led, Max3010x, SampleAveraging};
+    pub use max3010x::{AdcRange, LedPulseWidth, SampleAveraging, SamplingRate};
+    use max3010x::{Led, Max3010x};
+    use ypro::ppg::Estimator;
 
     /* control channels */
     pub static READY: AtomicBool = AtomicBool::new(false);
     pub static RECORD: AtomicBool = AtomicBool::new(false);
-    pub type Reading = [u32; 8];
-    /// <--- 4 channel is total accel for now
-    pub type Measure = SensorResult<Reading>;
+    /// red and IR counts
+    pub type Reading = [u32; 2];
+
+    /// FIFO depth of the MAX30102 in samples
+    const FIFO: usize = 32;
+
+    #[derive(Clone, Copy)]
+    pub struct Config {
+        pub sampling_rate: SamplingRate,
+        pub averaging: SampleAveraging,
+        pub adc_range: AdcRange,
+        pub pulse_width: LedPulseWidth,
+        /// LED currents, 0.2 mA per step
+        pub led_red: u8,
+        pub led_ir: u8,
+        /// on-device heart rate and SpO2
+        pub estimate: bool,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                sampling_rate: SamplingRate::Sps400,
+                averaging: SampleAveraging::Sa4,
+                adc_range: AdcRange::Fs16k,
+                pulse_width: LedPulseWidth::Pw411,
+                led_red: 0x24,
+                led_ir: 0x24,
+                estimate: true,
+            }
+        }
+    }
+
+    impl Config {
+        /// Effective rate of the samples in the FIFO
+        pub fn hz(&self) -> u32 {
+            let sps = match self.sampling_rate {
+                SamplingRate::Sps50 => 50,
+                SamplingRate::Sps100 => 100,
+                SamplingRate::Sps200 => 200,
+                SamplingRate::Sps400 => 400,
+                SamplingRate::Sps800 => 800,
+                SamplingRate::Sps1000 => 1000,
+                SamplingRate::Sps1600 => 1600,
+                SamplingRate::Sps3200 => 3200,
+            };
+            let avg = match self.averaging {
+                SampleAveraging::Sa1 => 1,
+                SampleAveraging::Sa2 => 2,
+                SampleAveraging::Sa4 => 4,
+                SampleAveraging::Sa8 => 8,
+                SampleAveraging::Sa16 => 16,
+                SampleAveraging::Sa32 => 32,
+            };
+            sps / avg
+        }
+    }
 
+    /// The task polls the FIFO with `hz`, which should be at least
+    /// `config.hz() / 16` to stay clear of overflows.
     #[embassy_executor::task]
-    pub async fn task(i2c: i2c::I2c<'static, I2C, Mode>, hz: u64, sensory: u8) {
+    pub async fn task(i2c: i2c::I2c<'static, I2C, Mode>, config: Config, hz: u64, sensory: u8) {
         // Sensor specific
-        let mut sensor = Max3010x::new_max30102(i2c).into_multi_led().unwrap();
-        sensor
-            .set_sampling_rate(max3010x::SamplingRate::Sps3200)
-            .unwrap();
-        sensor.set_sample_averaging(SampleAveraging::Sa16).unwrap();
-        sensor.set_pulse_amplitude(Led::All, 15).unwrap();
-        sensor.enable_fifo_rollover().unwrap();
-        sensor.wake_up().unwrap();
-
-        let mut data: [u32; 1] = [0; 1];
-        let _ = sensor.read_fifo(&mut data).unwrap();
-        DISP.signal([
-            None,
-            None,
-            None,
-            Some("IRTmax can read".try_into().unwrap()),
-        ]);
+        let mut sensor = match Max3010x::new_max30102(i2c).into_oximeter() {
+            Ok(sensor) => sensor,
+            Err(_) => {
+                telemetry::report(Report::Error(sensory));
+                return;
+            }
+        };
+        let setup = sensor
+            .set_sampling_rate(config.sampling_rate)
+            .and_then(|_| sensor.set_sample_averaging(config.averaging))
+            .and_then(|_| sensor.set_adc_range(config.adc_range))
+            .and_then(|_| sensor.set_pulse_width(config.pulse_width))
+            .and_then(|_| sensor.set_pulse_amplitude(Led::Led1, config.led_red))
+            .and_then(|_| sensor.set_pulse_amplitude(Led::Led2, config.led_ir))
+            .and_then(|_| sensor.enable_fifo_rollover())
+            .and_then(|_| sensor.clear_fifo())
+            .and_then(|_| sensor.wake_up());
+        if setup.is_err() {
+            telemetry::report(Report::Error(sensory));
+            return;
+        }
+        let period = Duration::from_hz(config.hz() as u64);
+        let mut estimator = Estimator::new(config.hz() as f32);
         // Ticker
         let mut ticker = Ticker::every(Duration::from_hz(hz));
-        //let mut result: SensorResult<Reading>;
+        telemetry::report(Report::Target(sensory, hz as f32));
+        // red and IR words interleaved
+        let mut data = [0u32; 2 * FIFO];
         READY.store(true, ORD);
         loop {
+            ticker.next().await;
             if RECORD.load(ORD) {
-                let mut reading = [0; 1];
-                let _ = sensor.read_fifo(&mut reading);
-
-                let sample = Sample {
-                    sensory: sensory,
-                    time: Instant::now(),
-                    read: reading,
+                let n = match sensor.read_fifo(&mut data) {
+                    Ok(n) => n as usize,
+                    Err(_) => {
+                        telemetry::report(Report::Error(sensory));
+                        continue;
+                    }
                 };
-                SINK.send(sample.into()).await;
-                /*log::info!("{},1,{},,,,,,,",
-                Instant::now().as_micros(),
-                reading[0]);*/
-                ticker.next().await;
+                let now = Instant::now();
+                for (i, word) in data[..2 * n].chunks_exact(2).enumerate() {
+                    let (red, ir) = (word[0], word[1]);
+                    // the newest sample was taken just now
+                    let time = now - period * (n - 1 - i) as u32;
+                    let mut read: YtfRead = [None; YTF_LEN];
+                    read[0] = Some(red.into());
+                    read[1] = Some(ir.into());
+                    if config.estimate {
+                        if let Some(vitals) = estimator.update(red, ir) {
+                            read[2] = Some(vitals.bpm.into());
+                            read[3] = vitals.spo2.map(|v| v.into());
+                        }
+                    }
+                    bsu::send(Ytf { sensory, time, read }).await;
+                }
             };
         }
     }
 }
 
+#[cfg(feature = "irt")]
 pub mod yirt {
-    // MLX90614
+    //! ## MLX90614 IR thermometers
+    //!
+    //! Talks SMBus on the async I2C driver, so several sensors can be
+    //! read in one task. Every sensor needs its own address,
+    //! which can be programmed with `provision`.
+    //! Dual-zone variants (MLX90614xBx) additionally report object 2,
+    //! which is detected from the sensor's configuration register.
     /* Sensor Generics */
     use super::*;
-    use embassy_time::{Duration, Instant, Ticker};
-    use mlx9061x::{Mlx9061x, SlaveAddr};
+    use embassy_time::{Duration, Instant, Ticker, Timer};
 
     // Generic result
-    pub type Reading = [f32; 2];
+    pub type Reading = [f32; 3];
     pub type Measure = SensorResult<Reading>;
 
     // I2C
     use hal::i2c;
     use hal::peripherals::I2C0;
+    type I2c = i2c::I2c<'static, I2C0, i2c::Async>;
 
     /* control channels */
     use core::sync::atomic::AtomicBool;
@@ -741,10 +1121,153 @@ pub mod yirt {
     pub static READY: AtomicBool = AtomicBool::new(false);
     pub static RECORD: AtomicBool = AtomicBool::new(false);
 
+    /// Factory address, every sensor also answers on 0x00
+    pub const DEFAULT_ADDRESS: u8 = 0x5A;
+    const BROADCAST: u8 = 0x00;
+
+    // RAM and EEPROM commands
+    const T_AMBIENT: u8 = 0x06;
+    const T_OBJECT1: u8 = 0x07;
+    const T_OBJECT2: u8 = 0x08;
+    const EMISSIVITY: u8 = 0x24;
+    const CONFIG: u8 = 0x25;
+    const ADDRESS: u8 = 0x2E;
+    const DUAL_ZONE: u16 = 1 << 6;
+
+    #[derive(Debug)]
+    pub enum Error {
+        I2c(i2c::Error),
+        /// checksum mismatch
+        Pec,
+        /// the sensor flagged the reading as invalid
+        Invalid,
+    }
+
+    impl From<i2c::Error> for Error {
+        fn from(e: i2c::Error) -> Self {
+            Error::I2c(e)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Config {
+        pub address: u8,
+        /// written to EEPROM when different from the stored value
+        pub emissivity: Option<f32>,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                address: DEFAULT_ADDRESS,
+                emissivity: None,
+            }
+        }
+    }
+
+    /// SMBus packet error code, CRC-8 with polynomial 0x07
+    fn pec(bytes: &[u8]) -> u8 {
+        let mut crc: u8 = 0;
+        for &byte in bytes {
+            crc ^= byte;
+            for _ in 0..8 {
+                crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+            }
+        }
+        crc
+    }
+
+    async fn read_word(i2c: &mut I2c, address: u8, command: u8) -> Result<u16, Error> {
+        let mut buf = [0u8; 3];
+        i2c.write_read_async(address as u16, [command], &mut buf).await?;
+        if pec(&[address << 1, command, (address << 1) | 1, buf[0], buf[1]]) != buf[2] {
+            return Err(Error::Pec);
+        }
+        Ok(u16::from_le_bytes([buf[0], buf[1]]))
+    }
+
+    async fn write_word(i2c: &mut I2c, address: u8, command: u8, word: u16) -> Result<(), Error> {
+        let [lsb, msb] = word.to_le_bytes();
+        let crc = pec(&[address << 1, command, lsb, msb]);
+        i2c.write_async(address as u16, [command, lsb, msb, crc]).await?;
+        Ok(())
+    }
+
+    /// EEPROM cells have to be erased before they are written,
+    /// both take about 5 ms.
+    async fn write_eeprom(i2c: &mut I2c, address: u8, command: u8, word: u16) -> Result<(), Error> {
+        write_word(i2c, address, command, 0).await?;
+        Timer::after(Duration::from_millis(10)).await;
+        write_word(i2c, address, command, word).await?;
+        Timer::after(Duration::from_millis(10)).await;
+        Ok(())
+    }
+
+    /// Temperature in °C
+    async fn temperature(i2c: &mut I2c, address: u8, command: u8) -> Result<f32, Error> {
+        let raw = read_word(i2c, address, command).await?;
+        if raw & 0x8000 != 0 {
+            return Err(Error::Invalid);
+        }
+        Ok(raw as f32 * 0.02 - 273.15)
+    }
+
+    /// Sets the emissivity (0.1 to 1.0), if it differs from the stored one
+    pub async fn set_emissivity(i2c: &mut I2c, address: u8, emissivity: f32) -> Result<(), Error> {
+        let word = (emissivity.clamp(0.1, 1.0) * 65535.0 + 0.5) as u16;
+        if read_word(i2c, address, EMISSIVITY).await? != word {
+            write_eeprom(i2c, address, EMISSIVITY, word).await?;
+        }
+        Ok(())
+    }
+
+    /// ### Provisioning
+    ///
+    /// Programs a new SMBus address into EEPROM. This talks to
+    /// the broadcast address, so only the sensor to be programmed may
+    /// be connected. The new address is used after a power cycle.
+    pub async fn provision(i2c: &mut I2c, address: u8) -> Result<(), Error> {
+        let old = read_word(i2c, BROADCAST, ADDRESS).await?;
+        let word = (old & 0xFF00) | address as u16;
+        write_eeprom(i2c, BROADCAST, ADDRESS, word).await?;
+        if read_word(i2c, BROADCAST, ADDRESS).await? == word {
+            Ok(())
+        } else {
+            Err(Error::Invalid)
+        }
+    }
+
+    /// Reads object 1, ambient and, on dual-zone sensors, object 2.
+    async fn read(i2c: &mut I2c, address: u8, dual: bool) -> Result<YtfRead, Error> {
+        let mut read: YtfRead = [None; YTF_LEN];
+        read[0] = Some(temperature(i2c, address, T_OBJECT1).await?.into());
+        read[1] = Some(temperature(i2c, address, T_AMBIENT).await?.into());
+        if dual {
+            read[2] = Some(temperature(i2c, address, T_OBJECT2).await?.into());
+        }
+        Ok(read)
+    }
+
+    /// Reads all `sensors` on the bus, sent as `sensory`, `sensory + 1`, ...
     #[embassy_executor::task]
-    pub async fn task(i2c: i2c::I2c<'static, I2C0, i2c::Blocking>, hz: u64, sensory: u8) {
-        let address = SlaveAddr::default();
-        let mut sensor = Mlx9061x::new_mlx90614(i2c, address, 5).unwrap();
+    pub async fn task(mut i2c: I2c, sensors: Vec<Config, 8>, hz: u64, sensory: u8) {
+        // configure and find out which sensors are dual-zone
+        let mut dual_zone: Vec<Option<bool>, 8> = Vec::new();
+        for (s, config) in sensors.iter().enumerate() {
+            let mut setup = Ok(());
+            if let Some(emissivity) = config.emissivity {
+                setup = set_emissivity(&mut i2c, config.address, emissivity).await;
+            }
+            let zones = match setup {
+                Ok(_) => read_word(&mut i2c, config.address, CONFIG).await.ok(),
+                Err(_) => None,
+            };
+            match zones {
+                Some(_) => telemetry::report(Report::Target(sensory + s as u8, hz as f32)),
+                None => telemetry::report(Report::Error(sensory + s as u8)),
+            }
+            let _ = dual_zone.push(zones.map(|c| c & DUAL_ZONE != 0));
+        }
         let mut ticker = Ticker::every(Duration::from_hz(hz));
         //let mut reading: Reading;
         //let mut result: SensorResult<Reading>;
@@ -752,15 +1275,19 @@ pub mod yirt {
         loop {
             ticker.next().await;
             if RECORD.load(ORD) {
-                let obj_temp: f32 = sensor.object1_temperature().unwrap().into();
-                let amb_temp: f32 = sensor.ambient_temperature().unwrap().into();
-                let reading: Reading = [obj_temp.into(), amb_temp.into()];
-                let sample = Sample {
-                    sensory: sensory,
-                    time: Instant::now(),
-                    read: reading,
-                };
-                SINK.send(sample.into()).await;
+                for (s, config) in sensors.iter().enumerate() {
+                    // sensors that failed setup are skipped
+                    let Some(dual) = dual_zone[s] else { continue };
+                    match read(&mut i2c, config.address, dual).await {
+                        Ok(read) => bsu::send(Ytf {
+                            sensory: sensory + s as u8,
+                            time: Instant::now(),
+                            read,
+                        })
+                        .await,
+                        Err(_) => telemetry::report(Report::Error(sensory + s as u8)),
+                    }
+                }
                 /*log::info!("{},T,{},{}",
                 result.time.as_micros(),
                 result.reading[0],
@@ -770,73 +1297,1663 @@ pub mod yirt {
     }
 }
 
+#[cfg(feature = "co2")]
 pub mod yco2 {
+    //! ## SCD4x CO2 sensor
+    //!
+    //! Runs in single shot (SCD41 only), periodic (5 s) or
+    //! low-power periodic (30 s) mode. Compensation settings and
+    //! calibration can be changed at runtime through `CONTROL`,
+    //! which is also reachable from the host (`ytfk::cmd`),
+    //! e.g. `co2 frc 420` after five minutes in fresh air.
+    //!
+    //! Every command is logged in the stream as an event on
+    //! `sensory + 1`: channel 0 holds the event code, channel 1
+    //! the value and channel 2 is 1 on success.
     use super::*;
+    use core::str::SplitWhitespace;
     use hal::peripherals::I2C0;
     use scd4x;
 
     /* control channels */
     pub static READY: AtomicBool = AtomicBool::new(false);
     pub static RECORD: AtomicBool = AtomicBool::new(false);
+    pub static CONTROL: Channel<RawMutex, Command, 4> = Channel::new();
 
     // Generic result
     pub type Reading = [f32; 3];
     pub type Measure = SensorResult<Reading>;
 
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Mode {
+        /// one measurement per `Config::interval`, SCD41 only
+        SingleShot,
+        /// one measurement every 5 s
+        Periodic,
+        /// one measurement every 30 s
+        LowPower,
+    }
+
+    impl Mode {
+        /// Samples per second sent: `hz`, or with 0 one per measurement
+        fn target(self, hz: u64, interval: Duration) -> f32 {
+            let period = match (hz, self) {
+                (0, Mode::SingleShot) => interval.max(Duration::from_secs(5)),
+                (0, Mode::Periodic) => Duration::from_secs(5),
+                (0, Mode::LowPower) => Duration::from_secs(30),
+                (hz, _) => return hz as f32,
+            };
+            1e6 / period.as_micros() as f32
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Command {
+        Mode(Mode),
+        /// °C subtracted from the temperature reading
+        TemperatureOffset(f32),
+        /// m above sea level
+        Altitude(u16),
+        /// hPa, overrides the altitude
+        Pressure(u16),
+        /// target concentration in ppm
+        ForcedRecalibration(u16),
+        AutoCalibration(bool),
+        /// write the settings to the sensor's EEPROM
+        Persist,
+    }
+
+    impl Command {
+        /// Event code in the stream
+        pub fn code(&self) -> u8 {
+            match self {
+                Command::Mode(_) => 1,
+                Command::TemperatureOffset(_) => 2,
+                Command::Altitude(_) => 3,
+                Command::Pressure(_) => 4,
+                Command::ForcedRecalibration(_) => 5,
+                Command::AutoCalibration(_) => 6,
+                Command::Persist => 7,
+            }
+        }
+
+        /// Parses the words following `co2` on a command line
+        pub fn parse(mut words: SplitWhitespace) -> Option<Command> {
+            let command = match (words.next()?, words.next()) {
+                ("mode", Some("single")) => Command::Mode(Mode::SingleShot),
+                ("mode", Some("periodic")) => Command::Mode(Mode::Periodic),
+                ("mode", Some("lowpower")) => Command::Mode(Mode::LowPower),
+                ("offset", Some(v)) => Command::TemperatureOffset(v.parse().ok()?),
+                ("altitude", Some(v)) => Command::Altitude(v.parse().ok()?),
+                ("pressure", Some(v)) => Command::Pressure(v.parse().ok()?),
+                ("frc", Some(v)) => Command::ForcedRecalibration(v.parse().ok()?),
+                ("asc", Some("on")) => Command::AutoCalibration(true),
+                ("asc", Some("off")) => Command::AutoCalibration(false),
+                ("persist", None) => Command::Persist,
+                _ => return None,
+            };
+            Some(command)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Config {
+        pub mode: Mode,
+        /// time between single shots, at least 5 s
+        pub interval: Duration,
+        pub temperature_offset: Option<f32>,
+        pub altitude: Option<u16>,
+        pub pressure: Option<u16>,
+        pub auto_calibration: Option<bool>,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                mode: Mode::Periodic,
+                interval: Duration::from_secs(5),
+                temperature_offset: None,
+                altitude: None,
+                pressure: None,
+                auto_calibration: None,
+            }
+        }
+    }
+
+    type Sensor = scd4x::Scd4x<i2c::I2c<'static, I2C0, i2c::Async>, time::Delay>;
+
+    fn start(sensor: &mut Sensor, mode: Mode) -> bool {
+        match mode {
+            Mode::SingleShot => Ok(()),
+            Mode::Periodic => sensor.start_periodic_measurement(),
+            Mode::LowPower => sensor.start_low_power_periodic_measurements(),
+        }
+        .is_ok()
+    }
+
+    /// Applies a command. Most settings can only be changed
+    /// while idle, so periodic measurement is stopped and restarted.
+    /// Returns the value to log and whether the command succeeded.
+    fn apply(sensor: &mut Sensor, mode: &mut Mode, command: Command) -> (f32, bool) {
+        if *mode != Mode::SingleShot && sensor.stop_periodic_measurement().is_err() {
+            return (0.0, false);
+        }
+        let (value, ok) = match command {
+            Command::Mode(next) => {
+                *mode = next;
+                (next as u8 as f32, true)
+            }
+            Command::TemperatureOffset(v) => (v, sensor.set_temperature_offset(v).is_ok()),
+            Command::Altitude(v) => (v as f32, sensor.set_altitude(v).is_ok()),
+            Command::Pressure(v) => (v as f32, sensor.set_ambient_pressure(v).is_ok()),
+            // the sensor answers with the correction it applied
+            Command::ForcedRecalibration(v) => match sensor.forced_recalibration(v) {
+                Ok(correction) => (correction as i16 as f32, true),
+                Err(_) => (v as f32, false),
+            },
+            Command::AutoCalibration(on) => (
+                on as u8 as f32,
+                sensor.set_automatic_self_calibration(on).is_ok(),
+            ),
+            Command::Persist => (0.0, sensor.persist_settings().is_ok()),
+        };
+        (value, start(sensor, *mode) && ok)
+    }
+
+    /// Measures in the current mode, polled once per second.
+    /// A single shot is started when due and read 5 s later.
+    struct Measurement {
+        next_shot: Instant,
+        shot: Option<Instant>,
+        next_poll: Instant,
+    }
+
+    impl Measurement {
+        fn poll(&mut self, sensor: &mut Sensor, mode: Mode, interval: Duration, sensory: u8) -> Option<Reading> {
+            let now = Instant::now();
+            if now < self.next_poll {
+                return None;
+            }
+            self.next_poll = now + Duration::from_secs(1);
+            if mode == Mode::SingleShot {
+                match self.shot {
+                    Some(done) if now >= done => self.shot = None,
+                    Some(_) => return None,
+                    None => {
+                        if now >= self.next_shot {
+                            self.next_shot += interval.max(Duration::from_secs(5));
+                            if sensor.measure_single_shot_non_blocking().is_err() {
+                                telemetry::report(Report::Error(sensory));
+                            } else {
+                                // a single shot takes 5 s
+                                self.shot = Some(now + Duration::from_secs(5));
+                            }
+                        }
+                        return None;
+                    }
+                }
+            } else if !matches!(sensor.data_ready_status(), Ok(true)) {
+                return None;
+            }
+            match sensor.measurement() {
+                Err(_) => {
+                    telemetry::report(Report::Error(sensory));
+                    None
+                }
+                Ok(raw) => Some([raw.co2 as f32, raw.humidity as f32, raw.temperature as f32]),
+            }
+        }
+    }
+
+    /// With `hz` above 0, the latest measurement is repeated at
+    /// `hz`, which lines CO2 up with faster streams. With 0, every
+    /// measurement is sent once, as it comes.
     #[embassy_executor::task]
-    pub async fn task(i2c: i2c::I2c<'static, I2C0, Mode>, sensory: u8) {
+    pub async fn task(i2c: i2c::I2c<'static, I2C0, i2c::Async>, config: Config, hz: u64, sensory: u8) {
         //DISP.signal([None, None, None, Some("CO2 start".try_into().unwrap())]);
-        let mut sensor = scd4x::Scd4x::new(i2c, time::Delay);
+        let mut sensor: Sensor = scd4x::Scd4x::new(i2c, time::Delay);
         //sensor.wake_up(); <---- This fails
         sensor.stop_periodic_measurement().unwrap();
         match sensor.reinit() {
             Ok(_) => {}
             Err(_) => {
-                DISP.signal([None, None, None, Some("Reinit failed".try_into().unwrap())]);
+                telemetry::report(Report::Error(sensory));
                 return;
             }
         }
+        // settings from the configuration, the sensor is idle now
+        let mut mode = Mode::SingleShot;
+        let settings = [
+            config.temperature_offset.map(Command::TemperatureOffset),
+            config.altitude.map(Command::Altitude),
+            config.pressure.map(Command::Pressure),
+            config.auto_calibration.map(Command::AutoCalibration),
+        ];
+        for command in settings.into_iter().flatten() {
+            let _ = apply(&mut sensor, &mut mode, command);
+        }
+        mode = config.mode;
+        start(&mut sensor, mode);
         //DISP.signal([None, None, None, Some("CO2 init".try_into().unwrap())]);
-        let mut ticker = Ticker::every(Duration::from_secs(5));
-        //let mut result: SensorResult<Reading>;
+        let mut ticker = Ticker::every(match hz {
+            0 => Duration::from_secs(1),
+            hz => Duration::from_hz(hz),
+        });
+        let mut measurement = Measurement {
+            next_shot: Instant::now(),
+            shot: None,
+            next_poll: Instant::now(),
+        };
+        let mut latest = None;
+        telemetry::report(Report::Target(sensory, mode.target(hz, config.interval)));
         READY.store(true, ORD);
         //DISP.signal([None, None, None, Some("CO2 ticking".try_into().unwrap())]);
         loop {
-            if RECORD.load(ORD) {
-                match sensor.measure_single_shot_non_blocking() {
-                    Err(_) => {
-                        DISP.signal([
-                            None,
-                            None,
-                            None,
-                            Some("CO2 prep failed".try_into().unwrap()),
-                        ]);
-                    }
-                    Ok(_) => {
-                        ticker.next().await;
-                        match sensor.measurement() {
-                            Err(_) => {
-                                DISP.signal([
-                                    None,
-                                    None,
-                                    None,
-                                    Some("CO2 read failed".try_into().unwrap()),
-                                ]);
-                            }
-                            Ok(raw) => {
-                                let reading: Reading =
-                                    [raw.co2 as f32, raw.humidity as f32, raw.temperature as f32];
-                                let sample = Sample {
-                                    sensory: sensory,
-                                    time: Instant::now(),
-                                    read: reading,
-                                };
-                                SINK.send(sample.into()).await;
-                            }
-                        };
-                    }
+            ticker.next().await;
+            while let Ok(command) = CONTROL.try_receive() {
+                let (value, ok) = apply(&mut sensor, &mut mode, command);
+                let event = Sample {
+                    sensory: sensory + 1,
+                    time: Instant::now(),
+                    read: [command.code() as f32, value, ok as u8 as f32],
                 };
+                bsu::send(event.into()).await;
+                telemetry::report(Report::Target(sensory, mode.target(hz, config.interval)));
+            }
+            if !RECORD.load(ORD) {
+                continue;
+            }
+            let fresh = measurement.poll(&mut sensor, mode, config.interval, sensory);
+            let reading = match hz {
+                0 => fresh,
+                _ => {
+                    latest = fresh.or(latest);
+                    latest
+                }
             };
+            if let Some(read) = reading {
+                let sample = Sample {
+                    sensory,
+                    time: Instant::now(),
+                    read,
+                };
+                bsu::send(sample.into()).await;
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "exg", test))]
+pub mod yexg {
+    //! ## Biopotential front-end (EEG, ECG, EMG)
+    //!
+    //! ADS1299 (4, 6 or 8 channels) and ADS1292 (2 channels)
+    //! on SPI0, in SPI mode 1. The chip is detected from its ID register.
+    //! The task runs in continuous read mode and reads one frame
+    //! per falling edge of DRDY, so the sample clock is the chip's.
+    //! Everything but the task works on any `SpiDevice`, so
+    //! register setup and frame parsing are checked on the host.
+    //!
+    //! Streams:
+    //!
+    //! + `sensory`: channel voltages in µV
+    //! + `sensory + 1`: lead-off flags (bit masks of positive and
+    //!   negative inputs), sent whenever they change
+    //! + `sensory + 2`: electrode impedance in kΩ, once per second
+    //!   (only in `Mode::Impedance`)
+    use super::*;
+    use embedded_hal_async::digital::Wait;
+    use embedded_hal_async::spi::{Operation, SpiDevice};
+    #[cfg(feature = "rp")]
+    use {
+        embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice as Device,
+        hal::gpio::{AnyPin, Input, Level, Output, Pull},
+        hal::peripherals::SPI0 as SPI,
+        hal::spi::{self, Spi},
+    };
+
+    /* control channels */
+    pub static READY: AtomicBool = AtomicBool::new(false);
+    pub static RECORD: AtomicBool = AtomicBool::new(false);
+
+    pub const MAX_CHANNELS: usize = 8;
+    /// three status bytes and three bytes per channel
+    pub const MAX_FRAME: usize = 3 + 3 * MAX_CHANNELS;
+
+    // commands
+    const WAKEUP: u8 = 0x02;
+    const RESET: u8 = 0x06;
+    const START: u8 = 0x08;
+    const STOP: u8 = 0x0A;
+    const RDATAC: u8 = 0x10;
+    const SDATAC: u8 = 0x11;
+    const RREG: u8 = 0x20;
+    const WREG: u8 = 0x40;
+
+    // registers shared by both families
+    const ID: u8 = 0x00;
+    const CONFIG1: u8 = 0x01;
+    const CONFIG2: u8 = 0x02;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Rate {
+        Sps250,
+        Sps500,
+        Sps1000,
+    }
+
+    impl Rate {
+        pub fn hz(self) -> u32 {
+            match self {
+                Rate::Sps250 => 250,
+                Rate::Sps500 => 500,
+                Rate::Sps1000 => 1000,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Gain {
+        X1,
+        X2,
+        X4,
+        X6,
+        X8,
+        X12,
+        X24,
+    }
+
+    impl Gain {
+        pub fn factor(self) -> f32 {
+            match self {
+                Gain::X1 => 1.0,
+                Gain::X2 => 2.0,
+                Gain::X4 => 4.0,
+                Gain::X6 => 6.0,
+                Gain::X8 => 8.0,
+                Gain::X12 => 12.0,
+                Gain::X24 => 24.0,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Mode {
+        /// electrodes, with DC lead-off detection
+        Record,
+        /// internal square wave test signal on all channels
+        Test,
+        /// AC excitation at fDR/4 for impedance checks
+        Impedance,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Config {
+        pub rate: Rate,
+        pub gain: [Gain; MAX_CHANNELS],
+        pub mode: Mode,
+        /// ADS1299: all negative inputs on SRB1 (common reference)
+        pub common_reference: bool,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                rate: Rate::Sps250,
+                gain: [Gain::X24; MAX_CHANNELS],
+                mode: Mode::Record,
+                common_reference: true,
+            }
+        }
+    }
+
+    /// Lead-off excitation current in the impedance check
+    const I_LEADOFF_NA: f32 = 6.0;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Chip {
+        Ads1299(u8),
+        Ads1292,
+    }
+
+    impl Chip {
+        pub fn from_id(id: u8) -> Option<Chip> {
+            match id & 0x1F {
+                0x1C => Some(Chip::Ads1299(4)),
+                0x1D => Some(Chip::Ads1299(6)),
+                0x1E => Some(Chip::Ads1299(8)),
+                0x13 => Some(Chip::Ads1292),
+                _ => None,
+            }
+        }
+
+        pub fn channels(self) -> usize {
+            match self {
+                Chip::Ads1299(n) => n as usize,
+                Chip::Ads1292 => 2,
+            }
+        }
+
+        pub fn frame_len(self) -> usize {
+            3 + 3 * self.channels()
+        }
+
+        /// µV per LSB, from full scale ±VREF / gain over 24 bit
+        pub fn lsb_uv(self, gain: Gain) -> f32 {
+            let vref = match self {
+                Chip::Ads1299(_) => 4.5,
+                Chip::Ads1292 => 2.42,
+            };
+            2.0 * vref * 1e6 / (gain.factor() * 16_777_216.0)
+        }
+
+        /// Register values from address 0x01 onwards
+        pub fn registers(self, config: &Config) -> Vec<u8, 24> {
+            let mut regs: Vec<u8, 24> = Vec::new();
+            match self {
+                Chip::Ads1299(n) => {
+                    let dr = match config.rate {
+                        Rate::Sps250 => 0b110,
+                        Rate::Sps500 => 0b101,
+                        Rate::Sps1000 => 0b100,
+                    };
+                    let (config2, loff, mux) = match config.mode {
+                        Mode::Record => (0xC0, 0x00, 0b000),
+                        Mode::Test => (0xD0, 0x00, 0b101),
+                        Mode::Impedance => (0xC0, 0x03, 0b000),
+                    };
+                    let all = ((1u16 << n) - 1) as u8;
+                    let _ = regs.extend_from_slice(&[0x90 | dr, config2, 0xEC, loff]);
+                    for ch in 0..MAX_CHANNELS {
+                        let gain = match config.gain[ch] {
+                            Gain::X1 => 0b000,
+                            Gain::X2 => 0b001,
+                            Gain::X4 => 0b010,
+                            Gain::X6 => 0b011,
+                            Gain::X8 => 0b100,
+                            Gain::X12 => 0b101,
+                            Gain::X24 => 0b110,
+                        };
+                        // unused channels are powered down and shorted
+                        let chset = if ch < n as usize { gain << 4 | mux } else { 0x81 };
+                        let _ = regs.push(chset);
+                    }
+                    let misc1 = if config.common_reference { 0x20 } else { 0x00 };
+                    // BIAS_SENSP/N, LOFF_SENSP/N, LOFF_FLIP, LOFF_STATP/N, GPIO, MISC1, MISC2, CONFIG4
+                    let _ = regs.extend_from_slice(&[all, all, all, all, 0x00, 0x00, 0x00, 0x0F, misc1, 0x00, 0x02]);
+                }
+                Chip::Ads1292 => {
+                    let dr = match config.rate {
+                        Rate::Sps250 => 0b001,
+                        Rate::Sps500 => 0b010,
+                        Rate::Sps1000 => 0b011,
+                    };
+                    let (config2, loff, mux) = match config.mode {
+                        Mode::Record => (0xE0, 0x10, 0b0000),
+                        Mode::Test => (0xE3, 0x10, 0b0101),
+                        Mode::Impedance => (0xE0, 0x11, 0b0000),
+                    };
+                    let _ = regs.extend_from_slice(&[dr, config2, loff]);
+                    for ch in 0..2 {
+                        // no 24x on this chip
+                        let gain = match config.gain[ch] {
+                            Gain::X1 => 0b001,
+                            Gain::X2 => 0b010,
+                            Gain::X4 => 0b100,
+                            Gain::X6 => 0b000,
+                            Gain::X8 => 0b101,
+                            Gain::X12 | Gain::X24 => 0b110,
+                        };
+                        let _ = regs.push(gain << 4 | mux);
+                    }
+                    // RLD_SENS, LOFF_SENS
+                    let _ = regs.extend_from_slice(&[0x2F, 0x0F]);
+                }
+            }
+            regs
+        }
+
+        /// Gain as set by `registers`
+        pub fn gain(self, gain: Gain) -> Gain {
+            match (self, gain) {
+                (Chip::Ads1292, Gain::X24) => Gain::X12,
+                (_, gain) => gain,
+            }
+        }
+    }
+
+    /// ### One frame of data
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Frame {
+        /// lead-off flags of positive inputs, one bit per channel
+        pub loff_p: u8,
+        /// lead-off flags of negative inputs
+        pub loff_n: u8,
+        pub counts: [i32; MAX_CHANNELS],
+    }
+
+    impl Frame {
+        /// Parses a frame read in continuous mode. Returns `None`
+        /// if the status word does not start with 0b1100.
+        pub fn parse(chip: Chip, bytes: &[u8]) -> Option<Frame> {
+            if bytes.len() < chip.frame_len() || bytes[0] & 0xF0 != 0xC0 {
+                return None;
+            }
+            let status = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+            let (loff_p, loff_n) = match chip {
+                Chip::Ads1299(_) => ((status >> 12) as u8, (status >> 4) as u8),
+                // 1292: RLD, IN2N, IN2P, IN1N, IN1P in bits 19..15
+                Chip::Ads1292 => {
+                    let stat = (status >> 15) & 0x1F;
+                    (
+                        ((stat & 0b0001) | (stat >> 1 & 0b0010)) as u8,
+                        ((stat >> 1 & 0b0001) | (stat >> 2 & 0b0010)) as u8,
+                    )
+                }
+            };
+            let mut counts = [0; MAX_CHANNELS];
+            for (ch, raw) in bytes[3..chip.frame_len()].chunks_exact(3).enumerate() {
+                // sign extension of 24 bit two's complement
+                counts[ch] = i32::from_be_bytes([raw[0], raw[1], raw[2], 0]) >> 8;
+            }
+            Some(Frame {
+                loff_p,
+                loff_n,
+                counts,
+            })
+        }
+    }
+
+    /// ### Impedance from the AC lead-off excitation
+    ///
+    /// The excitation current makes the signal swing by
+    /// `2 I Z`, so half the peak-to-peak voltage in µV over
+    /// the current in nA gives kΩ.
+    pub struct Impedance {
+        min: [f32; MAX_CHANNELS],
+        max: [f32; MAX_CHANNELS],
+        n: u32,
+        window: u32,
+    }
+
+    impl Impedance {
+        pub fn new(window: u32) -> Self {
+            Impedance {
+                min: [f32::MAX; MAX_CHANNELS],
+                max: [f32::MIN; MAX_CHANNELS],
+                n: 0,
+                window,
+            }
+        }
+
+        /// Feeds one frame in µV, returns kΩ per channel once per window
+        pub fn update(&mut self, uv: &[f32]) -> Option<[f32; MAX_CHANNELS]> {
+            for (ch, v) in uv.iter().enumerate() {
+                self.min[ch] = self.min[ch].min(*v);
+                self.max[ch] = self.max[ch].max(*v);
+            }
+            self.n += 1;
+            if self.n < self.window {
+                return None;
+            }
+            let mut kohm = [0.0; MAX_CHANNELS];
+            for ch in 0..uv.len() {
+                kohm[ch] = (self.max[ch] - self.min[ch]) / 2.0 / I_LEADOFF_NA;
+            }
+            *self = Impedance::new(self.window);
+            Some(kohm)
+        }
+    }
+
+    /// Commands need 4 tCLK (2 µs) between bytes
+    const T_BYTE_NS: u32 = 2_000;
+    /// WREG with all registers of the ADS1299
+    const MAX_COMMAND: usize = 2 + 24;
+
+    async fn command<D: SpiDevice>(spi: &mut D, bytes: &[u8]) -> Result<(), D::Error> {
+        let mut operations: Vec<Operation<'_, u8>, { 2 * MAX_COMMAND }> = Vec::new();
+        for byte in bytes.chunks(1) {
+            let _ = operations.push(Operation::Write(byte));
+            let _ = operations.push(Operation::DelayNs(T_BYTE_NS));
+        }
+        spi.transaction(&mut operations).await
+    }
+
+    async fn read_register<D: SpiDevice>(spi: &mut D, address: u8) -> Result<u8, D::Error> {
+        let mut value = [0u8];
+        spi.transaction(&mut [
+            Operation::Write(&[RREG | address]),
+            Operation::DelayNs(T_BYTE_NS),
+            Operation::Write(&[0x00]),
+            Operation::DelayNs(T_BYTE_NS),
+            Operation::Read(&mut value),
+        ])
+        .await?;
+        Ok(value[0])
+    }
+
+    /// Resets the chip, detects it, writes the configuration and
+    /// starts continuous conversion. `None` for an unknown chip
+    /// or registers that do not read back.
+    pub async fn setup<D: SpiDevice>(spi: &mut D, config: &Config) -> Result<Option<Chip>, D::Error> {
+        command(spi, &[WAKEUP]).await?;
+        command(spi, &[RESET]).await?;
+        time::Timer::after(Duration::from_millis(1)).await;
+        // registers can only be accessed outside continuous mode
+        command(spi, &[SDATAC]).await?;
+        let Some(chip) = Chip::from_id(read_register(spi, ID).await?) else {
+            return Ok(None);
+        };
+        let regs = chip.registers(config);
+        let mut wreg: Vec<u8, MAX_COMMAND> = Vec::new();
+        let _ = wreg.extend_from_slice(&[WREG | CONFIG1, regs.len() as u8 - 1]);
+        let _ = wreg.extend_from_slice(&regs);
+        command(spi, &wreg).await?;
+        // the internal reference needs to settle
+        time::Timer::after(Duration::from_millis(150)).await;
+        if read_register(spi, CONFIG2).await? != regs[1] {
+            return Ok(None);
+        }
+        command(spi, &[START]).await?;
+        command(spi, &[RDATAC]).await?;
+        Ok(Some(chip))
+    }
+
+    /// Stops continuous conversion, e.g. before handing the bus on
+    pub async fn stop<D: SpiDevice>(spi: &mut D) -> Result<(), D::Error> {
+        command(spi, &[SDATAC]).await?;
+        command(spi, &[STOP]).await
+    }
+
+    /// ### Frames to samples
+    ///
+    /// Scales the channels to µV and derives the lead-off
+    /// and impedance streams.
+    pub struct Stream {
+        chip: Chip,
+        mode: Mode,
+        sensory: u8,
+        lsb: [f32; MAX_CHANNELS],
+        impedance: Impedance,
+        loff: (u8, u8),
+    }
+
+    impl Stream {
+        pub fn new(chip: Chip, config: &Config, sensory: u8) -> Self {
+            let mut lsb = [0.0; MAX_CHANNELS];
+            for ch in 0..chip.channels() {
+                lsb[ch] = chip.lsb_uv(chip.gain(config.gain[ch]));
+            }
+            Stream {
+                chip,
+                mode: config.mode,
+                sensory,
+                lsb,
+                impedance: Impedance::new(config.rate.hz()),
+                loff: (0, 0),
+            }
+        }
+
+        /// Samples of one frame read at `time`, none for a broken frame
+        pub fn samples(&mut self, time: Instant, bytes: &[u8]) -> Vec<Ytf, 3> {
+            let mut out = Vec::new();
+            let Some(frame) = Frame::parse(self.chip, bytes) else { return out };
+            let n = self.chip.channels();
+            let sensory = self.sensory;
+            let mut uv = [0.0f32; MAX_CHANNELS];
+            let mut read: YtfRead = [None; YTF_LEN];
+            for ch in 0..n {
+                uv[ch] = frame.counts[ch] as f32 * self.lsb[ch];
+                read[ch] = Some(uv[ch].into());
+            }
+            let _ = out.push(Ytf { sensory, time, read });
+
+            if self.mode == Mode::Record && (frame.loff_p, frame.loff_n) != self.loff {
+                self.loff = (frame.loff_p, frame.loff_n);
+                let sample = Sample {
+                    sensory: sensory + 1,
+                    time,
+                    read: [self.loff.0, self.loff.1],
+                };
+                let _ = out.push(sample.into());
+            }
+            if self.mode == Mode::Impedance {
+                if let Some(kohm) = self.impedance.update(&uv[..n]) {
+                    let mut read: YtfRead = [None; YTF_LEN];
+                    for ch in 0..n {
+                        read[ch] = Some(kohm[ch].into());
+                    }
+                    let _ = out.push(Ytf { sensory: sensory + 2, time, read });
+                }
+            }
+            out
+        }
+    }
+
+    /// Sets the chip up and streams one frame per falling edge of `drdy`
+    pub async fn run<D: SpiDevice, W: Wait>(mut spi: D, mut drdy: W, config: Config, sensory: u8) {
+        let chip = match setup(&mut spi, &config).await {
+            Ok(Some(chip)) => chip,
+            _ => {
+                telemetry::report(Report::Error(sensory));
+                return;
+            }
+        };
+        let mut stream = Stream::new(chip, &config, sensory);
+        let mut bytes = [0u8; MAX_FRAME];
+        telemetry::report(Report::Target(sensory, config.rate.hz() as f32));
+        READY.store(true, ORD);
+        loop {
+            if drdy.wait_for_falling_edge().await.is_err() {
+                continue;
+            }
+            let time = Instant::now();
+            let read = spi.read(&mut bytes[..chip.frame_len()]).await;
+            if !RECORD.load(ORD) || read.is_err() {
+                continue;
+            }
+            for sample in stream.samples(time, &bytes) {
+                bsu::send(sample).await;
+            }
+        }
+    }
+
+    /// SPI0 with its chip select, DRDY with a pull-up
+    #[cfg(feature = "rp")]
+    #[embassy_executor::task]
+    pub async fn task(spi: Spi<'static, SPI, spi::Async>, cs: AnyPin, drdy: AnyPin, config: Config, sensory: u8) {
+        let bus: Mutex<RawMutex, _> = Mutex::new(spi);
+        let device = Device::new(&bus, Output::new(cs, Level::High));
+        run(device, Input::new(drdy, Pull::Up), config, sensory).await
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use core::convert::Infallible;
+        use embassy_futures::block_on;
+        use embedded_hal_async::spi::ErrorType;
+
+        /// Register file of a chip behind the bus. Writes are
+        /// collected per transaction, reads answer RREG and frames.
+        struct Mock {
+            id: u8,
+            regs: [u8; 32],
+            /// registers ignore WREG, as with a chip that is not there
+            stuck: bool,
+            sent: std::vec::Vec<std::vec::Vec<u8>>,
+            delays: usize,
+            frame: std::vec::Vec<u8>,
+        }
+
+        impl Mock {
+            fn new(id: u8) -> Self {
+                Mock {
+                    id,
+                    regs: [0; 32],
+                    stuck: false,
+                    sent: std::vec::Vec::new(),
+                    delays: 0,
+                    frame: std::vec::Vec::new(),
+                }
+            }
+        }
+
+        impl ErrorType for Mock {
+            type Error = Infallible;
+        }
+
+        impl SpiDevice for Mock {
+            async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+                let mut written = std::vec::Vec::new();
+                for operation in operations {
+                    match operation {
+                        Operation::Write(bytes) => written.extend_from_slice(bytes),
+                        Operation::DelayNs(_) => self.delays += 1,
+                        Operation::Read(buf) => match written.first() {
+                            Some(&op) if op & 0xE0 == RREG => {
+                                let address = (op & 0x1F) as usize;
+                                buf[0] = if address == ID as usize { self.id } else { self.regs[address] };
+                            }
+                            _ => buf.copy_from_slice(&self.frame[..buf.len()]),
+                        },
+                        other => panic!("driver sent {:?} after opcode {:#04x?}", other, written.first()),
+                    }
+                }
+                if let [op, count, values @ ..] = written.as_slice() {
+                    if op & 0xE0 == WREG && !self.stuck {
+                        let start = (op & 0x1F) as usize;
+                        assert_eq!(*count as usize + 1, values.len());
+                        self.regs[start..start + values.len()].copy_from_slice(values);
+                    }
+                }
+                self.sent.push(written);
+                Ok(())
+            }
+        }
+
+        /// Status word with lead-off flags, then 24 bit counts
+        fn raw(status: u32, counts: &[i32]) -> std::vec::Vec<u8> {
+            let mut bytes = status.to_be_bytes()[1..].to_vec();
+            for count in counts {
+                bytes.extend_from_slice(&count.to_be_bytes()[1..]);
+            }
+            bytes
+        }
+
+        #[test]
+        fn setup_writes_the_configuration() {
+            let mut mock = Mock::new(0x3E);
+            let config = Config {
+                rate: Rate::Sps500,
+                ..Default::default()
+            };
+            let chip = block_on(setup(&mut mock, &config)).unwrap();
+            assert_eq!(chip, Some(Chip::Ads1299(8)));
+            let regs = Chip::Ads1299(8).registers(&config);
+            assert_eq!(mock.regs[1..1 + regs.len()], regs[..]);
+            assert_eq!(mock.regs[1] & 0x07, 0b101);
+            let commands: std::vec::Vec<_> = mock.sent.iter().filter(|s| s.len() == 1).map(|s| s[0]).collect();
+            assert_eq!(commands, [WAKEUP, RESET, SDATAC, START, RDATAC]);
+            // every byte is followed by its gap, also within WREG
+            let bytes: usize = mock.sent.iter().map(|s| s.len()).sum();
+            assert_eq!(mock.delays, bytes);
+        }
+
+        #[test]
+        fn setup_rejects_unknown_and_absent_chips() {
+            let mut mock = Mock::new(0x00);
+            assert_eq!(block_on(setup(&mut mock, &Config::default())).unwrap(), None);
+            // no START after a failed detection
+            assert!(!mock.sent.iter().any(|s| s[..] == [START]));
+
+            let mut mock = Mock::new(0x53);
+            mock.stuck = true;
+            assert_eq!(block_on(setup(&mut mock, &Config::default())).unwrap(), None);
+            assert!(!mock.sent.iter().any(|s| s[..] == [START]));
+        }
+
+        #[test]
+        fn ids_and_frame_lengths() {
+            assert_eq!(Chip::from_id(0x3C), Some(Chip::Ads1299(4)));
+            assert_eq!(Chip::from_id(0x3D), Some(Chip::Ads1299(6)));
+            assert_eq!(Chip::from_id(0x53), Some(Chip::Ads1292));
+            assert_eq!(Chip::Ads1299(8).frame_len(), MAX_FRAME);
+            assert_eq!(Chip::Ads1292.frame_len(), 9);
+            // no 24x on the 1292
+            assert_eq!(Chip::Ads1292.gain(Gain::X24), Gain::X12);
+        }
+
+        #[test]
+        fn frame_counts_are_sign_extended() {
+            let counts = [1, -1, 0x7F_FFFF, -0x80_0000, 0, 1234, -1234, 42];
+            let bytes = raw(0xC0_0000, &counts);
+            let frame = Frame::parse(Chip::Ads1299(8), &bytes).unwrap();
+            assert_eq!(frame.counts, counts);
+            assert_eq!((frame.loff_p, frame.loff_n), (0, 0));
+        }
+
+        #[test]
+        fn broken_frames_are_rejected() {
+            let bytes = raw(0x40_0000, &[0; 8]);
+            assert_eq!(Frame::parse(Chip::Ads1299(8), &bytes), None);
+            let bytes = raw(0xC0_0000, &[0; 1]);
+            assert_eq!(Frame::parse(Chip::Ads1292, &bytes), None);
+        }
+
+        #[test]
+        fn lead_off_flags_of_both_families() {
+            // ADS1299: LOFF_STATP in bits 19..12, LOFF_STATN in 11..4
+            let bytes = raw(0xC0_0000 | 0x81 << 12 | 0x02 << 4, &[0; 8]);
+            let frame = Frame::parse(Chip::Ads1299(8), &bytes).unwrap();
+            assert_eq!((frame.loff_p, frame.loff_n), (0x81, 0x02));
+            // ADS1292: IN2P and IN1N off
+            let bytes = raw(0xC0_0000 | (0b00110 << 15), &[5, -5]);
+            let frame = Frame::parse(Chip::Ads1292, &bytes).unwrap();
+            assert_eq!((frame.loff_p, frame.loff_n), (0b10, 0b01));
+            assert_eq!(frame.counts[..2], [5, -5]);
+        }
+
+        #[test]
+        fn stream_scales_and_reports_lead_off_changes() {
+            let config = Config::default();
+            let chip = Chip::Ads1299(4);
+            let mut stream = Stream::new(chip, &config, 5);
+            let time = Instant::from_millis(1);
+            let lsb = chip.lsb_uv(config.gain[0]);
+
+            let samples = stream.samples(time, &raw(0xC0_0000, &[1000, -1000, 0, 1]));
+            assert_eq!(samples.len(), 1);
+            assert_eq!(samples[0].sensory, 5);
+            let uv = samples[0].read[0].unwrap();
+            assert!((uv - 1000.0 * lsb as f64).abs() < 1e-3);
+            assert_eq!(samples[0].read[4], None);
+
+            let off = raw(0xC0_0000 | 0x01 << 12, &[0; 4]);
+            let samples = stream.samples(time, &off);
+            assert_eq!(samples.len(), 2);
+            assert_eq!(samples[1].sensory, 6);
+            assert_eq!(samples[1].read[0], Some(1.0));
+            // unchanged flags are not repeated
+            assert_eq!(stream.samples(time, &off).len(), 1);
+            assert!(stream.samples(time, &[0; MAX_FRAME]).is_empty());
+        }
+
+        #[test]
+        fn impedance_once_per_window() {
+            let config = Config {
+                mode: Mode::Impedance,
+                rate: Rate::Sps250,
+                ..Default::default()
+            };
+            let chip = Chip::Ads1292;
+            let mut stream = Stream::new(chip, &config, 0);
+            let lsb = chip.lsb_uv(chip.gain(config.gain[0])) as f64;
+            // ±600 µV swing on channel 0
+            let counts = (600.0 / lsb) as i32;
+            let mut impedance = std::vec::Vec::new();
+            for i in 0..2 * 250 {
+                let c = if i % 2 == 0 { counts } else { -counts };
+                for sample in stream.samples(Instant::from_millis(i), &raw(0xC0_0000, &[c, 0])) {
+                    if sample.sensory == 2 {
+                        impedance.push(sample);
+                    }
+                }
+            }
+            assert_eq!(impedance.len(), 2);
+            // 1200 µV peak-to-peak from 6 nA: 100 kΩ
+            let kohm = impedance[0].read[0].unwrap();
+            assert!((kohm - 100.0).abs() < 0.1, "{kohm}");
+        }
+    }
+}
+
+#[cfg(feature = "loadcell")]
+pub mod yfrc {
+    //! ## Load cells on HX711 amplifiers
+    //!
+    //! Up to four HX711 share one clock line, each with its own data
+    //! line, e.g. the four corners of a force plate. The task waits
+    //! asynchronously until all amplifiers have a conversion ready and
+    //! then shifts out all of them at once by bit-banging. The shifting
+    //! takes about 60 µs with interrupts masked, because a clock
+    //! pulse longer than 60 µs powers the HX711 down.
+    //!
+    //! Readings are sent per cell, plus the total when there is
+    //! more than one cell. Tare and two-point calibration come in
+    //! through `CONTROL` (host: `lc tare`, `lc cal <cell> <grams>`,
+    //! `lc store`) and are kept in flash (`ymem::Slot::LoadCell`).
+    use super::*;
+    use core::str::SplitWhitespace;
+    use cortex_m::asm::delay;
+    use hal::gpio::{AnyPin, Input, Level, Output, Pull};
+
+    /* control channels */
+    pub static READY: AtomicBool = AtomicBool::new(false);
+    pub static RECORD: AtomicBool = AtomicBool::new(false);
+    pub static CONTROL: Channel<RawMutex, Command, 4> = Channel::new();
+
+    pub const MAX_CELLS: usize = 4;
+    /// readings averaged for tare and calibration
+    const AVERAGE: i32 = 16;
+    /// half a clock period, 1 µs at 125 MHz
+    const HALF_CLOCK: u32 = 125;
+    /// conversions per second with RATE low, as on most boards
+    const HZ: f32 = 10.0;
+
+    /// Input channel and gain of the next conversion
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Gain {
+        A128,
+        A64,
+        B32,
+    }
+
+    impl Gain {
+        /// clock pulses after the 24 data bits
+        fn pulses(self) -> u8 {
+            match self {
+                Gain::A128 => 1,
+                Gain::B32 => 2,
+                Gain::A64 => 3,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Unit {
+        Counts,
+        Grams,
+        Newtons,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Calibration {
+        /// counts at zero load
+        pub offset: i32,
+        /// counts per gram
+        pub scale: f32,
+    }
+
+    impl Default for Calibration {
+        fn default() -> Self {
+            Calibration {
+                offset: 0,
+                scale: 1.0,
+            }
+        }
+    }
+
+    impl Calibration {
+        pub fn grams(&self, counts: i32) -> f32 {
+            (counts - self.offset) as f32 / self.scale
+        }
+
+        /// Second point of a two-point calibration, the first being the tare
+        pub fn calibrate(&mut self, counts: i32, grams: f32) {
+            if grams != 0.0 && counts != self.offset {
+                self.scale = (counts - self.offset) as f32 / grams;
+            }
+        }
+
+        const BYTES: usize = 8;
+
+        fn to_bytes(&self) -> [u8; Self::BYTES] {
+            let mut bytes = [0u8; Self::BYTES];
+            bytes[..4].copy_from_slice(&self.offset.to_le_bytes());
+            bytes[4..].copy_from_slice(&self.scale.to_le_bytes());
+            bytes
+        }
+
+        fn from_bytes(bytes: &[u8]) -> Self {
+            Calibration {
+                offset: i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                scale: f32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            }
+        }
+    }
+
+    /// Calibration of all cells from flash, defaults if there is none
+    pub async fn load() -> [Calibration; MAX_CELLS] {
+        let mut cal = [Calibration::default(); MAX_CELLS];
+        let mut buf = [0u8; Calibration::BYTES * MAX_CELLS];
+        if ymem::load(ymem::Slot::LoadCell, &mut buf).await == Some(buf.len()) {
+            for (c, bytes) in buf.chunks_exact(Calibration::BYTES).enumerate() {
+                cal[c] = Calibration::from_bytes(bytes);
+            }
+        }
+        cal
+    }
+
+    pub async fn store(cal: &[Calibration; MAX_CELLS]) -> Result<(), ymem::Error> {
+        let mut buf = [0u8; Calibration::BYTES * MAX_CELLS];
+        for (c, bytes) in buf.chunks_exact_mut(Calibration::BYTES).enumerate() {
+            bytes.copy_from_slice(&cal[c].to_bytes());
+        }
+        ymem::store(ymem::Slot::LoadCell, &buf).await
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Command {
+        /// zero all cells
+        Tare,
+        /// known load in grams on one cell
+        Calibrate(u8, f32),
+        /// write the calibration to flash
+        Store,
+    }
+
+    impl Command {
+        /// Parses the words following `lc` on a command line
+        pub fn parse(mut words: SplitWhitespace) -> Option<Command> {
+            let command = match words.next()? {
+                "tare" => Command::Tare,
+                "cal" => Command::Calibrate(words.next()?.parse().ok()?, words.next()?.parse().ok()?),
+                "store" => Command::Store,
+                _ => return None,
+            };
+            Some(command)
+        }
+    }
+
+    /// Shifts one conversion out of every amplifier and
+    /// selects the gain for the next one.
+    fn shift(sck: &mut Output<'static, AnyPin>, dout: &[Input<'static, AnyPin>], gain: Gain) -> [i32; MAX_CELLS] {
+        let mut raw = [0u32; MAX_CELLS];
+        cortex_m::interrupt::free(|_| {
+            for _ in 0..24 {
+                sck.set_high();
+                delay(HALF_CLOCK);
+                sck.set_low();
+                for (c, pin) in dout.iter().enumerate() {
+                    raw[c] = raw[c] << 1 | pin.is_high() as u32;
+                }
+                delay(HALF_CLOCK);
+            }
+            for _ in 0..gain.pulses() {
+                sck.set_high();
+                delay(HALF_CLOCK);
+                sck.set_low();
+                delay(HALF_CLOCK);
+            }
+        });
+        // sign extension of 24 bit two's complement
+        raw.map(|r| ((r << 8) as i32) >> 8)
+    }
+
+    async fn conversion(sck: &mut Output<'static, AnyPin>, dout: &mut [Input<'static, AnyPin>], gain: Gain) -> [i32; MAX_CELLS] {
+        // a conversion is ready when DOUT goes low
+        for pin in dout.iter_mut() {
+            pin.wait_for_low().await;
+        }
+        shift(sck, dout, gain)
+    }
+
+    #[embassy_executor::task]
+    pub async fn task(sck: AnyPin, dout: Vec<AnyPin, MAX_CELLS>, gain: Gain, unit: Unit, sensory: u8) {
+        let mut sck = Output::new(sck, Level::Low);
+        let mut dout: Vec<Input<'static, AnyPin>, MAX_CELLS> =
+            dout.into_iter().map(|pin| Input::new(pin, Pull::None)).collect();
+        let n = dout.len();
+        let mut cal = load().await;
+        // the first conversion still has the power-up gain
+        let _ = conversion(&mut sck, &mut dout, gain).await;
+        telemetry::report(Report::Target(sensory, HZ));
+        READY.store(true, ORD);
+        loop {
+            while let Ok(command) = CONTROL.try_receive() {
+                match command {
+                    Command::Tare | Command::Calibrate(..) => {
+                        let mut sum = [0i32; MAX_CELLS];
+                        for _ in 0..AVERAGE {
+                            let counts = conversion(&mut sck, &mut dout, gain).await;
+                            for c in 0..n {
+                                sum[c] += counts[c];
+                            }
+                        }
+                        let mean = sum.map(|s| s / AVERAGE);
+                        match command {
+                            Command::Calibrate(c, grams) if (c as usize) < n => {
+                                cal[c as usize].calibrate(mean[c as usize], grams)
+                            }
+                            Command::Tare => {
+                                for c in 0..n {
+                                    cal[c].offset = mean[c];
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    Command::Store => {
+                        if store(&cal).await.is_err() {
+                            telemetry::report(Report::Error(sensory));
+                        }
+                    }
+                }
+            }
+            let counts = conversion(&mut sck, &mut dout, gain).await;
+            let time = Instant::now();
+            if !RECORD.load(ORD) {
+                continue;
+            }
+            let mut read: YtfRead = [None; YTF_LEN];
+            let mut total = 0.0;
+            for c in 0..n {
+                let value = match unit {
+                    Unit::Counts => counts[c] as f32,
+                    Unit::Grams => cal[c].grams(counts[c]),
+                    Unit::Newtons => cal[c].grams(counts[c]) * 9.806_65e-3,
+                };
+                total += value;
+                read[c] = Some(value.into());
+            }
+            if n > 1 {
+                read[n] = Some(total.into());
+            }
+            bsu::send(Ytf { sensory, time, read }).await;
+        }
+    }
+}
+
+#[cfg(feature = "encoder")]
+pub mod yenc {
+    //! ## Quadrature encoders
+    //!
+    //! A PIO state machine decodes the A/B signals and keeps the count
+    //! in its Y register, so no edge is lost, however slow the task is.
+    //! The program is the jump table decoder from the pico-examples,
+    //! which has to sit at address 0 of its PIO block.
+    //! A and B must be neighbouring pins (B = A + 1).
+    //!
+    //! The task sends position, velocity (per second) and direction
+    //! (-1, 0, 1). An optional index pulse resets the position to zero.
+    use super::*;
+    use embassy_futures::select::{select, Either};
+    use hal::gpio::{AnyPin, Input, Pull};
+    use hal::peripherals::PIO0;
+    use hal::pio::{Common, Config as PioConfig, Direction, Instance, PioPin, ShiftConfig, ShiftDirection, StateMachine};
+    use hal::relocate::RelocatedProgram;
+
+    /* control channels */
+    pub static READY: AtomicBool = AtomicBool::new(false);
+    pub static RECORD: AtomicBool = AtomicBool::new(false);
+
+    pub type Reading = [f32; 3];
+    pub type Sample = crate::Sample<f32, 3>;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Config {
+        /// counts per unit of position, e.g. 4 x lines per revolution
+        pub counts_per_unit: f32,
+        /// flips the direction
+        pub invert: bool,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                counts_per_unit: 1.0,
+                invert: false,
+            }
+        }
+    }
+
+    pub struct Encoder<'d, P: Instance, const S: usize> {
+        sm: StateMachine<'d, P, S>,
+    }
+
+    impl<'d, P: Instance, const S: usize> Encoder<'d, P, S> {
+        pub fn new(pio: &mut Common<'d, P>, mut sm: StateMachine<'d, P, S>, pin_a: impl PioPin, pin_b: impl PioPin) -> Self {
+            use ::pio::{Assembler, InSource, JmpCondition, MovDestination, MovOperation, MovSource, OutDestination};
+            let mut a: Assembler<32> = Assembler::new();
+            let mut update = a.label();
+            let mut increment = a.label();
+            let mut increment_cont = a.label();
+            let mut decrement = a.label();
+            let mut wrap_source = a.label();
+
+            // jump table, indexed by the previous and current state of A and B
+            let table: [u8; 14] = [0, 2, 1, 0, 1, 0, 0, 2, 2, 0, 0, 1, 0, 1];
+            for action in table {
+                match action {
+                    1 => a.jmp(JmpCondition::Always, &mut increment),
+                    2 => a.jmp(JmpCondition::Always, &mut decrement),
+                    _ => a.jmp(JmpCondition::Always, &mut update),
+                }
+            }
+            // entry 14: a pure decrement of Y
+            a.bind(&mut decrement);
+            a.jmp(JmpCondition::YDecNonZero, &mut update);
+            // entry 15 and main loop: report the count
+            a.bind(&mut update);
+            a.mov(MovDestination::ISR, MovOperation::None, MovSource::Y);
+            a.push(false, false);
+            // previous state from OSR, current from the pins
+            a.out(OutDestination::ISR, 2);
+            a.r#in(InSource::PINS, 2);
+            a.mov(MovDestination::OSR, MovOperation::None, MovSource::ISR);
+            a.mov(MovDestination::PC, MovOperation::None, MovSource::ISR);
+            // there is no increment, so negate, decrement, negate
+            a.bind(&mut increment);
+            a.mov(MovDestination::Y, MovOperation::Invert, MovSource::Y);
+            a.jmp(JmpCondition::YDecNonZero, &mut increment_cont);
+            a.bind(&mut increment_cont);
+            a.mov(MovDestination::Y, MovOperation::Invert, MovSource::Y);
+            a.bind(&mut wrap_source);
+
+            let mut prg = a.assemble_with_wrap(wrap_source, update);
+            // the computed jump needs the table at address 0
+            prg.origin = Some(0);
+            let mut cfg = PioConfig::default();
+
+            // Pin config
+            let mut pin_a = pio.make_pio_pin(pin_a);
+            let mut pin_b = pio.make_pio_pin(pin_b);
+            pin_a.set_pull(Pull::Up);
+            pin_b.set_pull(Pull::Up);
+            sm.set_pin_dirs(Direction::In, &[&pin_a, &pin_b]);
+            cfg.set_in_pins(&[&pin_a, &pin_b]);
+
+            let relocated = RelocatedProgram::new(&prg);
+            cfg.use_program(&pio.load_program(&relocated), &[]);
+            cfg.shift_in = ShiftConfig {
+                auto_fill: false,
+                threshold: 32,
+                direction: ShiftDirection::Left,
+            };
+            cfg.shift_out = ShiftConfig {
+                auto_fill: false,
+                threshold: 32,
+                direction: ShiftDirection::Right,
+            };
+            // full speed, about 10 M transitions per second
+            cfg.clock_divider = fixed::types::U24F8::from_num(1);
+
+            sm.set_config(&cfg);
+            sm.set_enable(true);
+            Self { sm }
+        }
+
+        /// Current count. The state machine pushes all the time and
+        /// drops values while the FIFO is full, so the stale ones are
+        /// drained before waiting for a fresh one.
+        pub async fn count(&mut self) -> i32 {
+            while self.sm.rx().try_pull().is_some() {}
+            self.sm.rx().wait_pull().await as i32
+        }
+    }
+
+    #[embassy_executor::task]
+    pub async fn task(
+        mut encoder: Encoder<'static, PIO0, 0>,
+        index: Option<AnyPin>,
+        config: Config,
+        hz: u64,
+        sensory: u8,
+    ) {
+        let mut index = index.map(|pin| Input::new(pin, Pull::Up));
+        let sign = if config.invert { -1.0 } else { 1.0 };
+        let scale = sign / config.counts_per_unit;
+        let mut zero = encoder.count().await;
+        let mut last = (Instant::now(), zero);
+        let mut ticker = Ticker::every(Duration::from_hz(hz));
+        telemetry::report(Report::Target(sensory, hz as f32));
+        READY.store(true, ORD);
+        loop {
+            let tick = match index.as_mut() {
+                Some(index) => select(ticker.next(), index.wait_for_rising_edge()).await,
+                None => Either::First(ticker.next().await),
+            };
+            let count = encoder.count().await;
+            let time = Instant::now();
+            if let Either::Second(_) = tick {
+                zero = count;
+                continue;
+            }
+            if !RECORD.load(ORD) {
+                last = (time, count);
+                continue;
+            }
+            let dt = (time - last.0).as_micros() as f32 * 1e-6;
+            // counts wrap around, so the difference is taken in i32
+            let delta = count.wrapping_sub(last.1) as f32 * scale;
+            let velocity = if dt > 0.0 { delta / dt } else { 0.0 };
+            let direction = if delta > 0.0 {
+                1.0
+            } else if delta < 0.0 {
+                -1.0
+            } else {
+                0.0
+            };
+            last = (time, count);
+            let reading: Reading = [count.wrapping_sub(zero) as f32 * scale, velocity, direction];
+            let sample = Sample {
+                sensory,
+                time,
+                read: reading,
+            };
+            bsu::send(sample.into()).await;
+        }
+    }
+}
+
+#[cfg(feature = "logic")]
+pub mod ylog {
+    //! ## Logic analyzer capture
+    //!
+    //! A PIO state machine samples up to eight neighbouring pins at a
+    //! fixed rate (up to tens of MHz) and DMA moves the samples into a
+    //! buffer. Each buffer is a capture window, which is compressed
+    //! into edge events afterwards. Edges are exact to one sample period.
+    //! Two buffers take turns: while one is compressed and sent,
+    //! DMA fills the other, so windows follow each other without
+    //! a gap as long as `RECORD` is set and sending keeps up.
+    //! Samples lost when it does not are marked in the stream.
+    //!
+    //! Optionally, capture waits for a trigger level on one
+    //! of the pins, e.g. a stimulus onset.
+    //!
+    //! Edge events are sent with read `[t, level, changed]`, where
+    //! `t` is the time since window start in µs (with fractions),
+    //! `level` the state of all pins and `changed` the bit mask of pins
+    //! that switched. The first event of every window has all pins
+    //! marked as changed, to give the initial levels.
+    //!
+    //! Gaps are sent on `sensory + 1` with read `[gap]`, the
+    //! time without samples in µs, starting at the event time.
+    use super::*;
+    use embassy_futures::join::join;
+    use hal::dma::{AnyChannel, Channel as DmaChannel, Transfer};
+    use hal::peripherals::PIO1;
+    use hal::pio::{Common, Config as PioConfig, FifoJoin, Instance, Pin, ShiftConfig, ShiftDirection, StateMachine};
+    use hal::relocate::RelocatedProgram;
+    use hal::{clocks, into_ref, Peripheral, PeripheralRef};
+    use static_cell::StaticCell;
+
+    /* control channels */
+    pub static READY: AtomicBool = AtomicBool::new(false);
+    pub static RECORD: AtomicBool = AtomicBool::new(false);
+
+    /// Words per window, four samples each
+    pub const WINDOW: usize = 2048;
+    /// Samples the RX FIFO (8 words) holds until DMA picks them up
+    const FIFO_SAMPLES: u64 = 8 * 4;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Trigger {
+        /// pin relative to the first captured pin
+        pub pin: u8,
+        pub level: bool,
+    }
+
+    pub struct Capture<'d, P: Instance, const S: usize> {
+        dma: PeripheralRef<'d, AnyChannel>,
+        sm: StateMachine<'d, P, S>,
+        origin: u8,
+        rate: u32,
+        mask: u8,
+    }
+
+    impl<'d, P: Instance, const S: usize> Capture<'d, P, S> {
+        /// Samples `n` pins from `base` at `rate` Hz
+        pub fn new(
+            pio: &mut Common<'d, P>,
+            mut sm: StateMachine<'d, P, S>,
+            dma: impl Peripheral<P = impl DmaChannel> + 'd,
+            base: &Pin<'d, P>,
+            n: u8,
+            rate: u32,
+            trigger: Option<Trigger>,
+        ) -> Self {
+            into_ref!(dma);
+            let mut a: ::pio::Assembler<32> = ::pio::Assembler::new();
+            let mut wrap_target = a.label();
+            let mut wrap_source = a.label();
+            if let Some(trigger) = trigger {
+                a.wait(trigger.level as u8, ::pio::WaitSource::PIN, trigger.pin, false);
+            }
+            a.bind(&mut wrap_target);
+            // always a full byte, so four samples fill a word
+            a.r#in(::pio::InSource::PINS, 8);
+            a.bind(&mut wrap_source);
+            let prg = a.assemble_with_wrap(wrap_source, wrap_target);
+
+            let mut cfg = PioConfig::default();
+            cfg.set_in_pins(&[base]);
+            let relocated = RelocatedProgram::new(&prg);
+            let loaded = pio.load_program(&relocated);
+            let origin = loaded.origin;
+            cfg.use_program(&loaded, &[]);
+            // first sample ends up in the lowest byte
+            cfg.shift_in = ShiftConfig {
+                auto_fill: true,
+                threshold: 32,
+                direction: ShiftDirection::Right,
+            };
+            cfg.fifo_join = FifoJoin::RxOnly;
+            // one instruction per sample
+            let divider = (clocks::clk_sys_freq() as f32 / rate as f32).max(1.0);
+            cfg.clock_divider = fixed::types::U24F8::from_num(divider);
+            sm.set_config(&cfg);
+            let rate = (clocks::clk_sys_freq() as f32 / cfg.clock_divider.to_num::<f32>()) as u32;
+            Self {
+                dma: dma.map_into(),
+                sm,
+                origin,
+                rate,
+                mask: ((1u16 << n.min(8)) - 1) as u8,
+            }
+        }
+
+        /// Actual sample rate after rounding the clock divider
+        pub fn rate(&self) -> u32 {
+            self.rate
+        }
+
+        /// Duration of `words` sampled words
+        pub fn duration(&self, words: usize) -> Duration {
+            Duration::from_micros(words as u64 * 4 * 1_000_000 / self.rate as u64)
+        }
+
+        /// (Re)starts sampling, after the trigger if there is one.
+        /// From now on, samples wait in the FIFO for `pull`.
+        pub fn start(&mut self) {
+            self.sm.set_enable(false);
+            self.sm.clear_fifos();
+            self.sm.restart();
+            unsafe { self.sm.exec_jmp(self.origin) };
+            let _ = self.sm.rx().stalled();
+            self.sm.set_enable(true);
+        }
+
+        pub fn stop(&mut self) {
+            self.sm.set_enable(false);
+        }
+
+        /// Starts moving the next window into `buf` right away,
+        /// the returned transfer completes when it is full
+        pub fn pull<'b>(&'b mut self, buf: &'b mut [u32]) -> Transfer<'b, AnyChannel> {
+            self.sm.rx().dma_pull(self.dma.reborrow(), buf)
+        }
+
+        /// Whether samples were lost since the last call, because
+        /// the FIFO ran full while no transfer was running
+        pub fn overrun(&mut self) -> bool {
+            self.sm.rx().stalled()
+        }
+    }
+
+    /// ### Edge compression
+    ///
+    /// Iterates over the first sample of a window and every sample
+    /// that differs from the one before, as `(index, level, changed)`.
+    pub struct Edges<'a> {
+        words: &'a [u32],
+        mask: u8,
+        index: usize,
+        last: Option<u8>,
+    }
+
+    impl<'a> Edges<'a> {
+        pub fn new(words: &'a [u32], mask: u8) -> Self {
+            Edges {
+                words,
+                mask,
+                index: 0,
+                last: None,
+            }
+        }
+    }
+
+    impl<'a> Iterator for Edges<'a> {
+        type Item = (usize, u8, u8);
+
+        fn next(&mut self) -> Option<Self::Item> {
+            while self.index < 4 * self.words.len() {
+                let i = self.index;
+                self.index += 1;
+                let level = (self.words[i / 4] >> (8 * (i % 4))) as u8 & self.mask;
+                let changed = match self.last.replace(level) {
+                    Some(last) => level ^ last,
+                    None => self.mask,
+                };
+                if changed != 0 {
+                    return Some((i, level, changed));
+                }
+            }
+            None
+        }
+    }
+
+    /// Edge events of one window, then the gap after it, if any
+    async fn send(words: &[u32], mask: u8, start: Instant, period_us: f64, gap: Option<(Instant, Duration)>, sensory: u8) {
+        for (i, level, changed) in Edges::new(words, mask) {
+            let t = i as f64 * period_us;
+            let mut read: YtfRead = [None; YTF_LEN];
+            read[0] = Some(t);
+            read[1] = Some(level.into());
+            read[2] = Some(changed.into());
+            let time = start + Duration::from_micros(t as u64);
+            bsu::send(Ytf { sensory, time, read }).await;
+        }
+        if let Some((time, gap)) = gap {
+            let sample = Sample {
+                sensory: sensory + 1,
+                time,
+                read: [gap.as_micros() as f64],
+            };
+            bsu::send(sample.into()).await;
+        }
+    }
+
+    /// The two windows, 16 KiB, which would not fit
+    /// into the task future next to all other tasks
+    static WINDOWS: StaticCell<[[u32; WINDOW]; 2]> = StaticCell::new();
+
+    #[embassy_executor::task]
+    pub async fn task(mut capture: Capture<'static, PIO1, 0>, sensory: u8) {
+        // zeroed in place, a value built first would go through the stack
+        let bufs = unsafe {
+            let bufs = WINDOWS.uninit();
+            bufs.as_mut_ptr().write_bytes(0, 1);
+            bufs.assume_init_mut()
+        };
+        let period_us = 1e6 / capture.rate() as f64;
+        let window = capture.duration(WINDOW);
+        let fifo = Duration::from_micros(FIFO_SAMPLES * 1_000_000 / capture.rate() as u64);
+        let mask = capture.mask;
+        // edges are sent, not samples, so the sent rate stays below
+        telemetry::report(Report::Target(sensory, capture.rate() as f32));
+        READY.store(true, ORD);
+        loop {
+            if !RECORD.load(ORD) {
+                time::Timer::after(Duration::from_millis(10)).await;
+                continue;
+            }
+            let (first, second) = bufs.split_at_mut(1);
+            let (mut front, mut back) = (&mut first[0], &mut second[0]);
+            capture.start();
+            capture.pull(front).await;
+            let mut start = Instant::now() - window;
+            while RECORD.load(ORD) {
+                // a stall can only happen between two transfers,
+                // so whatever was lost is right before this window
+                let lost = capture.overrun();
+                let now = Instant::now();
+                let next = capture.pull(back);
+                let end = start + window;
+                let (next_start, gap) = if lost {
+                    let resumed = now - fifo;
+                    (resumed, Some((end, resumed.checked_duration_since(end).unwrap_or_default())))
+                } else {
+                    (end, None)
+                };
+                join(next, send(front, mask, start, period_us, gap, sensory)).await;
+                start = next_start;
+                core::mem::swap(&mut front, &mut back);
+            }
+            capture.stop();
+            send(front, mask, start, period_us, None, sensory).await;
         }
     }
 }
@@ -2,11 +2,11 @@ pub use super::*;
 pub use core::fmt::Write;
 
 //pub type Ytf = Sample<[Option<f32>; 8]>; // standard transport format
-type YtfLine = Vec<u8, 512>;
+pub(crate) type YtfLine = Vec<u8, 512>;
 
-trait YtfSend{
+/// The lines of the transports, shared with `ysim::host`
+pub(crate) trait YtfSend{
     fn msg_csv(&self) -> Result<YtfLine, core::fmt::Error>;
-    fn msg_bin(&self) -> Result<YtfLine, core::fmt::Error>;
 }
 
 impl YtfSend for Ytf {
@@ -17,29 +17,84 @@ impl YtfSend for Ytf {
             Err(e) => return Err(e)
        }
     }
-
-    fn msg_bin(&self) -> Result<YtfLine, core::fmt::Error>{
-        todo!()
-    }
 }
 
 
 
 pub mod bsu {
     use super::*;
-    use hal::bind_interrupts;
-    use hal::peripherals::USB;
-    use hal::usb::{Driver, InterruptHandler};
-    use log::LevelFilter;
-    use embassy_usb_logger::*;
+    #[cfg(any(feature = "serial", feature = "logger"))]
+    use hal::{bind_interrupts, peripherals::USB, usb::{Driver, InterruptHandler}};
+    #[cfg(feature = "logger")]
+    use {embassy_usb_logger::*, log::LevelFilter};
+
+    /// Samples on their way out. Room for a few ms of all sensors,
+    /// so short stalls of the transport do not hold them up.
+    pub static SINK: Channel<RawMutex, Ytf, 16> = Channel::new();
+    /// Copies of what goes out, for views on the device (`yuio::graph`).
+    /// Copies are dropped while the queue is full, so nobody has to read.
+    pub static TAP: Channel<RawMutex, Ytf, 4> = Channel::new();
+    /// Answers to host commands and log records (`ReplyLog`),
+    /// sent as lines starting with `#`
+    pub type Reply = String<160>;
+    pub static REPLY: Channel<RawMutex, Reply, 4> = Channel::new();
+
+    /// Log records as `#` lines, for binaries running `serial_task`
+    /// instead of `logger_task`. Logging cannot wait, so records
+    /// are dropped while `REPLY` is full.
+    pub struct ReplyLog;
+
+    impl log::Log for ReplyLog {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let mut reply = Reply::new();
+            // overlong records are cut off
+            let _ = write!(reply, "{} {}", record.level(), record.args());
+            let _ = REPLY.try_send(reply);
+        }
 
-    pub static SINK: Channel<RawMutex, Ytf, 3> = Channel::new();
+        fn flush(&self) {}
+    }
 
+    /// Installs `ReplyLog` up to `level`. Call once, before the executors run.
+    pub fn log_to_reply(level: log::LevelFilter) {
+        static LOG: ReplyLog = ReplyLog;
+        // the M0+ has no compare-and-swap for `set_logger`, and
+        // nothing logs yet, so the racy setters are fine here
+        unsafe {
+            let _ = log::set_logger_racy(&LOG);
+            log::set_max_level_racy(level);
+        }
+    }
+
+    /// Sends a sample to `SINK`, as every producer does. While `SINK`
+    /// is full, the producer waits, and the time it waited goes to
+    /// `telemetry` (`Report::Waited`), which is where a sensor falls
+    /// behind its rate. A copy goes to the HID rules right away,
+    /// so they do not wait for the transport and fire without a host reading.
+    pub async fn send(sample: Ytf) {
+        #[cfg(feature = "serial")]
+        let _ = hid::SAMPLES.try_send(sample);
+        if SINK.try_send(sample).is_err() {
+            let start = Instant::now();
+            SINK.send(sample).await;
+            let waited = (Instant::now() - start).as_micros() as u32;
+            telemetry::report(telemetry::Report::Waited(sample.sensory, waited));
+        }
+    }
+
+    // shared by all USB transports, only one of them can run
+    #[cfg(any(feature = "serial", feature = "logger"))]
+    bind_interrupts!(struct Irqs {
+        USBCTRL_IRQ => InterruptHandler<USB>;
+    });
+
+    #[cfg(feature = "logger")]
     #[embassy_executor::task]
     pub async fn logger_task(usb: USB, level: LevelFilter) {
-        bind_interrupts!(struct Irqs {
-            USBCTRL_IRQ => InterruptHandler<USB>;
-        }); 
         let driver = Driver::new(usb, Irqs);
         run!(1024, level, driver);
     }
@@ -48,10 +103,701 @@ pub mod bsu {
     pub async fn task() {
         loop {
             let sample: Ytf = SINK.receive().await;
+            let _ = TAP.try_send(sample);
+            telemetry::report(telemetry::Report::Sent(sample.sensory));
             log::info!("{}", sample);
             //time::Timer::after_nanos(500).await;
         }
     }
 
+    /// ## Serial transport with host commands
+    ///
+    /// Replaces `logger_task` and `task`. Opens a USB serial port
+    /// which sends `SINK` as CSV lines and reads command lines
+    /// coming from the host (see `cmd`). As long as no host
+    /// has the port open, samples are dropped.
+    ///
+    /// With `hid`, the device also shows up as a keyboard or gamepad,
+    /// driven by `hid::INPUT`, the board buttons and the rules in `hid::RULES`.
+    #[cfg(feature = "serial")]
+    #[embassy_executor::task]
+    pub async fn serial_task(usb: USB, hid: Option<hid::Kind>) {
+        use core::future::pending;
+        use embassy_futures::join::{join, join4};
+        use embassy_futures::select::{select, Either};
+        use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+        use embassy_usb::class::hid::{HidWriter, State as HidState};
+        use embassy_usb::{Builder, Config};
+
+        let driver = Driver::new(usb, Irqs);
+        let mut config = Config::new(0xc0de, 0xcafe);
+        config.manufacturer = Some("YLab");
+        config.product = Some("YLab Edge Go");
+        config.serial_number = None;
+        config.max_power = 100;
+        config.max_packet_size_0 = 64;
+        // Required for windows compatibility.
+        config.device_class = 0xEF;
+        config.device_sub_class = 0x02;
+        config.device_protocol = 0x01;
+        config.composite_with_iads = true;
+
+        let mut config_descriptor = [0; 256];
+        let mut bos_descriptor = [0; 256];
+        let mut control_buf = [0; 64];
+        let mut state = State::new();
+        let mut hid_state = HidState::new();
+        let mut builder = Builder::new(
+            driver,
+            config,
+            &mut config_descriptor,
+            &mut bos_descriptor,
+            &mut [],
+            &mut control_buf,
+        );
+        let class = CdcAcmClass::new(&mut builder, &mut state, 64);
+        let writer = hid.map(|kind| HidWriter::<_, 8>::new(&mut builder, &mut hid_state, kind.config()));
+        let mut usb = builder.build();
+        let (mut tx, mut rx) = class.split();
+
+        let keys = async {
+            match (writer, hid) {
+                (Some(writer), Some(kind)) => {
+                    join(hid::run(writer, kind), hid::rules()).await;
+                }
+                _ => pending().await,
+            }
+        };
+        let send = async {
+            use telemetry::Report;
+            let mut usb_connected = false;
+            loop {
+                let sample: Ytf = match select(SINK.receive(), REPLY.receive()).await {
+                    Either::First(sample) => sample,
+                    Either::Second(reply) => {
+                        let mut msg: YtfLine = Vec::new();
+                        if tx.dtr() && write!(msg, "# {}\r\n", reply).is_ok() {
+                            let _ = write_line(&mut tx, &msg).await;
+                        }
+                        continue;
+                    }
+                };
+                let _ = TAP.try_send(sample);
+                let connected = tx.dtr();
+                if connected != usb_connected {
+                    usb_connected = connected;
+                    telemetry::report(Report::Usb(connected));
+                }
+                if !connected {
+                    telemetry::report(Report::Dropped(sample.sensory));
+                    continue;
+                }
+                let sent = match sample.msg_csv() {
+                    Ok(mut msg) => msg.extend_from_slice(b"\r\n").is_ok() && write_line(&mut tx, &msg).await.is_ok(),
+                    Err(_) => false,
+                };
+                telemetry::report(if sent { Report::Sent(sample.sensory) } else { Report::Dropped(sample.sensory) });
+            }
+        };
+        let receive = async {
+            loop {
+                rx.wait_connection().await;
+                let _ = read_lines(&mut rx).await;
+            }
+        };
+        join4(usb.run(), send, receive, keys).await;
+    }
+
+    #[cfg(feature = "serial")]
+    use embassy_usb::class::cdc_acm::{Receiver, Sender};
+    #[cfg(feature = "serial")]
+    use embassy_usb::driver::EndpointError;
+
+    #[cfg(feature = "serial")]
+    async fn write_line<'d>(
+        tx: &mut Sender<'d, Driver<'d, USB>>,
+        msg: &[u8],
+    ) -> Result<(), EndpointError> {
+        let size = tx.max_packet_size() as usize;
+        for packet in msg.chunks(size) {
+            tx.write_packet(packet).await?;
+        }
+        // a full last packet needs a zero-length packet to be flushed
+        if msg.len() % size == 0 {
+            tx.write_packet(&[]).await?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "serial")]
+    async fn read_lines<'d>(rx: &mut Receiver<'d, Driver<'d, USB>>) -> Result<(), EndpointError> {
+        let mut buf = [0u8; 64];
+        let mut line: Vec<u8, { cmd::MAX_LINE }> = Vec::new();
+        loop {
+            let n = rx.read_packet(&mut buf).await?;
+            for &byte in &buf[..n] {
+                match byte {
+                    b'\r' | b'\n' => {
+                        if let Ok(text) = core::str::from_utf8(&line) {
+                            match cmd::parse(text) {
+                                Some(command) => cmd::dispatch(command).await,
+                                None if !text.is_empty() => log::warn!("Unknown command: {}", text),
+                                None => {}
+                            }
+                        }
+                        line.clear();
+                    }
+                    // overlong lines are cut off
+                    _ => {
+                        let _ = line.push(byte);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// ## Telemetry
+///
+/// How the recording goes, as counters per sensory. Sensor tasks
+/// report their target rate and failed reads, the transport reports
+/// what it sent or dropped and whether a host is connected.
+/// Reports are counted right away, so they cost a critical section
+/// and never block. `yuio::graph` shows them on its dashboard page.
+pub mod telemetry {
+    use super::*;
+    use core::cell::RefCell;
+    use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+
+    pub const MAX_SENSORIES: usize = 8;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Report {
+        /// sensory and its sample rate in Hz
+        Target(u8, f32),
+        /// a sample of the sensory went out
+        Sent(u8),
+        /// a sample of the sensory was thrown away
+        Dropped(u8),
+        /// a sample of the sensory waited for room in `SINK`, µs
+        Waited(u8, u32),
+        /// a read failed
+        Error(u8),
+        /// host connected or not
+        Usb(bool),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    pub struct Counts {
+        /// 0 when the sensory never reported
+        pub target: f32,
+        pub sent: u32,
+        pub dropped: u32,
+        pub errors: u32,
+        /// µs spent waiting for `SINK`, wraps
+        pub waited: u32,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    pub struct Snapshot {
+        pub sensories: [Counts; MAX_SENSORIES],
+        pub usb: bool,
+    }
+
+    static STATE: BlockingMutex<RawMutex, RefCell<Snapshot>> = BlockingMutex::new(RefCell::new(Snapshot {
+        sensories: [Counts { target: 0.0, sent: 0, dropped: 0, errors: 0, waited: 0 }; MAX_SENSORIES],
+        usb: false,
+    }));
+
+    pub fn report(report: Report) {
+        STATE.lock(|state| {
+            let mut state = state.borrow_mut();
+            let sensory = match report {
+                Report::Usb(connected) => {
+                    state.usb = connected;
+                    return;
+                }
+                Report::Target(s, _)
+                | Report::Sent(s)
+                | Report::Dropped(s)
+                | Report::Error(s)
+                | Report::Waited(s, _) => s,
+            };
+            let Some(c) = state.sensories.get_mut(sensory as usize) else { return };
+            match report {
+                Report::Target(_, hz) => c.target = hz,
+                Report::Sent(_) => c.sent = c.sent.wrapping_add(1),
+                Report::Dropped(_) => c.dropped = c.dropped.wrapping_add(1),
+                Report::Error(_) => c.errors = c.errors.wrapping_add(1),
+                Report::Waited(_, us) => c.waited = c.waited.wrapping_add(us),
+                Report::Usb(_) => {}
+            }
+        })
+    }
+
+    pub fn snapshot() -> Snapshot {
+        STATE.lock(|state| *state.borrow())
+    }
+}
+
+/// ## USB HID response device
+///
+/// Experiment software that reads keys (PsychoPy, OpenSesame)
+/// can use the board as keyboard or gamepad, next to the data stream.
+/// Key presses come from three sources:
+///
+/// + any task sending to `INPUT`
+/// + the buttons of `yuii::keys`, as keys 1-3 or gamepad buttons 1-3
+/// + `RULES`, which watch the samples sent by `bsu::send` and press
+///   while a value is above a threshold, e.g. a `moi` pin or a force
+///   sensor. Rules run next to the reports (`rules`), on copies
+///   taken before `SINK`, so the serial port does not delay them.
+///
+/// Rules can be set by the host, e.g. `hid map 0 2 0.5 44`
+/// presses the space bar while `moi` pin 2 is high.
+#[cfg(feature = "serial")]
+pub mod hid {
+    use super::*;
+    use core::str::SplitWhitespace;
+    use embassy_usb::class::hid::{Config, HidWriter};
+    use hal::peripherals::USB;
+    use hal::usb::Driver;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Kind {
+        /// boot keyboard, usages are key codes
+        Keyboard,
+        /// 16 buttons, usages are button numbers 1-16
+        Gamepad,
+    }
+
+    const KEYBOARD: &[u8] = &[
+        0x05, 0x01, 0x09, 0x06, 0xA1, 0x01, // Generic Desktop, Keyboard, Collection
+        0x05, 0x07, 0x19, 0xE0, 0x29, 0xE7, 0x15, 0x00, 0x25, 0x01, // modifiers
+        0x75, 0x01, 0x95, 0x08, 0x81, 0x02, //
+        0x95, 0x01, 0x75, 0x08, 0x81, 0x01, // reserved byte
+        0x95, 0x06, 0x75, 0x08, 0x15, 0x00, 0x25, 0x65, // six key codes
+        0x05, 0x07, 0x19, 0x00, 0x29, 0x65, 0x81, 0x00, //
+        0xC0,
+    ];
+
+    const GAMEPAD: &[u8] = &[
+        0x05, 0x01, 0x09, 0x05, 0xA1, 0x01, // Generic Desktop, Gamepad, Collection
+        0x05, 0x09, 0x19, 0x01, 0x29, 0x10, 0x15, 0x00, 0x25, 0x01, // 16 buttons
+        0x75, 0x01, 0x95, 0x10, 0x81, 0x02, //
+        0xC0,
+    ];
+
+    impl Kind {
+        /// Usage of a button of `yuii::keys`: `1`, `2`, `3`
+        /// on the keyboard, buttons 1-3 of the gamepad
+        pub fn button(self, button: u8) -> u8 {
+            match self {
+                Kind::Keyboard => 0x1E + button,
+                Kind::Gamepad => 1 + button,
+            }
+        }
+
+        pub fn config(self) -> Config<'static> {
+            Config {
+                report_descriptor: match self {
+                    Kind::Keyboard => KEYBOARD,
+                    Kind::Gamepad => GAMEPAD,
+                },
+                request_handler: None,
+                // the host polls every ms
+                poll_ms: 1,
+                max_packet_size: 8,
+            }
+        }
+    }
+
+    /// Key code or button number, pressed or released
+    pub static INPUT: Channel<RawMutex, (u8, bool), 8> = Channel::new();
+    /// Buttons of `yuii::keys`, counted from 0, pressed or released
+    pub static BUTTONS: Channel<RawMutex, (u8, bool), 8> = Channel::new();
+    /// Copies of the samples for `rules`, dropped while it is busy
+    pub static SAMPLES: Channel<RawMutex, Ytf, 8> = Channel::new();
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Rule {
+        pub sensory: u8,
+        pub channel: u8,
+        pub threshold: f64,
+        pub usage: u8,
+    }
+
+    pub const MAX_RULES: usize = 8;
+    /// Rules and whether they are pressed right now
+    pub static RULES: Mutex<RawMutex, Vec<(Rule, bool), MAX_RULES>> = Mutex::new(Vec::new());
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Command {
+        Map(Rule),
+        Clear,
+    }
+
+    impl Command {
+        /// Parses the words following `hid` on a command line
+        pub fn parse(mut words: SplitWhitespace) -> Option<Command> {
+            let command = match words.next()? {
+                "map" => Command::Map(Rule {
+                    sensory: words.next()?.parse().ok()?,
+                    channel: words.next()?.parse().ok()?,
+                    threshold: words.next()?.parse().ok()?,
+                    usage: words.next()?.parse().ok()?,
+                }),
+                "clear" => Command::Clear,
+                _ => return None,
+            };
+            Some(command)
+        }
+    }
+
+    pub async fn apply(command: Command) {
+        let mut rules = RULES.lock().await;
+        match command {
+            Command::Map(rule) => {
+                if rules.push((rule, false)).is_err() {
+                    log::warn!("HID: too many rules");
+                }
+            }
+            Command::Clear => {
+                for (rule, pressed) in rules.iter() {
+                    if *pressed {
+                        let _ = INPUT.try_send((rule.usage, false));
+                    }
+                }
+                rules.clear();
+            }
+        }
+    }
+
+    /// Runs a sample past the rules, pressing and
+    /// releasing when a value crosses its threshold.
+    pub async fn check(sample: &Ytf) {
+        let mut rules = RULES.lock().await;
+        for (rule, pressed) in rules.iter_mut() {
+            if rule.sensory != sample.sensory {
+                continue;
+            }
+            let Some(Some(value)) = sample.read.get(rule.channel as usize) else {
+                continue;
+            };
+            let above = *value > rule.threshold;
+            if above != *pressed {
+                *pressed = above;
+                let _ = INPUT.try_send((rule.usage, above));
+            }
+        }
+    }
+
+    /// Checks the rules on every copy in `SAMPLES`
+    pub async fn rules() -> ! {
+        loop {
+            let sample = SAMPLES.receive().await;
+            check(&sample).await;
+        }
+    }
+
+    /// Turns `INPUT` and `BUTTONS` into reports
+    pub async fn run<'d>(mut writer: HidWriter<'d, Driver<'d, USB>, 8>, kind: Kind) {
+        use embassy_futures::select::{select, Either};
+        let mut keys: Vec<u8, 6> = Vec::new();
+        let mut buttons: u16 = 0;
+        loop {
+            let (usage, pressed) = match select(INPUT.receive(), BUTTONS.receive()).await {
+                Either::First(input) => input,
+                Either::Second((button, pressed)) => (kind.button(button), pressed),
+            };
+            let report: Vec<u8, 8> = match kind {
+                Kind::Keyboard => {
+                    keys.retain(|k| *k != usage);
+                    if pressed {
+                        // rollover drops the oldest key
+                        if keys.is_full() {
+                            keys.remove(0);
+                        }
+                        let _ = keys.push(usage);
+                    }
+                    let mut report = [0u8; 8];
+                    report[2..2 + keys.len()].copy_from_slice(&keys);
+                    Vec::from_slice(&report).unwrap()
+                }
+                Kind::Gamepad => {
+                    let bit = 1u16 << (usage.clamp(1, 16) - 1);
+                    if pressed {
+                        buttons |= bit;
+                    } else {
+                        buttons &= !bit;
+                    }
+                    Vec::from_slice(&buttons.to_le_bytes()).unwrap()
+                }
+            };
+            writer.ready().await;
+            let _ = writer.write(&report).await;
+        }
+    }
+}
+
+/// ## Replay of recordings
+///
+/// Plays back YTF records into `SINK`, under the sensory they were
+/// recorded with and with their original timing, scaled by the speed:
+/// 2 plays twice as fast, 0 as fast as `SINK` takes them. Samples
+/// go out with the time they are played at, on the clock of the device.
+///
+/// Records come in as `Command::Record`, so they can be streamed from
+/// the host, one CSV line as sent by the transports after `play`:
+///
+/// ```text
+/// sed 's/^/play /' recording.csv > /dev/ttyACM0
+/// ```
+///
+/// As commands wait for room in `CONTROL`, the host is slowed down
+/// to the pace of the playback. In the simulation, `ysim::replay`
+/// reads CSV and binary (`Ytf::to_bytes`) recordings from files.
+///
+/// The first record starts the clock, as does a record older than
+/// the first (a new recording), `play reset` and every change of speed.
+pub mod replay {
+    use super::*;
+    use super::bsu;
+    use core::str::SplitWhitespace;
+    use embassy_time::Timer;
+
+    pub static READY: AtomicBool = AtomicBool::new(false);
+    pub static RECORD: AtomicBool = AtomicBool::new(false);
+    pub static CONTROL: Channel<RawMutex, Command, 16> = Channel::new();
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Command {
+        Record(Ytf),
+        Speed(f32),
+        Reset,
+    }
+
+    impl Command {
+        /// Parses the words following `play` on a command line
+        pub fn parse(mut words: SplitWhitespace) -> Option<Command> {
+            match words.next()? {
+                "speed" => {
+                    let speed: f32 = words.next()?.parse().ok()?;
+                    (speed >= 0.0).then_some(Command::Speed(speed))
+                }
+                "reset" => Some(Command::Reset),
+                // CSV has no spaces within a field
+                first => {
+                    let mut line: String<{ cmd::MAX_LINE }> = String::new();
+                    line.push_str(first).ok()?;
+                    for word in words {
+                        line.push_str(word).ok()?;
+                    }
+                    line.parse().ok().map(Command::Record)
+                }
+            }
+        }
+    }
+
+    /// Maps the times of a recording to the clock of the device
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Player {
+        speed: f32,
+        /// first record, and when it was played
+        origin: Option<(Instant, Instant)>,
+    }
+
+    impl Player {
+        pub fn new(speed: f32) -> Player {
+            Player { speed, origin: None }
+        }
+
+        pub fn speed(&mut self, speed: f32) {
+            self.speed = speed;
+            self.origin = None;
+        }
+
+        pub fn reset(&mut self) {
+            self.origin = None;
+        }
+
+        /// When to play a record made at `recorded`
+        pub fn due(&mut self, recorded: Instant, now: Instant) -> Instant {
+            if self.speed <= 0.0 {
+                return now;
+            }
+            let (first, start) = match self.origin {
+                Some((first, start)) if recorded >= first => (first, start),
+                _ => {
+                    self.origin = Some((recorded, now));
+                    (recorded, now)
+                }
+            };
+            let offset = (recorded - first).as_micros() as f64 / self.speed as f64;
+            start + Duration::from_micros(offset as u64)
+        }
+    }
+
+    #[embassy_executor::task]
+    pub async fn task(speed: f32) {
+        let mut player = Player::new(speed);
+        READY.store(true, ORD);
+        loop {
+            match CONTROL.receive().await {
+                Command::Record(record) => {
+                    let at = player.due(record.time, Instant::now());
+                    Timer::at(at).await;
+                    if RECORD.load(ORD) {
+                        bsu::send(Ytf { time: at, ..record }).await;
+                    }
+                }
+                Command::Speed(speed) => player.speed(speed),
+                Command::Reset => player.reset(),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn at(ms: u64) -> Instant {
+            Instant::from_millis(ms)
+        }
+
+        #[test]
+        fn speed() {
+            let mut player = Player::new(2.0);
+            assert_eq!(player.due(at(1000), at(50)), at(50));
+            assert_eq!(player.due(at(1400), at(60)), at(250));
+            assert_eq!(player.due(at(3000), at(70)), at(1050));
+            player.speed(0.5);
+            assert_eq!(player.due(at(3100), at(2000)), at(2000));
+            assert_eq!(player.due(at(3200), at(2000)), at(2200));
+        }
+
+        #[test]
+        fn older_record_restarts() {
+            let mut player = Player::new(1.0);
+            assert_eq!(player.due(at(5000), at(0)), at(0));
+            assert_eq!(player.due(at(6000), at(10)), at(1000));
+            // a new recording, from its own start
+            assert_eq!(player.due(at(1000), at(1500)), at(1500));
+            assert_eq!(player.due(at(1250), at(1510)), at(1750));
+            player.reset();
+            assert_eq!(player.due(at(1500), at(3000)), at(3000));
+        }
+
+        #[test]
+        fn speed_zero() {
+            let mut player = Player::new(0.0);
+            assert_eq!(player.due(at(1000), at(5)), at(5));
+            assert_eq!(player.due(at(9000), at(6)), at(6));
+            assert_eq!(player.due(at(0), at(7)), at(7));
+        }
+    }
+}
+
+/// ## Host commands
+///
+/// Commands arrive as text lines, starting with the module
+/// they are meant for, e.g.
+///
+/// ```text
+/// co2 frc 420
+/// co2 mode lowpower
+/// lc cal 0 500
+/// rt onset 12
+/// hid map 0 2 0.5 44
+/// pr add led 200000 800000
+/// bz stim 1000 50
+/// cfg set rate 1 200
+/// play 1250000, 2,0.125,-0.500,9.810,,,,,
+/// ```
+///
+/// Every module that takes commands parses its own part of the
+/// line and owns a `CONTROL` channel the command is forwarded to.
+pub mod cmd {
+    use super::*;
+
+    /// Longer lines are cut off
+    pub const MAX_LINE: usize = 160;
+
+    pub enum Command {
+        #[cfg(feature = "co2")]
+        Co2(ysns::yco2::Command),
+        #[cfg(feature = "loadcell")]
+        LoadCell(ysns::yfrc::Command),
+        Response(yuii::rbx::Command),
+        #[cfg(feature = "serial")]
+        Hid(hid::Command),
+        Protocol(yuio::stim::Command),
+        Buzzer(yuio::buzzer::Command),
+        Config(ycfg::Command),
+        Replay(replay::Command),
+    }
+
+    pub fn parse(line: &str) -> Option<Command> {
+        let mut words = line.split_whitespace();
+        match words.next()? {
+            #[cfg(feature = "co2")]
+            "co2" => ysns::yco2::Command::parse(words).map(Command::Co2),
+            #[cfg(feature = "loadcell")]
+            "lc" => ysns::yfrc::Command::parse(words).map(Command::LoadCell),
+            "rt" => yuii::rbx::Command::parse(words).map(Command::Response),
+            #[cfg(feature = "serial")]
+            "hid" => hid::Command::parse(words).map(Command::Hid),
+            "pr" => yuio::stim::Command::parse(words).map(Command::Protocol),
+            "bz" => yuio::buzzer::Command::parse(words).map(Command::Buzzer),
+            "cfg" => ycfg::Command::parse(words).map(Command::Config),
+            "play" => replay::Command::parse(words).map(Command::Replay),
+            _ => None,
+        }
+    }
+
+    /// Hands a command to the task it is for. Commands for other
+    /// tasks go to their control channels without waiting, so a task
+    /// that is busy or not running in this binary does not hold up
+    /// the command input. The host gets a reply instead.
+    pub async fn dispatch(command: Command) {
+        match command {
+            #[cfg(feature = "co2")]
+            Command::Co2(c) => forward(&ysns::yco2::CONTROL, c, "co2"),
+            #[cfg(feature = "loadcell")]
+            Command::LoadCell(c) => forward(&ysns::yfrc::CONTROL, c, "lc"),
+            Command::Response(c) => forward(&yuii::rbx::CONTROL, c, "rt"),
+            #[cfg(feature = "serial")]
+            Command::Hid(c) => hid::apply(c).await,
+            Command::Protocol(c) => forward(&yuio::stim::CONTROL, c, "pr"),
+            Command::Buzzer(c) => forward(&yuio::buzzer::CONTROL, c, "bz"),
+            Command::Config(c) => ycfg::apply(c).await,
+            Command::Replay(c) => forward(&replay::CONTROL, c, "play"),
+        }
+    }
+
+    fn forward<T, const N: usize>(control: &Channel<RawMutex, T, N>, command: T, name: &str) {
+        if control.try_send(command).is_err() {
+            let mut reply = bsu::Reply::new();
+            let _ = write!(reply, "{}: not running or busy", name);
+            // like log records, replies cannot wait here
+            let _ = bsu::REPLY.try_send(reply);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use embassy_futures::block_on;
+
+        /// Nothing serves the buzzer in the tests: once its channel
+        /// is full, commands are answered instead of blocking the input.
+        #[test]
+        fn unserved_command_is_answered() {
+            for _ in 0..6 {
+                block_on(dispatch(parse("bz tone 440 100").unwrap()));
+            }
+            assert_eq!(bsu::REPLY.try_receive().unwrap(), "bz: not running or busy");
+        }
+    }
 }
 
@@ -1,32 +1,1034 @@
 pub use crate::*;
 
-pub mod btn {
+pub mod keys {
+    //! ## Button pad
+    //!
+    //! Reads up to three buttons (GP20-22 on the Maker Pi Pico) and
+    //! queues gestures in `EVENTS`, so a slow reader loses none.
+    //! Buttons are a bit mask, the first pin is bit 0. Buttons going
+    //! down together form a chord, which counts like one button.
+    //!
+    //! A press gives `Down` and `Up`, and in between `Long` once held
+    //! for `long_press`, then `Repeat` every `repeat`. A press that
+    //! was not long is a `Click`, unless the same buttons go down
+    //! again within `double_click`: that second press gives
+    //! `DoubleClick` right after its `Down`, and no clicks. So clicks
+    //! come that much late; set `double_click` to zero to have them at once.
+    //!
+    //! While all buttons are up, the task sleeps on the pin edges,
+    //! otherwise it polls every 5 ms.
+    //!
+    //! With `serial`, presses and releases also go to `ytfk::hid`.
     use super::*;
-    use embassy_time::{Duration, Timer, Instant};
-    use embassy_rp::gpio::{AnyPin, Input, Pull};
-    use embassy_sync::signal::Signal;
-    pub enum Event {Press, Short, Long}
-    pub static BTN: Signal<RawMutex, Event> = Signal::new();
+    use embassy_time::{Duration, Instant};
+    #[cfg(feature = "rp")]
+    use {
+        core::future::pending,
+        embassy_futures::select::select3,
+        embassy_rp::gpio::{AnyPin, Input, Pull},
+        embassy_time::Ticker,
+    };
 
+    pub const MAX_BUTTONS: usize = 3;
+    #[cfg(feature = "rp")]
+    const POLL: Duration = Duration::from_millis(5);
 
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Gesture {
+        Down,
+        Up,
+        Click,
+        DoubleClick,
+        Long,
+        Repeat,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Event {
+        /// bit mask of the buttons
+        pub buttons: u8,
+        pub gesture: Gesture,
+        pub time: Instant,
+    }
+
+    pub static EVENTS: Channel<RawMutex, Event, 16> = Channel::new();
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Config {
+        pub debounce: Duration,
+        pub double_click: Duration,
+        pub long_press: Duration,
+        pub repeat: Duration,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                debounce: Duration::from_millis(30),
+                double_click: Duration::from_millis(300),
+                long_press: Duration::from_millis(1000),
+                repeat: Duration::from_millis(200),
+            }
+        }
+    }
+
+    impl Config {
+        /// Clicks at once, for a single button that has no double click
+        pub fn single() -> Self {
+            Config {
+                double_click: Duration::from_ticks(0),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[cfg(feature = "rp")]
+    type Buttons = [Option<Input<'static, AnyPin>>; MAX_BUTTONS];
+
+    #[cfg(feature = "rp")]
+    fn read(buttons: &Buttons) -> u8 {
+        let mut mask = 0;
+        for (b, button) in buttons.iter().enumerate() {
+            if let Some(button) = button {
+                if button.is_low() {
+                    mask |= 1 << b;
+                }
+            }
+        }
+        mask
+    }
+
+    #[cfg(feature = "rp")]
+    async fn any_down(buttons: &mut Buttons) {
+        async fn low(button: &mut Option<Input<'static, AnyPin>>) {
+            match button {
+                Some(button) => button.wait_for_low().await,
+                None => pending().await,
+            }
+        }
+        let [b0, b1, b2] = buttons;
+        select3(low(b0), low(b1), low(b2)).await;
+    }
+
+    #[cfg(feature = "rp")]
+    async fn send(buttons: u8, gesture: Gesture, time: Instant) {
+        EVENTS.send(Event { buttons, gesture, time }).await;
+    }
+
+    /// Hands the buttons that changed to the USB HID device,
+    /// which has them as keys or gamepad buttons
+    #[cfg(feature = "serial")]
+    fn to_hid(before: u8, after: u8) {
+        for b in 0..MAX_BUTTONS as u8 {
+            if (before ^ after) & 1 << b != 0 {
+                let _ = crate::ytfk::hid::BUTTONS.try_send((b, after & 1 << b != 0));
+            }
+        }
+    }
+
+    #[cfg(feature = "rp")]
+    /// A press in progress
+    struct Held {
+        chord: u8,
+        long: bool,
+        /// the second press of a double click
+        double: bool,
+        /// time of the next `Long` or `Repeat`
+        next: Instant,
+    }
+
+    #[cfg(feature = "rp")]
+    #[embassy_executor::task]
+    pub async fn task(pins: Vec<AnyPin, MAX_BUTTONS>, config: Config) {
+        let mut buttons: Buttons = [None, None, None];
+        for (b, pin) in pins.into_iter().enumerate() {
+            buttons[b] = Some(Input::new(pin, Pull::Up));
+        }
+        let mut ticker = Ticker::every(POLL);
+        let mut stable: u8 = 0;
+        let mut raw: u8 = 0;
+        let mut since = Instant::now();
+        let mut held: Option<Held> = None;
+        // a click waiting for its second
+        let mut clicked: Option<(u8, Instant)> = None;
+        loop {
+            if raw == 0 && stable == 0 && clicked.is_none() {
+                any_down(&mut buttons).await;
+                ticker.reset();
+            }
+            ticker.next().await;
+            let now = Instant::now();
+            let mask = read(&buttons);
+            if mask != raw {
+                raw = mask;
+                since = now;
+            }
+            if raw != stable && now - since >= config.debounce {
+                let added = raw & !stable;
+                #[cfg(feature = "serial")]
+                to_hid(stable, raw);
+                stable = raw;
+                match held.as_mut() {
+                    None if stable != 0 => {
+                        let double = match clicked.take() {
+                            Some((first, _)) if first == stable => true,
+                            Some((first, time)) => {
+                                send(first, Gesture::Click, time).await;
+                                false
+                            }
+                            None => false,
+                        };
+                        held = Some(Held {
+                            chord: stable,
+                            long: false,
+                            double,
+                            next: since + config.long_press,
+                        });
+                        send(stable, Gesture::Down, since).await;
+                        if double {
+                            send(stable, Gesture::DoubleClick, since).await;
+                        }
+                    }
+                    // joining a chord late
+                    Some(press) if stable != 0 => press.chord |= added,
+                    Some(press) => {
+                        let (chord, single) = (press.chord, !press.long && !press.double);
+                        held = None;
+                        send(chord, Gesture::Up, since).await;
+                        if single {
+                            if config.double_click.as_ticks() == 0 {
+                                send(chord, Gesture::Click, since).await;
+                            } else {
+                                clicked = Some((chord, since));
+                            }
+                        }
+                    }
+                    None => {}
+                }
+            }
+            if let Some(press) = held.as_mut() {
+                if now >= press.next {
+                    let gesture = if press.long { Gesture::Repeat } else { Gesture::Long };
+                    press.long = true;
+                    press.next = now + config.repeat;
+                    send(press.chord, gesture, now).await;
+                }
+            }
+            if let Some((chord, time)) = clicked {
+                if now - time >= config.double_click {
+                    clicked = None;
+                    send(chord, Gesture::Click, time).await;
+                }
+            }
+        }
+    }
+}
+
+pub mod menu {
+    //! ## On-device menu
+    //!
+    //! Sensor selection, sample rates and the session (participant and
+    //! condition) can be set with the buttons, instead of recompiling.
+    //!
+    //! `Menu` is the model: it takes `Input`s, changes `Settings` and
+    //! renders four text lines. It does not touch any peripheral, so
+    //! it can be run on the host. `task` connects it to `keys::EVENTS`
+    //! and `disp::TEXT` (or `graph` on its text page), takes the
+    //! settings from `ycfg` at start and saves them on `Save` and `Start`.
+    //!
+    //! Buttons: first = up, second = down, third = select, long press
+    //! on select = next page. Holding up or down scrolls, first after
+    //! the long press time, then at the repeat rate. While a number is
+    //! edited, up and down change it and select ends editing.
+    //!
+    //! Starting a session signals `SESSION`, which the control task of
+    //! the binary waits for to switch its sensors (`ydev::menu_control`).
+    //!
+    //! The menu lists the sensors of the binary by device number,
+    //! as `firmware!` has them. Only their settings are written back.
+    use super::*;
+    use super::keys::Gesture;
+    use core::fmt::Write;
+
+    pub const MAX_SENSORS: usize = 8;
+    /// Selectable sample rates in Hz, 0 is the sensor's own
+    /// ("auto", e.g. every CO2 measurement or moi edge)
+    pub const RATES: [u16; 8] = [0, 10, 50, 100, 200, 500, 1000, 2000];
+
+    pub type Line = String<20>;
+    /// A sensor of the menu: its device number and name
+    pub type Device<'a> = (u8, &'a str);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Settings {
+        /// bit mask of active sensors
+        pub sensors: u8,
+        /// index into `RATES`, per sensor
+        pub rates: [u8; MAX_SENSORS],
+        /// bit mask of the sensors that may run at their own rate,
+        /// the others skip "auto"
+        pub native: u8,
+        pub participant: u16,
+        pub condition: u8,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Settings {
+                sensors: 0xFF,
+                rates: [3; MAX_SENSORS],
+                native: 0,
+                participant: 0,
+                condition: 0,
+            }
+        }
+    }
+
+    impl Settings {
+        pub fn active(&self, sensor: usize) -> bool {
+            self.sensors & (1 << sensor) != 0
+        }
+
+        pub fn hz(&self, sensor: usize) -> u16 {
+            RATES[self.rates[sensor] as usize % RATES.len()]
+        }
+
+        /// The lowest index into `RATES` the sensor may have
+        fn slowest(&self, sensor: usize) -> u8 {
+            (self.native & (1 << sensor) == 0) as u8
+        }
+
+        /// Writes the settings of `devices` into a device configuration
+        pub fn apply(&self, devices: &[Device], config: &mut ycfg::Config) {
+            for &(d, _) in devices {
+                let (d, bit) = (d as usize % MAX_SENSORS, 1 << (d as usize % MAX_SENSORS));
+                config.devices = (config.devices & !bit) | (self.sensors & bit);
+                config.rates[d] = self.hz(d);
+            }
+            config.participant = self.participant;
+            config.condition = self.condition;
+        }
+    }
+
+    /// Rates are rounded to the nearest of `RATES` the sensor may have
+    impl From<&ycfg::Config> for Settings {
+        fn from(config: &ycfg::Config) -> Self {
+            let mut settings = Settings {
+                sensors: config.devices,
+                rates: [0; MAX_SENSORS],
+                native: config.native,
+                participant: config.participant,
+                condition: config.condition,
+            };
+            for (sensor, &hz) in config.rates.iter().enumerate() {
+                settings.rates[sensor] = (settings.slowest(sensor) as usize..RATES.len())
+                    .min_by_key(|&r| (RATES[r] as i32 - hz as i32).abs())
+                    .unwrap_or(0) as u8;
+            }
+            settings
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Page {
+        Sensors,
+        Rates,
+        Session,
+        Storage,
+    }
+
+    impl Page {
+        fn next(self) -> Page {
+            match self {
+                Page::Sensors => Page::Rates,
+                Page::Rates => Page::Session,
+                Page::Session => Page::Storage,
+                Page::Storage => Page::Sensors,
+            }
+        }
+
+        fn title(self) -> &'static str {
+            match self {
+                Page::Sensors => "Sensors",
+                Page::Rates => "Rates",
+                Page::Session => "Session",
+                Page::Storage => "Storage",
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Input {
+        Up,
+        Down,
+        Select,
+        NextPage,
+    }
+
+    impl Input {
+        /// The input of a button gesture, if any
+        pub fn from_gesture(gesture: Gesture, buttons: u8) -> Option<Input> {
+            let input = match (gesture, buttons) {
+                (Gesture::Click | Gesture::Long | Gesture::Repeat, 0b001) => Input::Up,
+                (Gesture::Click | Gesture::Long | Gesture::Repeat, 0b010) => Input::Down,
+                (Gesture::Click, 0b100) => Input::Select,
+                (Gesture::Long, 0b100) => Input::NextPage,
+                _ => return None,
+            };
+            Some(input)
+        }
+    }
+
+    /// What the owner of the menu has to do after an input
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Action {
+        None,
+        Start,
+        Save,
+    }
+
+    pub struct Menu<'a> {
+        devices: &'a [Device<'a>],
+        pub settings: Settings,
+        /// shown on the storage page
+        pub storage: Line,
+        page: Page,
+        item: usize,
+        editing: bool,
+    }
+
+    impl<'a> Menu<'a> {
+        /// The menu for `devices`, at most `MAX_SENSORS`
+        pub fn new(devices: &'a [Device<'a>], settings: Settings) -> Self {
+            Menu {
+                devices: &devices[..devices.len().min(MAX_SENSORS)],
+                settings,
+                storage: Line::new(),
+                page: Page::Sensors,
+                item: 0,
+                editing: false,
+            }
+        }
+
+        pub fn page(&self) -> Page {
+            self.page
+        }
+
+        fn items(&self) -> usize {
+            match self.page {
+                Page::Sensors | Page::Rates => self.devices.len(),
+                // participant, condition, start
+                Page::Session => 3,
+                // status, save
+                Page::Storage => 2,
+            }
+        }
+
+        pub fn input(&mut self, input: Input) -> Action {
+            if self.editing {
+                let up = match input {
+                    Input::Up => true,
+                    Input::Down => false,
+                    _ => {
+                        self.editing = false;
+                        return Action::None;
+                    }
+                };
+                match self.item {
+                    0 if up => self.settings.participant = self.settings.participant.wrapping_add(1),
+                    0 => self.settings.participant = self.settings.participant.wrapping_sub(1),
+                    _ if up => self.settings.condition = self.settings.condition.wrapping_add(1),
+                    _ => self.settings.condition = self.settings.condition.wrapping_sub(1),
+                }
+                return Action::None;
+            }
+            let items = self.items().max(1);
+            match input {
+                Input::Up => self.item = (self.item + items - 1) % items,
+                Input::Down => self.item = (self.item + 1) % items,
+                Input::NextPage => {
+                    self.page = self.page.next();
+                    self.item = 0;
+                }
+                Input::Select => return self.select(),
+            }
+            Action::None
+        }
+
+        fn select(&mut self) -> Action {
+            let item = self.item;
+            let device = self.devices.get(item).map_or(0, |&(d, _)| d as usize % MAX_SENSORS);
+            match self.page {
+                Page::Sensors if item < self.devices.len() => self.settings.sensors ^= 1 << device,
+                Page::Rates if item < self.devices.len() => {
+                    let slowest = self.settings.slowest(device);
+                    let rate = &mut self.settings.rates[device];
+                    *rate = (*rate + 1) % RATES.len() as u8;
+                    *rate = (*rate).max(slowest);
+                }
+                Page::Session if item < 2 => self.editing = true,
+                Page::Session => return Action::Start,
+                Page::Storage if item == 1 => return Action::Save,
+                _ => {}
+            }
+            Action::None
+        }
+
+        fn item_line(&self, item: usize) -> Line {
+            let mut line = Line::new();
+            let cursor = match (item == self.item, self.editing) {
+                (true, true) => '*',
+                (true, false) => '>',
+                _ => ' ',
+            };
+            let _ = line.push(cursor);
+            let s = &self.settings;
+            let (device, name) = self.devices.get(item).map_or((0, ""), |&(d, name)| (d as usize % MAX_SENSORS, name));
+            let _ = match self.page {
+                Page::Sensors => {
+                    let mark = if s.active(device) { 'x' } else { ' ' };
+                    write!(line, "[{}] {}", mark, name)
+                }
+                Page::Rates => match s.hz(device) {
+                    0 => write!(line, "{:<8}{:>8}", name, "auto"),
+                    hz => write!(line, "{:<8}{:>5} Hz", name, hz),
+                },
+                Page::Session => match item {
+                    0 => write!(line, "Particip. {}", s.participant),
+                    1 => write!(line, "Condition {}", s.condition),
+                    _ => write!(line, "Start"),
+                },
+                Page::Storage => match item {
+                    0 => write!(line, "{}", self.storage),
+                    _ => write!(line, "Save settings"),
+                },
+            };
+            line
+        }
+
+        /// Title and the three items around the cursor
+        pub fn lines(&self) -> [Option<Line>; 4] {
+            let mut lines: [Option<Line>; 4] = [None, None, None, None];
+            let mut title = Line::new();
+            let _ = write!(title, "== {} ==", self.page.title());
+            lines[0] = Some(title);
+            let items = self.items();
+            let first = self.item.saturating_sub(1).min(items.saturating_sub(3));
+            for (row, item) in (first..items).take(3).enumerate() {
+                lines[row + 1] = Some(self.item_line(item));
+            }
+            lines
+        }
+    }
+
+    /// Settings of the session that was started
+    pub static SESSION: Signal<RawMutex, Settings> = Signal::new();
+
+    pub async fn load() -> Settings {
+        Settings::from(&ycfg::get().await)
+    }
+
+    pub async fn store(settings: &Settings, devices: &[Device<'_>]) -> Result<(), ymem::Error> {
+        let mut config = ycfg::get().await;
+        settings.apply(devices, &mut config);
+        ycfg::set(config).await;
+        ycfg::save().await
+    }
+
+    /// Runs the menu on the buttons of `keys::task`, which it takes over
+    #[embassy_executor::task]
+    pub async fn task(devices: &'static [Device<'static>]) {
+        use super::keys::EVENTS;
+        use crate::yuio::disp::TEXT;
+
+        let mut menu = Menu::new(devices, load().await);
+        let _ = write!(menu.storage, "config v{}", ycfg::VERSION);
+        loop {
+            TEXT.signal(menu.lines());
+            let event = EVENTS.receive().await;
+            let Some(input) = Input::from_gesture(event.gesture, event.buttons) else {
+                continue;
+            };
+            let action = menu.input(input);
+            if action == Action::None {
+                continue;
+            }
+            menu.storage.clear();
+            let _ = match store(&menu.settings, devices).await {
+                Ok(_) => write!(menu.storage, "saved"),
+                Err(e) => write!(menu.storage, "{:?}", e),
+            };
+            if action == Action::Start {
+                SESSION.signal(menu.settings);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const DEVICES: [Device; 4] = [(0, "moi"), (1, "adc"), (2, "lsm6"), (4, "co2")];
+
+        fn gestures(menu: &mut Menu, events: &[(Gesture, u8)]) -> Action {
+            let mut action = Action::None;
+            for &(gesture, buttons) in events {
+                if let Some(input) = Input::from_gesture(gesture, buttons) {
+                    action = menu.input(input);
+                }
+            }
+            action
+        }
+
+        #[test]
+        fn holding_scrolls_without_flipping_the_page() {
+            let mut menu = Menu::new(&DEVICES, Settings::default());
+            let hold = [
+                (Gesture::Down, 0b010),
+                (Gesture::Long, 0b010),
+                (Gesture::Repeat, 0b010),
+                (Gesture::Repeat, 0b010),
+                (Gesture::Up, 0b010),
+            ];
+            gestures(&mut menu, &hold);
+            assert_eq!(menu.page(), Page::Sensors);
+            assert_eq!(menu.item, 3);
+            gestures(&mut menu, &[(Gesture::Long, 0b001), (Gesture::Repeat, 0b001)]);
+            assert_eq!(menu.page(), Page::Sensors);
+            assert_eq!(menu.item, 1);
+        }
+
+        #[test]
+        fn long_select_flips_the_page() {
+            let mut menu = Menu::new(&DEVICES, Settings::default());
+            gestures(&mut menu, &[(Gesture::Click, 0b010)]);
+            gestures(&mut menu, &[(Gesture::Down, 0b100), (Gesture::Long, 0b100), (Gesture::Repeat, 0b100)]);
+            assert_eq!(menu.page(), Page::Rates);
+            assert_eq!(menu.item, 0);
+            for _ in 0..3 {
+                gestures(&mut menu, &[(Gesture::Long, 0b100)]);
+            }
+            assert_eq!(menu.page(), Page::Sensors);
+        }
+
+        #[test]
+        fn other_gestures_do_nothing() {
+            for gesture in [Gesture::Down, Gesture::Up, Gesture::DoubleClick] {
+                for buttons in [0b001, 0b010, 0b100] {
+                    assert_eq!(Input::from_gesture(gesture, buttons), None);
+                }
+            }
+            // chords are not mapped
+            assert_eq!(Input::from_gesture(Gesture::Click, 0b011), None);
+            assert_eq!(Input::from_gesture(Gesture::Repeat, 0b100), None);
+        }
+
+        #[test]
+        fn select_toggles_sensors_and_cycles_rates() {
+            let mut menu = Menu::new(&DEVICES, Settings::default());
+            gestures(&mut menu, &[(Gesture::Click, 0b010), (Gesture::Click, 0b100)]);
+            assert!(!menu.settings.active(1));
+            assert!(menu.settings.active(0));
+            let lines = menu.lines();
+            assert_eq!(lines[0].as_deref(), Some("== Sensors =="));
+            assert_eq!(lines[2].as_deref(), Some(">[ ] adc"));
+            menu.input(Input::NextPage);
+            let before = menu.settings.hz(0);
+            menu.input(Input::Select);
+            assert_ne!(menu.settings.hz(0), before);
+            // moi is not native here, so "auto" is skipped
+            for _ in 1..RATES.len() - 1 {
+                assert_ne!(menu.settings.hz(0), 0);
+                menu.input(Input::Select);
+            }
+            assert_eq!(menu.settings.hz(0), before);
+        }
+
+        #[test]
+        fn session_numbers_are_edited_and_started() {
+            let mut menu = Menu::new(&DEVICES, Settings::default());
+            menu.input(Input::NextPage);
+            menu.input(Input::NextPage);
+            assert_eq!(menu.page(), Page::Session);
+            // participant
+            menu.input(Input::Select);
+            menu.input(Input::Up);
+            menu.input(Input::Up);
+            menu.input(Input::Down);
+            assert_eq!(menu.lines()[1].as_deref(), Some("*Particip. 1"));
+            // any other input ends editing, without acting
+            assert_eq!(menu.input(Input::NextPage), Action::None);
+            assert_eq!(menu.page(), Page::Session);
+            assert_eq!(menu.settings.participant, 1);
+            menu.input(Input::Up);
+            assert_eq!(menu.input(Input::Select), Action::Start);
+        }
+
+        #[test]
+        fn save_is_on_the_storage_page() {
+            let mut menu = Menu::new(&DEVICES, Settings::default());
+            for _ in 0..3 {
+                menu.input(Input::NextPage);
+            }
+            assert_eq!(menu.input(Input::Select), Action::None);
+            menu.input(Input::Down);
+            assert_eq!(menu.input(Input::Select), Action::Save);
+        }
+
+        #[test]
+        fn items_are_the_devices() {
+            let settings = Settings { native: 1 << 4, ..Settings::default() };
+            let mut menu = Menu::new(&DEVICES, settings);
+            menu.input(Input::Up);
+            menu.input(Input::Select);
+            assert!(!menu.settings.active(4));
+            assert!(menu.settings.active(3));
+            menu.input(Input::NextPage);
+            menu.input(Input::Up);
+            for _ in 0..5 {
+                menu.input(Input::Select);
+            }
+            assert_eq!(menu.settings.hz(4), 0);
+            assert_eq!(menu.lines()[3].as_deref(), Some(">co2         auto"));
+        }
+
+        #[test]
+        fn settings_round_trip_the_config() {
+            let settings = Settings {
+                sensors: 0b0101,
+                rates: [3, 3, 6, 3, 3, 3, 3, 3],
+                native: 0,
+                participant: 42,
+                condition: 3,
+            };
+            let mut config = ycfg::Config::DEFAULT;
+            config.devices = 0b0111;
+            settings.apply(&DEVICES[..3], &mut config);
+            assert_eq!(config.rates[2], 1000);
+            assert_eq!(Settings::from(&config), settings);
+        }
+
+        /// Sensors the menu does not list keep their configuration,
+        /// 0 (the sensor's own rate) included
+        #[test]
+        fn only_listed_devices_are_written() {
+            let mut config = ycfg::Config::DEFAULT;
+            config.native = 1 << 4;
+            config.rates[4] = 0;
+            config.rates[5] = 149;
+            config.devices = 0b11_0000;
+            let mut settings = Settings::from(&config);
+            assert_eq!(settings.hz(4), 0);
+            settings.sensors = 0;
+            settings.rates = [1; MAX_SENSORS];
+            settings.apply(&DEVICES[..2], &mut config);
+            assert_eq!(config.devices, 0b11_0000);
+            assert_eq!(config.rates[..6], [10, 10, 100, 100, 0, 149]);
+        }
+    }
+}
+
+pub mod rbx {
+    //! ## Response box
+    //!
+    //! Measures reaction times on up to four response buttons.
+    //! A trial starts with a stimulus onset, either from the host
+    //! (`rt onset <trial>`) or a rising edge on the trigger pin.
+    //! The first falling edge of any button ends the trial.
+    //! A PIO state machine (`Stopwatch`) counts µs from the onset to
+    //! that edge, so the RT does not depend on when the task wakes up.
+    //! Bouncing after the first edge is ignored, as the next trial
+    //! only starts after all buttons have been released for `debounce`.
+    //!
+    //! Every trial is sent as one record with the onset as time
+    //! and read `[trial, button, rt]`, with the button counted from 1
+    //! (0 when the trial timed out) and the RT in ms. Onsets from the
+    //! trigger pin are timed by the task, the RT is not.
+    //!
+    //! `task` takes state machine 2 of PIO0, next to `yuio::rgb`
+    //! and `ysns::yenc`. `run` takes anything that is a `Watch`.
+    use super::*;
+    use crate::ytfk::bsu;
+    use core::str::SplitWhitespace;
+    use embassy_futures::select::{select, Either};
+    #[cfg(feature = "rp")]
+    use embassy_rp::clocks::clk_sys_freq;
+    #[cfg(feature = "rp")]
+    use embassy_rp::gpio::{AnyPin, Input, Pin, Pull};
+    #[cfg(feature = "rp")]
+    use embassy_rp::peripherals::PIO0;
+    #[cfg(feature = "rp")]
+    use embassy_rp::pio::{Common, Config as PioConfig, Instance, PioPin, ShiftConfig, ShiftDirection, StateMachine};
+    #[cfg(feature = "rp")]
+    use embassy_rp::relocate::RelocatedProgram;
+    #[cfg(feature = "rp")]
+    use embassy_rp::Peripheral;
+    use embassy_time::{with_timeout, Duration, Instant};
+    #[cfg(feature = "rp")]
+    use embassy_time::Timer;
+    #[cfg(feature = "rp")]
+    use fixed::types::U24F8;
+
+    pub const MAX_BUTTONS: usize = 4;
+
+    pub static RECORD: AtomicBool = AtomicBool::new(false);
+    pub static CONTROL: Channel<RawMutex, Command, 4> = Channel::new();
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Command {
+        /// stimulus onset of a trial
+        Onset(u16),
+        /// ms until a trial counts as missed
+        Timeout(u32),
+    }
+
+    impl Command {
+        /// Parses the words following `rt` on a command line
+        pub fn parse(mut words: SplitWhitespace) -> Option<Command> {
+            let command = match words.next()? {
+                "onset" => Command::Onset(words.next()?.parse().ok()?),
+                "timeout" => Command::Timeout(words.next()?.parse().ok()?),
+                _ => return None,
+            };
+            Some(command)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Config {
+        pub debounce: Duration,
+        pub timeout: Duration,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                debounce: Duration::from_millis(20),
+                timeout: Duration::from_secs(2),
+            }
+        }
+    }
+
+    /// What `run` needs of the response buttons
+    #[allow(async_fn_in_trait)]
+    pub trait Watch {
+        /// Waits until no button has been pressed for `debounce`
+        async fn released(&mut self, debounce: Duration);
+        fn has_trigger(&self) -> bool;
+        /// Starts counting now, or with `trigger` at the next
+        /// onset from the trigger (see `triggered`)
+        fn arm(&mut self, trigger: bool);
+        fn disarm(&mut self);
+        /// Waits for the trigger after `arm(true)`
+        async fn triggered(&mut self);
+        /// Waits for the first press since the start,
+        /// returns its button (from 1) and the RT
+        async fn press(&mut self) -> (u8, Duration);
+    }
+
+    /// ### Reaction timer on PIO
+    ///
+    /// The state machine waits for an arming word (0 starts right
+    /// away, 1 after the trigger goes high, which is reported by a
+    /// word of 0), then counts in µs until one of the buttons reads
+    /// low. It pushes the count and the bit mask of pressed buttons.
+    /// The buttons have to be on neighbouring pins, as the state
+    /// machine reads them in one go.
+    #[cfg(feature = "rp")]
+    pub struct Stopwatch<'d, P: Instance, const S: usize> {
+        sm: StateMachine<'d, P, S>,
+        origin: u8,
+        buttons: Vec<Input<'d, AnyPin>, MAX_BUTTONS>,
+        trigger: Option<Input<'d, AnyPin>>,
+    }
+
+    #[cfg(feature = "rp")]
+    impl<'d, P: Instance, const S: usize> Stopwatch<'d, P, S> {
+        /// `first` and the pins in `rest`, which follow it, are pulled
+        /// up and read by the CPU (release) as well as the PIO (press)
+        pub fn new(
+            pio: &mut Common<'d, P>,
+            mut sm: StateMachine<'d, P, S>,
+            first: impl PioPin,
+            rest: Vec<AnyPin, { MAX_BUTTONS - 1 }>,
+            trigger: Option<AnyPin>,
+        ) -> Self {
+            use ::pio::{Assembler, InSource, JmpCondition, MovDestination, MovOperation, MovSource, OutDestination, WaitSource};
+            let base = first.pin();
+            assert!(
+                rest.iter().enumerate().all(|(i, pin)| pin.pin() == base + 1 + i as u8),
+                "response buttons have to be on neighbouring pins"
+            );
+            let n = 1 + rest.len() as u8;
+            // PIO inputs see every pin, whatever its function, so the
+            // CPU keeps the inputs and the PIO only needs the base pin
+            let in_base = pio.make_pio_pin(unsafe { first.clone_unchecked() });
+            let mut buttons = Vec::new();
+            let _ = buttons.push(Input::new(first.degrade(), Pull::Up));
+            for pin in rest {
+                let _ = buttons.push(Input::new(pin, Pull::Up));
+            }
+            let trigger_pin = trigger.as_ref().map(|pin| pin.pin());
+            let trigger = trigger.map(|pin| Input::new(pin, Pull::Down));
+
+            let mut a: Assembler<32> = Assembler::new();
+            let mut wrap_target = a.label();
+            let mut wrap_source = a.label();
+            let mut go = a.label();
+            let mut count = a.label();
+            let mut released = a.label();
+            a.bind(&mut wrap_target);
+            a.pull(false, true);
+            a.out(OutDestination::Y, 32);
+            a.jmp(JmpCondition::YIsZero, &mut go);
+            if let Some(pin) = trigger_pin {
+                a.wait(1, WaitSource::GPIO, pin, false);
+                a.mov(MovDestination::ISR, MovOperation::None, MovSource::NULL);
+                a.push(false, true);
+            }
+            a.bind(&mut go);
+            a.mov(MovDestination::X, MovOperation::Invert, MovSource::NULL);
+            // five instructions per count
+            a.bind(&mut count);
+            a.mov(MovDestination::ISR, MovOperation::Invert, MovSource::NULL);
+            a.r#in(InSource::PINS, n);
+            // one bits for pressed buttons, zero if all are up
+            a.mov(MovDestination::Y, MovOperation::Invert, MovSource::ISR);
+            a.jmp(JmpCondition::YIsZero, &mut released);
+            a.mov(MovDestination::ISR, MovOperation::Invert, MovSource::X);
+            a.push(false, true);
+            a.mov(MovDestination::ISR, MovOperation::None, MovSource::Y);
+            a.push(false, true);
+            a.bind(&mut wrap_source);
+            a.bind(&mut released);
+            a.jmp(JmpCondition::XDecNonZero, &mut count);
+            a.jmp(JmpCondition::Always, &mut count);
+            let program = a.assemble_with_wrap(wrap_source, wrap_target);
+
+            let mut cfg = PioConfig::default();
+            cfg.set_in_pins(&[&in_base]);
+            let loaded = pio.load_program(&RelocatedProgram::new(&program));
+            let origin = loaded.origin;
+            cfg.use_program(&loaded, &[]);
+            cfg.shift_in = ShiftConfig {
+                auto_fill: false,
+                threshold: 32,
+                direction: ShiftDirection::Left,
+            };
+            // in kHz to avoid overflows, 5 MHz for one count per µs
+            cfg.clock_divider = U24F8::from_num(clk_sys_freq() / 1000) / U24F8::from_num(5_000);
+            sm.set_config(&cfg);
+            Stopwatch {
+                sm,
+                origin,
+                buttons,
+                trigger,
+            }
+        }
+    }
+
+    #[cfg(feature = "rp")]
+    impl<P: Instance, const S: usize> Watch for Stopwatch<'_, P, S> {
+        async fn released(&mut self, debounce: Duration) {
+            loop {
+                for button in self.buttons.iter_mut() {
+                    button.wait_for_high().await;
+                }
+                Timer::after(debounce).await;
+                if self.buttons.iter().all(|b| b.is_high()) {
+                    return;
+                }
+            }
+        }
+
+        fn has_trigger(&self) -> bool {
+            self.trigger.is_some()
+        }
+
+        /// `trigger` waits for the next high level of the trigger pin
+        fn arm(&mut self, trigger: bool) {
+            self.disarm();
+            unsafe { self.sm.exec_jmp(self.origin) };
+            self.sm.set_enable(true);
+            self.sm.tx().push((trigger && self.has_trigger()) as u32);
+        }
+
+        fn disarm(&mut self) {
+            self.sm.set_enable(false);
+            self.sm.clear_fifos();
+            self.sm.restart();
+        }
+
+        async fn triggered(&mut self) {
+            self.sm.rx().wait_pull().await;
+        }
+
+        async fn press(&mut self) -> (u8, Duration) {
+            let us = self.sm.rx().wait_pull().await;
+            let pressed = self.sm.rx().wait_pull().await;
+            (pressed.trailing_zeros() as u8 + 1, Duration::from_micros(us as u64))
+        }
+    }
+
+    /// Waits for the next onset from the trigger or the host
+    async fn onset(watch: &mut impl Watch, trial: &mut u16, config: &mut Config) -> Instant {
+        loop {
+            let trigger = watch.has_trigger();
+            if trigger {
+                watch.arm(true);
+            }
+            let triggered = async {
+                if trigger {
+                    watch.triggered().await
+                } else {
+                    core::future::pending().await
+                }
+            };
+            let event = select(triggered, CONTROL.receive()).await;
+            match event {
+                Either::First(_) => {
+                    *trial = trial.wrapping_add(1);
+                    return Instant::now();
+                }
+                Either::Second(Command::Onset(t)) => {
+                    let onset = Instant::now();
+                    watch.arm(false);
+                    *trial = t;
+                    return onset;
+                }
+                Either::Second(Command::Timeout(ms)) => {
+                    config.timeout = Duration::from_millis(ms as u64);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "rp")]
     #[embassy_executor::task]
-    pub async fn task(btn_pin: AnyPin) {
-        let mut btn = Input::new(btn_pin, Pull::Up);
-        let longpress = 1000;
-        let debounce = 50;
+    pub async fn task(watch: Stopwatch<'static, PIO0, 2>, config: Config, sensory: u8) {
+        run(watch, config, sensory).await
+    }
 
+    /// Runs trials on `watch`, see above
+    pub async fn run(mut watch: impl Watch, config: Config, sensory: u8) -> ! {
+        let mut config = config;
+        let mut trial: u16 = 0;
         loop {
-            btn.wait_for_low().await;
-            BTN.signal(Event::Press);
-            let when_pressed = Instant::now().as_millis();
-            Timer::after(Duration::from_millis(debounce)).await;
-            btn.wait_for_high().await;
-            if Instant::now().as_millis() - when_pressed >= longpress {
-                BTN.signal(Event::Long);    
-            } else {
-                BTN.signal(Event::Short);    
-            };
-            Timer::after(Duration::from_millis(longpress)).await;
-        };
-    }
-}
\ No newline at end of file
+            watch.disarm();
+            watch.released(config.debounce).await;
+            let onset = onset(&mut watch, &mut trial, &mut config).await;
+            let (button, rt) = match with_timeout(config.timeout, watch.press()).await {
+                Ok((button, rt)) => (button, rt),
+                Err(_) => (0, config.timeout),
+            };
+            if RECORD.load(ORD) {
+                let sample = Sample {
+                    sensory,
+                    time: onset,
+                    read: [trial as f32, button as f32, rt.as_micros() as f32 / 1000.0],
+                };
+                bsu::send(sample.into()).await;
+            }
+        }
+    }
+}
@@ -1,57 +1,860 @@
 pub use crate::*;
 
+pub mod pattern {
+    //! ## Light patterns
+    //!
+    //! A pattern is a list of steps, each showing a level (on/off for
+    //! the LED, a colour for RGB pixels) for some ms, repeated once,
+    //! n times or forever. A step of 0 ms holds until replaced.
+    //!
+    //! The `Engine` keeps a persistent base pattern and one transient
+    //! overlay on top. An overlay only replaces another of the same or
+    //! lower priority, and when it ends the base shows again. The
+    //! engine does no waiting itself: `poll` returns the current level
+    //! and when to poll next, so a task can wait for that and new
+    //! requests at the same time.
+    use super::*;
+    use embassy_time::{Duration, Instant};
+
+    pub const MAX_STEPS: usize = 24;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Repeat {
+        Once,
+        Times(u8),
+        Forever,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Step<C> {
+        pub level: C,
+        /// 0 holds
+        pub ms: u16,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Pattern<C: Copy> {
+        pub steps: Vec<Step<C>, MAX_STEPS>,
+        pub repeat: Repeat,
+    }
+
+    impl<C: Copy> Pattern<C> {
+        pub fn new(steps: &[(C, u16)], repeat: Repeat) -> Self {
+            Pattern {
+                steps: steps
+                    .iter()
+                    .take(MAX_STEPS)
+                    .map(|&(level, ms)| Step { level, ms })
+                    .collect(),
+                repeat,
+            }
+        }
+
+        pub fn steady(level: C) -> Self {
+            Self::new(&[(level, 0)], Repeat::Once)
+        }
+
+        pub fn blink(on: C, off: C, on_ms: u16, off_ms: u16, repeat: Repeat) -> Self {
+            Self::new(&[(on, on_ms), (off, off_ms)], repeat)
+        }
+
+        /// `n` short blinks, then a pause, e.g. one per sensor found
+        pub fn count(on: C, off: C, n: usize, repeat: Repeat) -> Self {
+            let mut pattern = Pattern { steps: Vec::new(), repeat };
+            for _ in 0..n.min(MAX_STEPS / 2 - 1) {
+                let _ = pattern.steps.push(Step { level: on, ms: 150 });
+                let _ = pattern.steps.push(Step { level: off, ms: 250 });
+            }
+            let _ = pattern.steps.push(Step { level: off, ms: 1000 });
+            pattern
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Request<C: Copy> {
+        Base(Pattern<C>),
+        /// pattern and priority
+        Overlay(Pattern<C>, u8),
+        /// ends the overlay
+        Clear,
+    }
+
+    struct Running<C: Copy> {
+        pattern: Pattern<C>,
+        priority: u8,
+        step: usize,
+        round: u8,
+        /// end of the current step, `None` holds
+        until: Option<Instant>,
+    }
+
+    impl<C: Copy> Running<C> {
+        fn start(pattern: Pattern<C>, priority: u8, now: Instant) -> Option<Self> {
+            let first = *pattern.steps.first()?;
+            Some(Running {
+                pattern,
+                priority,
+                step: 0,
+                round: 0,
+                until: Self::end(now, first.ms),
+            })
+        }
+
+        fn end(from: Instant, ms: u16) -> Option<Instant> {
+            match ms {
+                0 => None,
+                ms => Some(from + Duration::from_millis(ms as u64)),
+            }
+        }
+
+        fn level(&self) -> C {
+            self.pattern.steps[self.step].level
+        }
+
+        /// Catches up with `now`, false when the pattern is over
+        fn advance(&mut self, now: Instant) -> bool {
+            while let Some(until) = self.until {
+                if until > now {
+                    break;
+                }
+                self.step += 1;
+                if self.step == self.pattern.steps.len() {
+                    self.step = 0;
+                    self.round = self.round.saturating_add(1);
+                    let done = match self.pattern.repeat {
+                        Repeat::Once => true,
+                        Repeat::Times(n) => self.round >= n,
+                        Repeat::Forever => false,
+                    };
+                    if done {
+                        return false;
+                    }
+                }
+                self.until = Self::end(until, self.pattern.steps[self.step].ms);
+            }
+            true
+        }
+    }
+
+    pub struct Engine<C: Copy> {
+        idle: C,
+        base: Option<Running<C>>,
+        overlay: Option<Running<C>>,
+    }
+
+    impl<C: Copy> Engine<C> {
+        /// `idle` shows when no pattern is left
+        pub fn new(idle: C) -> Self {
+            Engine { idle, base: None, overlay: None }
+        }
+
+        pub fn apply(&mut self, request: Request<C>, now: Instant) {
+            match request {
+                Request::Base(pattern) => self.base = Running::start(pattern, 0, now),
+                Request::Overlay(pattern, priority) => {
+                    let free = match &self.overlay {
+                        Some(running) => running.priority <= priority,
+                        None => true,
+                    };
+                    if free {
+                        self.overlay = Running::start(pattern, priority, now);
+                    }
+                }
+                Request::Clear => self.overlay = None,
+            }
+        }
+
+        /// Current level and when it changes next
+        pub fn poll(&mut self, now: Instant) -> (C, Option<Instant>) {
+            if let Some(running) = &mut self.overlay {
+                if !running.advance(now) {
+                    self.overlay = None;
+                }
+            }
+            if let Some(running) = &mut self.base {
+                if !running.advance(now) {
+                    self.base = None;
+                }
+            }
+            let level = match (&self.overlay, &self.base) {
+                (Some(running), _) | (None, Some(running)) => running.level(),
+                (None, None) => self.idle,
+            };
+            let next = [&self.overlay, &self.base]
+                .into_iter()
+                .flatten()
+                .filter_map(|running| running.until)
+                .min();
+            (level, next)
+        }
+    }
+}
+
 pub mod led {
-    // LED control
+    //! ## LED
+    //!
+    //! Runs `pattern`s on a single LED. `LED` takes the classic states:
+    //! `Steady` and `Off` set the base, the others are short overlays,
+    //! so they no longer block or get lost. `Blink` (pause) and `Vibrate`
+    //! (reset) leave the LED off afterwards, as they always did.
+    //! Own patterns go to `PATTERN`.
+    //! `run` drives anything that can be switched, `task` a GPIO pin.
+    //! Switches for a `PATTERN` request are acknowledged on `SHOWN`.
     use super::*;
-    use embassy_time::{Duration, Timer};
-    use embassy_rp::gpio::{AnyPin, Output, Level};
-    //use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-    use embassy_sync::signal::Signal;
+    use super::pattern::{Engine, Pattern, Repeat, Request};
+    use core::future::pending;
+    use embassy_futures::select::{select3, Either3};
+    #[cfg(feature = "rp")]
+    use embassy_rp::gpio::{AnyPin, Level, Output};
+    use embassy_time::{Instant, Timer};
+
     pub enum State {Vibrate, Blink, Steady, Interrupt, Off}
     pub static LED: Signal<RawMutex, State> = Signal::new();
-    
+    pub static PATTERN: Channel<RawMutex, Request<bool>, 4> = Channel::new();
+    /// When the LED was set after the last `PATTERN` request
+    pub static SHOWN: Signal<RawMutex, Instant> = Signal::new();
+    /// Overlay priority of stimuli (`stim`), above all states
+    pub const STIMULUS: u8 = 4;
+
+    impl State {
+        /// The requests for a state, given the level shown right now
+        pub fn requests(self, level: bool) -> impl Iterator<Item = Request<bool>> {
+            let off = Request::Base(Pattern::steady(false));
+            let requests = match self {
+                State::Steady => [Some(Request::Base(Pattern::steady(true))), None],
+                State::Off => [Some(off), None],
+                State::Vibrate => {
+                    let vibrate = Pattern::blink(true, false, 25, 25, Repeat::Times(9));
+                    [Some(off), Some(Request::Overlay(vibrate, 2))]
+                }
+                State::Blink => {
+                    let blink = Pattern::new(&[(false, 25), (true, 50), (false, 25)], Repeat::Once);
+                    [Some(off), Some(Request::Overlay(blink, 1))]
+                }
+                State::Interrupt => [Some(Request::Overlay(Pattern::new(&[(!level, 5)], Repeat::Once), 3)), None],
+            };
+            requests.into_iter().flatten()
+        }
+    }
+
+    #[cfg(feature = "rp")]
     #[embassy_executor::task]
     pub async fn task(led_pin: AnyPin) {
-        let mut led 
-                = Output::new(led_pin, Level::Low);
-        loop {  
-                let next_signal = LED.wait().await;
-                match  next_signal {
-                    State::Vibrate      => {
-                        for _ in 1..10 {
-                            led.set_high();
-                            Timer::after(Duration::from_millis(25))
-                            .await;
-                            led.set_low();
-                            Timer::after(Duration::from_millis(25))
-                            .await;
-                        };
-                    },
-                    State::Blink  => {
-                        led.set_low();
-                        Timer::after(Duration::from_millis(25))
-                        .await;
-                        led.set_high();
-                        Timer::after(Duration::from_millis(50))
-                        .await;
-                        led.set_low()},
-                    State::Steady => {led.set_high()},
-                    State::Off    => {led.set_low()},
-                    State::Interrupt  => {
-                        led.toggle();
-                        Timer::after(Duration::from_millis(5))
-                        .await;
-                        led.toggle();}
-                }   
+        let mut led = Output::new(led_pin, Level::Low);
+        run(|level| led.set_level(level.into())).await
+    }
+
+    /// Runs the patterns, calling `set` with the level on every step
+    pub async fn run(mut set: impl FnMut(bool)) -> ! {
+        let mut engine: Engine<bool> = Engine::new(false);
+        let mut requested = false;
+        loop {
+            let (level, next) = engine.poll(Instant::now());
+            set(level);
+            if core::mem::take(&mut requested) {
+                SHOWN.signal(Instant::now());
+            }
+            let wake = async {
+                match next {
+                    Some(time) => Timer::at(time).await,
+                    None => pending().await,
+                }
+            };
+            match select3(wake, LED.wait(), PATTERN.receive()).await {
+                Either3::First(_) => {}
+                Either3::Second(state) => {
+                    for request in state.requests(level) {
+                        engine.apply(request, Instant::now());
+                    }
+                }
+                Either3::Third(request) => {
+                    engine.apply(request, Instant::now());
+                    requested = true;
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn after(state: State, base: State) -> bool {
+            let mut engine = Engine::new(false);
+            let now = Instant::from_millis(0);
+            for request in base.requests(false).chain(state.requests(true)) {
+                engine.apply(request, now);
+            }
+            engine.poll(now + embassy_time::Duration::from_millis(500)).0
+        }
+
+        /// Paused and reset look different from recording
+        #[test]
+        fn pause_and_reset_end_dark() {
+            assert!(!after(State::Blink, State::Steady));
+            assert!(!after(State::Vibrate, State::Steady));
+            assert!(after(State::Interrupt, State::Steady));
+            assert!(after(State::Steady, State::Off));
+        }
+    }
+}
+
+#[cfg(feature = "rgb")]
+pub mod rgb {
+    //! ## WS2812 status pixels
+    //!
+    //! Drives a strip of WS2812 (NeoPixel) LEDs by PIO and DMA,
+    //! e.g. the two on GP28 of the Maker Pi Pico. Pixel 0 shows the
+    //! device state (`STATE`), every further pixel the health of one
+    //! sensor bank, reported by sending `(sensory, Health)` to `HEALTH`.
+    //! Sensory 0 goes to pixel 1 and so on. Pixel 0 runs a `pattern`
+    //! engine, with the state as base and overlays from `PATTERN`.
+    //!
+    //! The program is loaded wherever there is room in the PIO block,
+    //! so it can share PIO0 with `ysns::yenc` (load the encoder first).
+    use super::*;
+    use super::pattern::{Engine, Pattern, Repeat, Request};
+    use core::future::pending;
+    use embassy_futures::select::{select4, Either4};
+    use embassy_rp::clocks::clk_sys_freq;
+    use embassy_rp::dma::{AnyChannel, Channel as DmaChannel};
+    use embassy_rp::peripherals::PIO0;
+    use embassy_rp::pio::{Common, Config, FifoJoin, Instance, PioPin, ShiftConfig, ShiftDirection, StateMachine};
+    use embassy_rp::relocate::RelocatedProgram;
+    use embassy_rp::{into_ref, Peripheral, PeripheralRef};
+    use embassy_time::{Instant, Timer};
+    use fixed::types::U24F8;
+    pub use smart_leds::RGB8;
+
+    pub const MAX_PIXELS: usize = 9;
+    /// low time after the last bit that ends a frame
+    const LATCH_US: u64 = 60;
+    /// joined TX FIFO and the shift register
+    const FIFO_WORDS: usize = 9;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum State {
+        Idle,
+        Recording,
+        Paused,
+        Error,
+        LowStorage,
+        SensorMissing,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Health {
+        /// nothing reported yet
+        Unknown,
+        Ok,
+        /// slower than configured
+        Lagging,
+        /// not found or not responding
+        Missing,
+    }
+
+    pub static STATE: Signal<RawMutex, State> = Signal::new();
+    pub static HEALTH: Channel<RawMutex, (u8, Health), 8> = Channel::new();
+    pub static PATTERN: Channel<RawMutex, Request<RGB8>, 4> = Channel::new();
+
+    impl State {
+        /// Errors blink, everything else is steady
+        pub fn pattern(self) -> Pattern<RGB8> {
+            match self {
+                State::Error => Pattern::blink(self.into(), RGB8::default(), 200, 200, Repeat::Forever),
+                _ => Pattern::steady(self.into()),
+            }
+        }
+    }
+
+    /// Full brightness is blinding, so colours are kept dim
+    impl From<State> for RGB8 {
+        fn from(state: State) -> RGB8 {
+            match state {
+                State::Idle => RGB8::new(0, 0, 16),
+                State::Recording => RGB8::new(0, 24, 0),
+                State::Paused => RGB8::new(16, 12, 0),
+                State::Error => RGB8::new(32, 0, 0),
+                State::LowStorage => RGB8::new(24, 0, 16),
+                State::SensorMissing => RGB8::new(24, 6, 0),
+            }
+        }
+    }
+
+    impl From<Health> for RGB8 {
+        fn from(health: Health) -> RGB8 {
+            match health {
+                Health::Unknown => RGB8::new(0, 0, 0),
+                Health::Ok => RGB8::new(0, 16, 0),
+                Health::Lagging => RGB8::new(16, 12, 0),
+                Health::Missing => RGB8::new(32, 0, 0),
+            }
+        }
+    }
+
+    pub struct Ws2812<'d, P: Instance, const S: usize> {
+        dma: PeripheralRef<'d, AnyChannel>,
+        sm: StateMachine<'d, P, S>,
+    }
+
+    impl<'d, P: Instance, const S: usize> Ws2812<'d, P, S> {
+        pub fn new(
+            pio: &mut Common<'d, P>,
+            mut sm: StateMachine<'d, P, S>,
+            dma: impl Peripheral<P = impl DmaChannel> + 'd,
+            pin: impl PioPin,
+        ) -> Self {
+            use ::pio::{Assembler, JmpCondition, OutDestination, SetDestination, SideSet};
+            into_ref!(dma);
+            let mut a: Assembler<32> = Assembler::new_with_side_set(SideSet::new(false, 1, false));
+            // cycles of start, data and stop part of a bit
+            const T1: u8 = 2;
+            const T2: u8 = 5;
+            const T3: u8 = 3;
+            const CYCLES_PER_BIT: u32 = (T1 + T2 + T3) as u32;
+
+            let mut wrap_target = a.label();
+            let mut wrap_source = a.label();
+            let mut do_zero = a.label();
+            a.set_with_side_set(SetDestination::PINDIRS, 1, 0);
+            a.bind(&mut wrap_target);
+            a.out_with_delay_and_side_set(OutDestination::X, 1, T3 - 1, 0);
+            a.jmp_with_delay_and_side_set(JmpCondition::XIsZero, &mut do_zero, T1 - 1, 1);
+            a.jmp_with_delay_and_side_set(JmpCondition::Always, &mut wrap_target, T2 - 1, 1);
+            a.bind(&mut do_zero);
+            a.nop_with_delay_and_side_set(T2 - 1, 0);
+            a.bind(&mut wrap_source);
+            let program = a.assemble_with_wrap(wrap_source, wrap_target);
+
+            let mut cfg = Config::default();
+            let out_pin = pio.make_pio_pin(pin);
+            cfg.set_out_pins(&[&out_pin]);
+            cfg.set_set_pins(&[&out_pin]);
+            let relocated = RelocatedProgram::new(&program);
+            cfg.use_program(&pio.load_program(&relocated), &[&out_pin]);
+            // in kHz to avoid overflows, 800 kHz bit rate
+            let clock = U24F8::from_num(clk_sys_freq() / 1000);
+            cfg.clock_divider = clock / (U24F8::from_num(800) * CYCLES_PER_BIT);
+            cfg.fifo_join = FifoJoin::TxOnly;
+            cfg.shift_out = ShiftConfig {
+                auto_fill: true,
+                threshold: 24,
+                direction: ShiftDirection::Left,
+            };
+            sm.set_config(&cfg);
+            sm.set_enable(true);
+            Ws2812 { dma: dma.map_into(), sm }
+        }
+
+        /// Sends the colours, GRB on the wire. Returns after the
+        /// line was held low for the latch, so writes cannot run
+        /// into each other.
+        pub async fn write(&mut self, colors: &[RGB8]) {
+            let mut words: Vec<u32, MAX_PIXELS> = Vec::new();
+            for c in colors.iter().take(MAX_PIXELS) {
+                let _ = words.push((u32::from(c.g) << 24) | (u32::from(c.r) << 16) | (u32::from(c.b) << 8));
+            }
+            self.sm.tx().dma_push(self.dma.reborrow(), &words).await;
+            // the DMA is done once the FIFO holds the last words,
+            // which still go out at 30 µs each before the latch
+            Timer::after_micros(30 * words.len().min(FIFO_WORDS) as u64 + LATCH_US).await;
+        }
+    }
+
+    /// Runs a strip of `pixels` status LEDs
+    #[embassy_executor::task]
+    pub async fn task(mut strip: Ws2812<'static, PIO0, 1>, pixels: usize) {
+        let pixels = pixels.clamp(1, MAX_PIXELS);
+        let mut colors = [RGB8::default(); MAX_PIXELS];
+        let mut engine: Engine<RGB8> = Engine::new(RGB8::default());
+        engine.apply(Request::Base(State::Idle.pattern()), Instant::now());
+        loop {
+            let (color, next) = engine.poll(Instant::now());
+            colors[0] = color;
+            strip.write(&colors[..pixels]).await;
+            let wake = async {
+                match next {
+                    Some(time) => Timer::at(time).await,
+                    None => pending().await,
+                }
+            };
+            match select4(wake, STATE.wait(), HEALTH.receive(), PATTERN.receive()).await {
+                Either4::First(_) => {}
+                Either4::Second(state) => engine.apply(Request::Base(state.pattern()), Instant::now()),
+                Either4::Third((sensory, health)) => {
+                    if let Some(pixel) = colors[1..pixels].get_mut(sensory as usize) {
+                        *pixel = health.into();
+                    }
+                }
+                Either4::Fourth(request) => engine.apply(request, Instant::now()),
+            }
+        }
+    }
+}
+
+pub mod stim {
+    //! ## Stimulus runner
+    //!
+    //! Runs a `ypro::protocol` on the device, so that stimulus timing
+    //! does not depend on USB. Steps are scheduled against the start
+    //! of the run, so a late step does not shift the ones after it.
+    //! Output pins are switched directly. The LED is an overlay
+    //! above the state patterns (`led::STIMULUS`), dark between
+    //! stimuli and cleared after the run, so the state shows again.
+    //! `run` switches pins through a function, `task` GPIO pins.
+    //!
+    //! Trials are added from the host, e.g. `pr add pin 0 200000 800000`
+    //! (µs on, µs off), then `pr seed 7` and `pr run`. `pr store` keeps
+    //! the protocol in flash, where the task finds it after a reset.
+    //!
+    //! Every step is sent as `[trial, stimulus, on, lag]`, where
+    //! stimulus is 0 for the LED and pin + 1 otherwise, and lag
+    //! is the µs between schedule and switching. For the LED,
+    //! switching is when `led::run` acknowledges it.
+    use super::*;
+    use crate::ymem::{self, Slot};
+    use crate::ypro::protocol::{Protocol, Stimulus, Trial, MAX_BYTES};
+    use super::pattern::{Pattern, Request};
+    use crate::ytfk::bsu;
+    use core::str::SplitWhitespace;
+    use embassy_futures::select::{select, Either};
+    #[cfg(feature = "rp")]
+    use embassy_rp::gpio::{AnyPin, Level, Output};
+    use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+    pub const MAX_PINS: usize = 4;
+    /// time between `run` and the first step
+    const LEAD: Duration = Duration::from_millis(10);
+    /// longest wait for the LED, which may have no task running
+    const LED_WAIT: Duration = Duration::from_millis(5);
+
+    pub static RECORD: AtomicBool = AtomicBool::new(true);
+    pub static CONTROL: Channel<RawMutex, Command, 4> = Channel::new();
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Command {
+        Add(Trial),
+        Seed(u32),
+        Clear,
+        Run,
+        Stop,
+        Store,
+        Load,
+    }
+
+    impl Command {
+        /// Parses the words following `pr` on a command line
+        pub fn parse(mut words: SplitWhitespace) -> Option<Command> {
+            let command = match words.next()? {
+                "add" => {
+                    let stimulus = match words.next()? {
+                        "led" => Stimulus::Led,
+                        "pin" => Stimulus::Pin(words.next()?.parse().ok()?),
+                        _ => return None,
+                    };
+                    Command::Add(Trial {
+                        stimulus,
+                        duration: words.next()?.parse().ok()?,
+                        isi: words.next()?.parse().ok()?,
+                    })
+                }
+                "seed" => Command::Seed(words.next()?.parse().ok()?),
+                "clear" => Command::Clear,
+                "run" => Command::Run,
+                "stop" => Command::Stop,
+                "store" => Command::Store,
+                "load" => Command::Load,
+                _ => return None,
+            };
+            Some(command)
+        }
+    }
+
+    async fn load() -> Option<Protocol> {
+        let mut buf = [0u8; MAX_BYTES];
+        let len = ymem::load(Slot::Protocol, &mut buf).await?;
+        Protocol::from_bytes(&buf[..len])
+    }
+
+    /// Switches a stimulus, returns when it switched
+    async fn switch(pin: &mut impl FnMut(u8, bool), stimulus: Stimulus, on: bool) -> Instant {
+        match stimulus {
+            Stimulus::Led => led_request(Request::Overlay(Pattern::steady(on), led::STIMULUS)).await,
+            Stimulus::Pin(n) => {
+                pin(n, on);
+                Instant::now()
+            }
+        }
+    }
+
+    async fn led_request(request: Request<bool>) -> Instant {
+        led::SHOWN.reset();
+        if led::PATTERN.try_send(request).is_ok() {
+            if let Ok(shown) = with_timeout(LED_WAIT, led::SHOWN.wait()).await {
+                return shown;
+            }
+        }
+        Instant::now()
+    }
+
+    /// Runs the protocol until it ends or is stopped
+    async fn play(protocol: &Protocol, pin: &mut impl FnMut(u8, bool), sensory: u8) {
+        let start = Instant::now() + LEAD;
+        for step in protocol.schedule() {
+            let due = start + Duration::from_micros(step.at);
+            // other commands are turned down, the step stays due
+            let stopped = loop {
+                match select(Timer::at(due), CONTROL.receive()).await {
+                    Either::First(_) => break false,
+                    Either::Second(Command::Stop) => break true,
+                    Either::Second(_) => log::warn!("Protocol: running, only stop is accepted"),
+                }
+            };
+            if stopped {
+                break;
+            }
+            let now = switch(pin, step.stimulus, step.on).await;
+            if RECORD.load(ORD) {
+                let sample = Sample {
+                    sensory,
+                    time: now,
+                    read: [
+                        step.trial as f32,
+                        step.stimulus.code() as f32,
+                        step.on as u8 as f32,
+                        (now - due).as_micros() as f32,
+                    ],
+                };
+                bsu::send(sample.into()).await;
+            }
+        }
+        for n in 0..MAX_PINS as u8 {
+            pin(n, false);
+        }
+        if protocol.trials.iter().any(|trial| trial.stimulus == Stimulus::Led) {
+            led_request(Request::Clear).await;
+        }
+    }
+
+    #[cfg(feature = "rp")]
+    #[embassy_executor::task]
+    pub async fn task(pins: Vec<AnyPin, MAX_PINS>, sensory: u8) {
+        let mut outputs: Vec<Output<'static, AnyPin>, MAX_PINS> =
+            pins.into_iter().map(|pin| Output::new(pin, Level::Low)).collect();
+        run(
+            |n, on| {
+                if let Some(pin) = outputs.get_mut(n as usize) {
+                    pin.set_level(if on { Level::High } else { Level::Low });
+                }
+            },
+            sensory,
+        )
+        .await
+    }
+
+    /// Takes the commands, calling `pin` with the number and level
+    /// of the output pin on every step; numbers without a pin are
+    /// for `pin` to ignore
+    pub async fn run(mut pin: impl FnMut(u8, bool), sensory: u8) -> ! {
+        let mut protocol = load().await.unwrap_or_default();
+        loop {
+            match CONTROL.receive().await {
+                Command::Add(trial) => {
+                    if protocol.trials.push(trial).is_err() {
+                        log::warn!("Protocol: too many trials");
+                    }
+                }
+                Command::Seed(seed) => protocol.seed = seed,
+                Command::Clear => protocol = Protocol::default(),
+                Command::Run => play(&protocol, &mut pin, sensory).await,
+                Command::Stop => {}
+                Command::Store => {
+                    if ymem::store(Slot::Protocol, &protocol.to_bytes()).await.is_err() {
+                        log::warn!("Protocol: storing failed");
+                    }
+                }
+                Command::Load => match load().await {
+                    Some(stored) => protocol = stored,
+                    None => log::warn!("Protocol: nothing stored"),
+                },
+            }
+        }
+    }
+}
+
+pub mod buzzer {
+    //! ## Piezo buzzer
+    //!
+    //! Square wave tones on one PWM slice (GP18 on the Maker Pi Pico
+    //! is slice 1, channel A). Three ways to make a sound:
+    //!
+    //! + `FEEDBACK`: short patterns for state changes, like `led::LED`
+    //! + `bz tone <hz> <ms>` from the host, not logged
+    //! + `bz stim <hz> <ms>` from the host, an auditory stimulus with
+    //!   onset and offset sent as `[hz, ms, on]`
+    //!
+    //! Onsets are switched right after the command arrives and the
+    //! offset is timed from the onset, so the duration is exact
+    //! to the timer tick, while the latency of the command is not.
+    //! `run` plays through a function taking the tone in Hz, `task`
+    //! on the PWM slice.
+    use super::*;
+    use crate::ytfk::bsu;
+    use core::str::SplitWhitespace;
+    use embassy_futures::select::{select, Either};
+    #[cfg(feature = "rp")]
+    use embassy_rp::clocks::clk_sys_freq;
+    #[cfg(feature = "rp")]
+    use embassy_rp::peripherals::{PIN_18, PWM_CH1};
+    #[cfg(feature = "rp")]
+    use embassy_rp::pwm::{Config, Pwm};
+    use embassy_time::{Duration, Instant, Timer};
+    #[cfg(feature = "rp")]
+    use fixed::FixedU16;
+
+    /// lowest tone the 8 bit divider reaches
+    pub const MIN_HZ: u32 = 20;
+    pub const MAX_HZ: u32 = 20_000;
+
+    pub static RECORD: AtomicBool = AtomicBool::new(true);
+    pub static CONTROL: Channel<RawMutex, Command, 4> = Channel::new();
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Pattern {
+        RecordStart,
+        RecordStop,
+        Ok,
+        Error,
+    }
+    pub static FEEDBACK: Signal<RawMutex, Pattern> = Signal::new();
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Command {
+        Tone(u32, u32),
+        Stimulus(u32, u32),
+        Stop,
+    }
+
+    impl Command {
+        /// Parses the words following `bz` on a command line
+        pub fn parse(mut words: SplitWhitespace) -> Option<Command> {
+            let command = match words.next()? {
+                "tone" => Command::Tone(words.next()?.parse().ok()?, words.next()?.parse().ok()?),
+                "stim" => Command::Stimulus(words.next()?.parse().ok()?, words.next()?.parse().ok()?),
+                "stop" => Command::Stop,
+                _ => return None,
             };
+            Some(command)
         }
+    }
+
+    impl Pattern {
+        /// Tones as (hz, ms), 0 Hz is a pause
+        fn tones(self) -> &'static [(u32, u32)] {
+            match self {
+                Pattern::RecordStart => &[(1000, 60), (0, 40), (2000, 60)],
+                Pattern::RecordStop => &[(2000, 60), (0, 40), (1000, 60)],
+                Pattern::Ok => &[(2000, 40)],
+                Pattern::Error => &[(400, 150), (0, 80), (400, 150), (0, 80), (400, 150)],
+            }
+        }
+    }
+
+    #[cfg(feature = "rp")]
+    struct Buzzer {
+        pwm: Pwm<'static, PWM_CH1>,
+        config: Config,
+    }
+
+    #[cfg(feature = "rp")]
+    impl Buzzer {
+        /// Sets the slice to `hz` at 50% duty, 0 is silent
+        fn tone(&mut self, hz: u32) {
+            if hz == 0 {
+                self.config.compare_a = 0;
+            } else {
+                let hz = hz.clamp(MIN_HZ, MAX_HZ);
+                let clock = clk_sys_freq();
+                // smallest divider that keeps top within 16 bit
+                let divider = (clock / (hz * 65_536) + 1).min(255);
+                let top = clock / (divider * hz) - 1;
+                self.config.divider = FixedU16::from_num(divider);
+                self.config.top = top as u16;
+                self.config.compare_a = (top / 2) as u16;
+            }
+            self.pwm.set_config(&self.config);
+        }
+    }
+
+    async fn play(tone: &mut impl FnMut(u32), tones: &[(u32, u32)]) {
+        for &(hz, ms) in tones {
+            tone(hz);
+            Timer::after(Duration::from_millis(ms as u64)).await;
+        }
+        tone(0);
+    }
+
+    fn mark(sensory: u8, time: Instant, hz: u32, ms: u32, on: bool) -> Ytf {
+        Sample {
+            sensory,
+            time,
+            read: [hz as f32, ms as f32, on as u8 as f32],
+        }
+        .into()
+    }
+
+    #[cfg(feature = "rp")]
+    #[embassy_executor::task]
+    pub async fn task(slice: PWM_CH1, pin: PIN_18, sensory: u8) {
+        let mut config = Config::default();
+        config.compare_a = 0;
+        let mut buzzer = Buzzer {
+            pwm: Pwm::new_output_a(slice, pin, config.clone()),
+            config,
+        };
+        run(|hz| buzzer.tone(hz), sensory).await
+    }
+
+    /// Takes feedback and commands, calling `tone` with the Hz
+    /// to sound, 0 for silence
+    pub async fn run(mut tone: impl FnMut(u32), sensory: u8) -> ! {
+        loop {
+            match select(FEEDBACK.wait(), CONTROL.receive()).await {
+                Either::First(pattern) => play(&mut tone, pattern.tones()).await,
+                Either::Second(Command::Tone(hz, ms)) => play(&mut tone, &[(hz, ms)]).await,
+                Either::Second(Command::Stimulus(hz, ms)) => {
+                    tone(hz);
+                    let onset = Instant::now();
+                    let offset = onset + Duration::from_millis(ms as u64);
+                    if RECORD.load(ORD) {
+                        bsu::send(mark(sensory, onset, hz, ms, true)).await;
+                    }
+                    // only a stop command cuts a stimulus short
+                    loop {
+                        match select(Timer::at(offset), CONTROL.receive()).await {
+                            Either::Second(Command::Stop) | Either::First(_) => break,
+                            Either::Second(_) => continue,
+                        }
+                    }
+                    tone(0);
+                    if RECORD.load(ORD) {
+                        bsu::send(mark(sensory, Instant::now(), hz, ms, false)).await;
+                    }
+                }
+                Either::Second(Command::Stop) => tone(0),
+            }
+        }
+    }
 }
 
 pub mod disp {
+    //! ## Text display
+    //!
+    //! `TEXT` is always there, so anyone can write to it. The SSD1306
+    //! driver behind it (`task`) needs the `display` feature.
     use super::*;
-    use hal::i2c;
-    use hal::peripherals::I2C1 as I2C;
-    use i2c::Async as Mode;
+    #[cfg(feature = "display")]
+    use hal::{i2c, i2c::Async as Mode, peripherals::I2C1 as I2C};
 
     //pub use heapless::String;
     // use itoa;
@@ -62,6 +865,7 @@ pub mod disp {
         text::{Baseline, Text},
         mono_font::{ascii::FONT_6X10, MonoTextStyleBuilder},
     };*/
+    #[cfg(feature = "display")]
     use ssd1306::{prelude::*, I2CDisplayInterface, Ssd1306};
     // inter-thread communication
     
@@ -72,8 +876,10 @@ pub mod disp {
                 = Signal::new();
 
     // Text display
+    #[cfg(feature = "display")]
     use core::fmt::Write;
 
+    #[cfg(feature = "display")]
     #[embassy_executor::task]
     pub async fn task(i2c: i2c::I2c<'static, I2C, Mode>) {
         let interface 
@@ -108,6 +914,369 @@ pub mod disp {
     }
 }
 
+#[cfg(feature = "display")]
+pub mod graph {
+    //! ## Graphics display
+    //!
+    //! The SSD1306 in buffered graphics mode, as an alternative
+    //! to `disp::task` (both need the display). Shows one page at a
+    //! time, selected by `PAGE`:
+    //!
+    //! + `Text`: the four lines from `disp::TEXT`
+    //! + `Plot`: rolling sparklines of up to three channels from
+    //!   `bsu::TAP`, each auto-scaled, with a level meter on the right
+    //!   and a recording indicator in the header.
+    //! + `Dash`: from `telemetry`, the achieved and target rate, errors,
+    //!   drops and ms per second spent waiting for `SINK` of up to four
+    //!   sensories, recording time (or uptime), USB connection and free flash.
+    //!
+    //! Frames are drawn every `FRAME` and nowhere in between, so the
+    //! display never takes more of the I2C bus than that. A sparkline
+    //! point is the mean of all samples that came in during one frame.
+    //!
+    //! Frames are drawn into a `Frame` in RAM and sent one 128 byte
+    //! page at a time by async I2C, so the executor keeps running
+    //! other tasks while the 1 KB goes out.
+    use super::*;
+    use super::disp::{FourLines, TEXT};
+    use crate::ytfk::bsu::TAP;
+    use crate::ytfk::telemetry::{self, Snapshot, MAX_SENSORIES};
+    use core::fmt::Write;
+    use embassy_futures::select::{select4, Either4};
+    use embassy_time::{Duration, Instant, Ticker};
+    use embedded_graphics::{
+        mono_font::{ascii::FONT_6X10, MonoTextStyle},
+        pixelcolor::BinaryColor,
+        prelude::*,
+        primitives::{Circle, Line, PrimitiveStyle, Rectangle},
+        text::{Baseline, Text},
+    };
+    use hal::i2c;
+    use hal::peripherals::I2C1 as I2C;
+    use heapless::HistoryBuffer;
+
+    pub const FRAME: Duration = Duration::from_millis(200);
+    pub const MAX_TRACES: usize = 3;
+    /// pixels of sparkline, the rest of the 128 is the meter
+    const WIDTH: usize = 112;
+    const HEADER: i32 = 10;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Page {
+        Text,
+        Plot,
+        Dash,
+    }
+
+    pub static PAGE: Signal<RawMutex, Page> = Signal::new();
+    /// shown in the header, set by the control task
+    pub static RECORDING: AtomicBool = AtomicBool::new(false);
+
+    const ADDRESS: u16 = 0x3C;
+    const COLUMNS: usize = 128;
+    const PAGES: usize = 8;
+    /// 128x64, charge pump on, page addressing, flipped to the
+    /// usual mounting; a 0x00 control byte, then the commands
+    const INIT: [u8; 26] = [
+        0x00, 0xAE, 0xD5, 0x80, 0xA8, 0x3F, 0xD3, 0x00, 0x40, 0x8D, 0x14, 0x20, 0x02, 0xA1, 0xC8, 0xDA, 0x12,
+        0x81, 0xCF, 0xD9, 0xF1, 0xDB, 0x40, 0xA4, 0xA6, 0xAF,
+    ];
+
+    /// The picture in SSD1306 memory layout: 8 pages of 128 columns,
+    /// each byte 8 pixels down with the lowest bit on top
+    pub struct Frame([[u8; COLUMNS]; PAGES]);
+
+    impl OriginDimensions for Frame {
+        fn size(&self) -> Size {
+            Size::new(COLUMNS as u32, (PAGES * 8) as u32)
+        }
+    }
+
+    impl DrawTarget for Frame {
+        type Color = BinaryColor;
+        type Error = core::convert::Infallible;
+
+        fn draw_iter<I: IntoIterator<Item = Pixel<BinaryColor>>>(&mut self, pixels: I) -> Result<(), Self::Error> {
+            for Pixel(point, color) in pixels {
+                let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) else {
+                    continue;
+                };
+                if let Some(byte) = self.0.get_mut(y / 8).and_then(|page| page.get_mut(x)) {
+                    if color.is_on() {
+                        *byte |= 1 << (y % 8);
+                    } else {
+                        *byte &= !(1 << (y % 8));
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// The SSD1306 on async I2C
+    pub struct Display {
+        i2c: i2c::I2c<'static, I2C, i2c::Async>,
+    }
+
+    impl Display {
+        async fn init(&mut self) -> Result<(), i2c::Error> {
+            self.i2c.write_async(ADDRESS, INIT).await
+        }
+
+        /// Sends the frame page by page, each an I2C transfer of its own
+        async fn flush(&mut self, frame: &Frame) -> Result<(), i2c::Error> {
+            for (page, data) in frame.0.iter().enumerate() {
+                // page start, column 0
+                self.i2c.write_async(ADDRESS, [0x00, 0xB0 | page as u8, 0x00, 0x10]).await?;
+                self.i2c.write_async(ADDRESS, core::iter::once(0x40).chain(data.iter().copied())).await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// A channel of a sensory to plot
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Trace {
+        pub sensory: u8,
+        pub channel: u8,
+    }
+
+    struct Plot {
+        trace: Trace,
+        history: HistoryBuffer<f32, WIDTH>,
+        sum: f32,
+        count: u32,
+    }
+
+    impl Plot {
+        fn new(trace: Trace) -> Self {
+            Plot { trace, history: HistoryBuffer::new(), sum: 0.0, count: 0 }
+        }
+
+        fn add(&mut self, sample: &Ytf) {
+            if sample.sensory != self.trace.sensory {
+                return;
+            }
+            if let Some(Some(value)) = sample.read.get(self.trace.channel as usize) {
+                self.sum += *value as f32;
+                self.count += 1;
+            }
+        }
+
+        /// Ends a frame, adding a point if there were samples
+        fn frame(&mut self) {
+            if self.count > 0 {
+                self.history.write(self.sum / self.count as f32);
+                self.sum = 0.0;
+                self.count = 0;
+            }
+        }
+
+        fn range(&self) -> (f32, f32) {
+            let (min, max) = self
+                .history
+                .oldest_ordered()
+                .fold((f32::MAX, f32::MIN), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+            // a flat line sits in the middle
+            if max - min < f32::EPSILON {
+                (min - 1.0, max + 1.0)
+            } else {
+                (min, max)
+            }
+        }
+
+        fn draw<D: DrawTarget<Color = BinaryColor>>(&self, target: &mut D, top: i32, height: i32) -> Result<(), D::Error> {
+            let line = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+            let fill = PrimitiveStyle::with_fill(BinaryColor::On);
+            let mut label: String<8> = String::new();
+            let _ = write!(label, "{}.{}", self.trace.sensory, self.trace.channel);
+            Text::with_baseline(label.as_str(), Point::new(0, top), MonoTextStyle::new(&FONT_6X10, BinaryColor::On), Baseline::Top)
+                .draw(target)?;
+            if self.history.len() == 0 {
+                return Ok(());
+            }
+            let (min, max) = self.range();
+            let y = |v: f32| top + height - 1 - ((v - min) / (max - min) * (height - 1) as f32) as i32;
+            // right aligned, so the newest point is next to the meter
+            let start = (WIDTH - self.history.len()) as i32;
+            let mut last: Option<Point> = None;
+            for (x, &v) in self.history.oldest_ordered().enumerate() {
+                let point = Point::new(start + x as i32, y(v));
+                if let Some(from) = last {
+                    Line::new(from, point).into_styled(line).draw(target)?;
+                }
+                last = Some(point);
+            }
+            if let Some(&v) = self.history.recent() {
+                let level = (top + height - y(v)).max(1);
+                Rectangle::new(Point::new(WIDTH as i32 + 4, top), Size::new(12, height as u32))
+                    .into_styled(line)
+                    .draw(target)?;
+                Rectangle::new(Point::new(WIDTH as i32 + 4, top + height - level), Size::new(12, level as u32))
+                    .into_styled(fill)
+                    .draw(target)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn draw_text(display: &mut Frame, lines: &FourLines) {
+        let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        for (row, line) in lines.iter().enumerate() {
+            if let Some(line) = line {
+                let _ = Text::with_baseline(line.as_str(), Point::new(0, row as i32 * 16), style, Baseline::Top).draw(display);
+            }
+        }
+    }
+
+    /// Rates and waits over the last second and the times to show
+    struct Dash {
+        last: Snapshot,
+        since: Instant,
+        rates: [f32; MAX_SENSORIES],
+        /// ms per second
+        waits: [u32; MAX_SENSORIES],
+        boot: Instant,
+        recording: Option<Instant>,
+        free: Option<usize>,
+        checked: Instant,
+    }
+
+    impl Dash {
+        fn new() -> Self {
+            Dash {
+                last: telemetry::snapshot(),
+                since: Instant::now(),
+                rates: [0.0; MAX_SENSORIES],
+                waits: [0; MAX_SENSORIES],
+                boot: Instant::now(),
+                recording: None,
+                free: None,
+                checked: Instant::now(),
+            }
+        }
+
+        /// Called every frame, true when free flash should be updated
+        fn frame(&mut self) -> bool {
+            let now = Instant::now();
+            match (RECORDING.load(ORD), self.recording) {
+                (true, None) => self.recording = Some(now),
+                (false, Some(_)) => self.recording = None,
+                _ => {}
+            }
+            let elapsed = now - self.since;
+            if elapsed < Duration::from_secs(1) {
+                return false;
+            }
+            let snapshot = telemetry::snapshot();
+            for s in 0..MAX_SENSORIES {
+                let (c, last) = (&snapshot.sensories[s], &self.last.sensories[s]);
+                self.rates[s] = c.sent.wrapping_sub(last.sent) as f32 * 1_000_000.0 / elapsed.as_micros() as f32;
+                self.waits[s] = (c.waited.wrapping_sub(last.waited) as u64 * 1000 / elapsed.as_micros()) as u32;
+            }
+            self.last = snapshot;
+            self.since = now;
+            if now - self.checked < Duration::from_secs(30) {
+                return false;
+            }
+            self.checked = now;
+            true
+        }
+
+        fn draw(&self, display: &mut Frame) {
+            let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+            let mut row = 0;
+            let mut text = |line: &str| {
+                let _ = Text::with_baseline(line, Point::new(0, row * 10), style, Baseline::Top).draw(display);
+                row += 1;
+            };
+            let mut line: String<21> = String::new();
+            let (label, from) = match self.recording {
+                Some(start) => ("REC", start),
+                None => ("up ", self.boot),
+            };
+            let secs = (Instant::now() - from).as_secs();
+            let usb = if self.last.usb { "USB" } else { "---" };
+            let _ = write!(line, "{} {:02}:{:02}:{:02}  {}", label, secs / 3600, secs / 60 % 60, secs % 60, usb);
+            text(line.as_str());
+            let active = self
+                .last
+                .sensories
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.target > 0.0 || c.sent > 0)
+                .take(4);
+            for (s, c) in active {
+                line.clear();
+                let (rate, wait) = (self.rates[s], self.waits[s]);
+                let _ = write!(line, "{} {:>4.0}/{:<4.0}e{} d{} w{}", s, rate, c.target, c.errors, c.dropped, wait);
+                text(line.as_str());
+            }
+            line.clear();
+            let _ = match self.free {
+                Some(bytes) => write!(line, "flash {}K free", bytes / 1024),
+                None => write!(line, "flash ?"),
+            };
+            let _ = Text::with_baseline(line.as_str(), Point::new(0, 54), style, Baseline::Top).draw(display);
+        }
+    }
+
+    fn draw_plots(display: &mut Frame, plots: &[Plot]) {
+        let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        if RECORDING.load(ORD) {
+            let _ = Circle::new(Point::new(0, 1), 8)
+                .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+                .draw(display);
+            let _ = Text::with_baseline("REC", Point::new(12, 0), style, Baseline::Top).draw(display);
+        } else {
+            let _ = Text::with_baseline("---", Point::new(12, 0), style, Baseline::Top).draw(display);
+        }
+        if plots.is_empty() {
+            return;
+        }
+        let height = (64 - HEADER) / plots.len() as i32;
+        for (row, plot) in plots.iter().enumerate() {
+            let _ = plot.draw(display, HEADER + row as i32 * height, height - 2);
+        }
+    }
+
+    #[embassy_executor::task]
+    pub async fn task(i2c: i2c::I2c<'static, I2C, i2c::Async>, traces: Vec<Trace, MAX_TRACES>) {
+        let mut display = Display { i2c };
+        let mut frame = Frame([[0; COLUMNS]; PAGES]);
+        if display.init().await.is_err() {
+            log::warn!("Display not found");
+            return;
+        }
+        let mut plots: Vec<Plot, MAX_TRACES> = traces.into_iter().map(Plot::new).collect();
+        let mut page = Page::Plot;
+        let mut lines: FourLines = [None, None, None, None];
+        let mut dash = Dash::new();
+        dash.free = ymem::free().await;
+        let mut ticker = Ticker::every(FRAME);
+        loop {
+            match select4(ticker.next(), TAP.receive(), TEXT.wait(), PAGE.wait()).await {
+                Either4::First(_) => {
+                    plots.iter_mut().for_each(Plot::frame);
+                    if dash.frame() && page == Page::Dash {
+                        dash.free = ymem::free().await;
+                    }
+                    let _ = frame.clear(BinaryColor::Off);
+                    match page {
+                        Page::Text => draw_text(&mut frame, &lines),
+                        Page::Plot => draw_plots(&mut frame, &plots),
+                        Page::Dash => dash.draw(&mut frame),
+                    }
+                    let _ = display.flush(&frame).await;
+                }
+                Either4::Second(sample) => plots.iter_mut().for_each(|plot| plot.add(&sample)),
+                Either4::Third(text) => lines = text,
+                Either4::Fourth(next) => page = next,
+            }
+        }
+    }
+}
+
 
 
 /*pub mod disp {